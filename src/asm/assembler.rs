@@ -0,0 +1,784 @@
+use std::collections::HashMap;
+
+use crate::{
+    cart::{consts::ROM_BANK_SIZE, header::nintendo_logo},
+    cpu::disasm::BankAddr,
+    mem::Addr,
+};
+
+use super::{
+    encode::{encode, Encoded, Patch},
+    expr::{full_symbol_name, is_symbol_char, parse_expr, Expr},
+    operand::parse_operand,
+};
+
+/// An assembled Game Boy program.
+pub struct Program {
+    /// The ROM image, with a valid header.
+    pub rom: Vec<u8>,
+    labels: HashMap<String, BankAddr>,
+}
+
+impl Program {
+    /// Returns the bank and address of the label with the given full name
+    /// (e.g. `Main` or `Main.loop`). Labels in RAM are in bank 0.
+    pub fn label_addr(&self, name: &str) -> Option<BankAddr> {
+        self.labels.get(name).copied()
+    }
+}
+
+/// Assembles RGBDS style source code into a ROM image.
+///
+/// Supported syntax:
+/// - Every SM83 instruction, plus `ldi`/`ldd`.
+/// - `SECTION "name", ROM0[addr]`, `ROMX[addr], BANK[n]`, and RAM sections
+///   (`WRAM0`, `WRAMX`, `HRAM`, `VRAM`, `SRAM`, `OAM`) which only hold labels
+///   and `ds`. Sections without an address continue where the last section
+///   placed in the same bank ended.
+/// - Global (`Main:`), exported (`Main::`) and local (`.loop:`) labels.
+/// - `db`, `dw`, `ds`, and `EQU` constants.
+/// - Expressions with `$`, `%` and `0x` numbers, `@`, `HIGH()`, `LOW()`,
+///   and C style operators.
+///
+/// After assembling, the header is fixed up like `rgbfix -v` would: the logo,
+/// ROM size and checksums are written. The cartridge type is set to MBC1 if
+/// more than 2 banks are used, unless the program writes it itself.
+pub fn assemble(src: &str) -> Result<Program, String> {
+    let mut asm = Assembler::default();
+
+    for (idx, line) in src.lines().enumerate() {
+        asm.line_num = idx + 1;
+        asm.parse_line(line).map_err(|msg| asm.error(msg))?;
+    }
+
+    asm.emit()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SectionKind {
+    Rom { bank: usize },
+    Ram,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum DataItem {
+    Expr(Expr),
+    Str(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum ItemKind {
+    Instr(Encoded),
+    Bytes(Vec<DataItem>),
+    Words(Vec<Expr>),
+    Space { len: u16, fill: Option<Expr> },
+}
+
+/// Something that occupies ROM space, found in the first pass.
+struct Item {
+    line_num: usize,
+    bank: usize,
+    addr: Addr,
+    kind: ItemKind,
+}
+
+#[derive(Default)]
+struct Assembler {
+    line_num: usize,
+    items: Vec<Item>,
+    labels: HashMap<String, BankAddr>,
+    constants: HashMap<String, Expr>,
+
+    /// The enclosing global label, used to expand local labels.
+    scope: String,
+
+    section: Option<SectionKind>,
+    /// The address where the next item in the current section goes.
+    pc: Addr,
+    /// Where the next section without an address starts, for each bank
+    /// (RAM sections are keyed by their start address).
+    section_ends: HashMap<(bool, usize), Addr>,
+}
+
+impl Assembler {
+    fn error(&self, msg: String) -> String {
+        format!("line {}: {}", self.line_num, msg)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = strip_comment(line).trim();
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        // Label definition.
+        let label_len = rest.chars().take_while(|c| is_symbol_char(*c)).count();
+        if label_len > 0 && rest[label_len..].starts_with(':') {
+            let name = &rest[..label_len];
+            self.define_label(name)?;
+            rest = rest[label_len..].trim_start_matches(':').trim();
+            if rest.is_empty() {
+                return Ok(());
+            }
+        }
+
+        let (word, args) = split_word(rest);
+        let keyword = word.to_ascii_lowercase();
+
+        // Constant definitions: `NAME EQU expr` or `DEF NAME EQU expr`.
+        let (def_name, def_args) = match keyword.as_str() {
+            "def" => split_word(args),
+            _ => (word, args),
+        };
+        let (equ, equ_args) = split_word(def_args);
+        if equ.eq_ignore_ascii_case("equ") {
+            let expr = parse_expr(equ_args, &self.scope)?;
+            self.define_symbol(def_name)?;
+            self.constants.insert(def_name.to_string(), expr);
+            return Ok(());
+        }
+
+        let operands = split_operands(args)?;
+        match keyword.as_str() {
+            "section" => self.start_section(&operands),
+            "db" => {
+                let items = operands
+                    .iter()
+                    .map(|op| match parse_string(op) {
+                        Some(s) => Ok(DataItem::Str(s)),
+                        None => parse_expr(op, &self.scope).map(DataItem::Expr),
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                self.add_item(ItemKind::Bytes(items))
+            }
+            "dw" => {
+                let exprs = operands
+                    .iter()
+                    .map(|op| parse_expr(op, &self.scope))
+                    .collect::<Result<Vec<_>, String>>()?;
+                self.add_item(ItemKind::Words(exprs))
+            }
+            "ds" => {
+                let Some(len) = operands.first() else {
+                    return Err("Expected a length for \"ds\".".to_string());
+                };
+                let len = parse_expr(len, &self.scope)?;
+                let len = len.eval(&|name| self.resolve_constant(name, 0), self.pc)?;
+                check_range(len, 0, 0xFFFF)?;
+                let fill = match operands.get(1) {
+                    Some(fill) => Some(parse_expr(fill, &self.scope)?),
+                    None => None,
+                };
+                self.add_item(ItemKind::Space {
+                    len: len as u16,
+                    fill,
+                })
+            }
+            _ => {
+                let operands = operands
+                    .iter()
+                    .map(|op| parse_operand(op, &self.scope))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let encoded = encode(&keyword, &operands)?;
+                self.add_item(ItemKind::Instr(encoded))
+            }
+        }
+    }
+
+    fn define_symbol(&self, name: &str) -> Result<(), String> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(format!("Symbol \"{}\" is already defined.", name));
+        }
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        let Some(section) = self.section else {
+            return Err(format!("Label \"{}\" is outside of a section.", name));
+        };
+
+        let full_name = full_symbol_name(name, &self.scope);
+        self.define_symbol(&full_name)?;
+
+        let bank = match section {
+            SectionKind::Rom { bank } => bank,
+            SectionKind::Ram => 0,
+        };
+        self.labels.insert(
+            full_name,
+            BankAddr {
+                bank,
+                addr: self.pc,
+            },
+        );
+
+        if !name.starts_with('.') {
+            self.scope = name.to_string();
+        }
+
+        Ok(())
+    }
+
+    /// Parses `SECTION "name", TYPE[addr], BANK[n]`.
+    fn start_section(&mut self, operands: &[&str]) -> Result<(), String> {
+        self.finish_section();
+
+        if operands.len() < 2 || parse_string(operands[0]).is_none() {
+            return Err("Expected: SECTION \"name\", TYPE[addr]".to_string());
+        }
+
+        let (kind_name, addr) = parse_bracketed(operands[1], &self.scope)?;
+        let bank = match operands.get(2) {
+            Some(bank) => {
+                let (name, bank) = parse_bracketed(bank, &self.scope)?;
+                if !name.eq_ignore_ascii_case("bank") || bank.is_none() {
+                    return Err(format!("Expected BANK[n], found \"{}\"", operands[2]));
+                }
+                bank
+            }
+            None => None,
+        };
+
+        let (kind, default_addr) = match kind_name.to_ascii_uppercase().as_str() {
+            "ROM0" => (SectionKind::Rom { bank: 0 }, 0x0150),
+            "ROMX" => {
+                let bank = bank.unwrap_or(1) as usize;
+                if bank == 0 {
+                    return Err("ROMX sections can't be in bank 0.".to_string());
+                }
+                (SectionKind::Rom { bank }, 0x4000)
+            }
+            "VRAM" => (SectionKind::Ram, 0x8000),
+            "SRAM" => (SectionKind::Ram, 0xA000),
+            "WRAM0" => (SectionKind::Ram, 0xC000),
+            "WRAMX" => (SectionKind::Ram, 0xD000),
+            "OAM" => (SectionKind::Ram, 0xFE00),
+            "HRAM" => (SectionKind::Ram, 0xFF80),
+            _ => return Err(format!("Unknown section type: \"{}\"", kind_name)),
+        };
+
+        let key = section_key(kind, default_addr);
+        let addr = match addr {
+            Some(addr) => addr as Addr,
+            None => *self.section_ends.get(&key).unwrap_or(&default_addr),
+        };
+
+        if let SectionKind::Rom { bank } = kind {
+            let range = if bank == 0 {
+                0x0000..0x4000
+            } else {
+                0x4000..0x8000
+            };
+            if !range.contains(&addr) {
+                return Err(format!("Section address ${:0>4X} is out of range.", addr));
+            }
+        }
+
+        self.section = Some(kind);
+        self.pc = addr;
+        self.scope.clear();
+
+        Ok(())
+    }
+
+    /// Remembers where the current section ended, so that the next section
+    /// without an address can continue from there.
+    fn finish_section(&mut self) {
+        let Some(kind) = self.section else {
+            return;
+        };
+
+        let key = section_key(kind, self.pc);
+        let end = self.section_ends.entry(key).or_insert(self.pc);
+        *end = Addr::max(*end, self.pc);
+    }
+
+    fn add_item(&mut self, kind: ItemKind) -> Result<(), String> {
+        let Some(section) = self.section else {
+            return Err("Code or data outside of a section.".to_string());
+        };
+
+        let len = match &kind {
+            ItemKind::Instr(encoded) => encoded.len(),
+            ItemKind::Bytes(items) => items
+                .iter()
+                .map(|item| match item {
+                    DataItem::Expr(_) => 1,
+                    DataItem::Str(s) => s.len() as u16,
+                })
+                .sum(),
+            ItemKind::Words(exprs) => 2 * exprs.len() as u16,
+            ItemKind::Space { len, .. } => *len,
+        };
+
+        let bank = match section {
+            SectionKind::Rom { bank } => bank,
+            SectionKind::Ram => {
+                if !matches!(kind, ItemKind::Space { fill: None, .. }) {
+                    return Err("RAM sections can only contain \"ds\".".to_string());
+                }
+                self.pc = self.pc.wrapping_add(len);
+                return Ok(());
+            }
+        };
+
+        let end = self.pc as usize + len as usize;
+        let bank_end = if bank == 0 { 0x4000 } else { 0x8000 };
+        if end > bank_end {
+            return Err("Section doesn't fit in its ROM bank.".to_string());
+        }
+
+        self.items.push(Item {
+            line_num: self.line_num,
+            bank,
+            addr: self.pc,
+            kind,
+        });
+        self.pc += len;
+
+        Ok(())
+    }
+
+    /// Resolves a constant or label. Labels are only allowed in the second
+    /// pass (`allow_labels`), once every label has an address.
+    fn resolve(&self, name: &str, depth: usize, allow_labels: bool) -> Result<i64, String> {
+        const MAX_DEPTH: usize = 32;
+
+        if let Some(loc) = self.labels.get(name).filter(|_| allow_labels) {
+            return Ok(loc.addr as i64);
+        }
+        if let Some(expr) = self.constants.get(name) {
+            if depth >= MAX_DEPTH {
+                return Err(format!("Constant \"{}\" is defined recursively.", name));
+            }
+            return expr.eval(&|name| self.resolve(name, depth + 1, allow_labels), 0);
+        }
+
+        Err(format!("Undefined symbol: \"{}\"", name))
+    }
+
+    fn resolve_constant(&self, name: &str, depth: usize) -> Result<i64, String> {
+        self.resolve(name, depth, false)
+    }
+
+    /// Runs the second pass: evaluates every operand and writes the ROM.
+    fn emit(mut self) -> Result<Program, String> {
+        self.finish_section();
+
+        let bank_count = self
+            .items
+            .iter()
+            .map(|item| item.bank + 1)
+            .max()
+            .unwrap_or(0)
+            .max(2)
+            .next_power_of_two();
+
+        let mut rom = vec![0; bank_count * ROM_BANK_SIZE];
+        let mut written = vec![false; rom.len()];
+
+        for item in &self.items {
+            let bytes = self
+                .emit_item(item)
+                .map_err(|msg| format!("line {}: {}", item.line_num, msg))?;
+
+            let loc = BankAddr {
+                bank: item.bank,
+                addr: item.addr,
+            };
+            let offset = item.bank * ROM_BANK_SIZE + (item.addr as usize % ROM_BANK_SIZE);
+            for (i, byte) in bytes.into_iter().enumerate() {
+                if written[offset + i] {
+                    return Err(format!(
+                        "line {}: Overlaps previously written data at {}.",
+                        item.line_num, loc
+                    ));
+                }
+                written[offset + i] = true;
+                rom[offset + i] = byte;
+            }
+        }
+
+        fix_header(&mut rom, &written, bank_count);
+
+        Ok(Program {
+            rom,
+            labels: self.labels,
+        })
+    }
+
+    fn emit_item(&self, item: &Item) -> Result<Vec<u8>, String> {
+        let pc = item.addr;
+        let eval = |expr: &Expr| expr.eval(&|name| self.resolve(name, 0, true), pc);
+        let byte = |expr: &Expr| {
+            let value = eval(expr)?;
+            check_range(value, -128, 0xFF)?;
+            Ok::<u8, String>(value as u8)
+        };
+        let word = |expr: &Expr| {
+            let value = eval(expr)?;
+            check_range(value, -0x8000, 0xFFFF)?;
+            Ok::<u16, String>(value as u16)
+        };
+
+        let mut bytes = vec![];
+        match &item.kind {
+            ItemKind::Instr(encoded) => {
+                bytes.extend_from_slice(&encoded.opcode);
+                let last = bytes.len() - 1;
+                match &encoded.patch {
+                    Patch::None => {}
+                    Patch::U8(e) => bytes.push(byte(e)?),
+                    Patch::I8(e) => {
+                        let value = eval(e)?;
+                        check_range(value, -128, 127)?;
+                        bytes.push(value as u8);
+                    }
+                    Patch::U16(e) => bytes.extend_from_slice(&word(e)?.to_le_bytes()),
+                    Patch::Rel(e) => {
+                        let next = pc as i64 + encoded.len() as i64;
+                        let offset = eval(e)? - next;
+                        check_range(offset, -128, 127)
+                            .map_err(|_| format!("Jump target is too far away ({}).", offset))?;
+                        bytes.push(offset as u8);
+                    }
+                    Patch::HighPage(e) => {
+                        let value = eval(e)?;
+                        if !(0..=0xFF).contains(&value) && !(0xFF00..=0xFFFF).contains(&value) {
+                            return Err(format!("ldh address ${:X} isn't in $FF00-$FFFF.", value));
+                        }
+                        bytes.push(value as u8);
+                    }
+                    Patch::RstVec(e) => {
+                        let value = eval(e)?;
+                        if !(0..=0x38).contains(&value) || value % 8 != 0 {
+                            return Err(format!("Invalid rst vector: ${:X}", value));
+                        }
+                        bytes[last] |= value as u8;
+                    }
+                    Patch::BitIdx(e) => {
+                        let value = eval(e)?;
+                        check_range(value, 0, 7)?;
+                        bytes[last] |= (value as u8) << 3;
+                    }
+                }
+            }
+            ItemKind::Bytes(items) => {
+                for item in items {
+                    match item {
+                        DataItem::Expr(e) => bytes.push(byte(e)?),
+                        DataItem::Str(s) => bytes.extend_from_slice(s.as_bytes()),
+                    }
+                }
+            }
+            ItemKind::Words(exprs) => {
+                for e in exprs {
+                    bytes.extend_from_slice(&word(e)?.to_le_bytes());
+                }
+            }
+            ItemKind::Space { len, fill } => {
+                let fill = match fill {
+                    Some(fill) => byte(fill)?,
+                    None => 0,
+                };
+                bytes.resize(*len as usize, fill);
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn section_key(kind: SectionKind, addr: Addr) -> (bool, usize) {
+    match kind {
+        SectionKind::Rom { bank } => (true, bank),
+        SectionKind::Ram => (false, (addr & 0xF000) as usize),
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> Result<(), String> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("Value {} is out of range.", value))
+    }
+}
+
+/// Writes the logo, ROM size and checksums into the header. The cartridge
+/// type is only written if the program didn't write it.
+fn fix_header(rom: &mut [u8], written: &[bool], bank_count: usize) {
+    let logo = nintendo_logo();
+    rom[0x0104..(0x0104 + logo.len())].copy_from_slice(&logo);
+
+    if !written[0x0147] && bank_count > 2 {
+        rom[0x0147] = 0x01; // MBC1
+    }
+    rom[0x0148] = bank_count.trailing_zeros() as u8 - 1;
+
+    let mut header_checksum: u8 = 0;
+    for byte in &rom[0x0134..=0x014C] {
+        header_checksum = header_checksum.wrapping_sub(*byte).wrapping_sub(1);
+    }
+    rom[0x014D] = header_checksum;
+
+    rom[0x014E] = 0;
+    rom[0x014F] = 0;
+    let global_checksum = rom
+        .iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    rom[0x014E..=0x014F].copy_from_slice(&global_checksum.to_be_bytes());
+}
+
+/// Removes a `;` comment that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// Splits off the first whitespace separated word.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Splits operands on commas that aren't inside brackets, parentheses or
+/// strings.
+fn split_operands(text: &str) -> Result<Vec<&str>, String> {
+    if text.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '[' | '(' if !in_string => depth += 1,
+            ']' | ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim());
+
+    if in_string || depth != 0 {
+        return Err(format!("Unbalanced operands: \"{}\"", text));
+    }
+    if operands.iter().any(|op| op.is_empty()) {
+        return Err(format!("Empty operand in \"{}\"", text));
+    }
+
+    Ok(operands)
+}
+
+/// Returns the contents of a quoted string.
+fn parse_string(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(text[1..(text.len() - 1)].to_string())
+    } else {
+        None
+    }
+}
+
+/// Parses `NAME` or `NAME[expr]`, where the expression must be a constant.
+fn parse_bracketed(text: &str, scope: &str) -> Result<(String, Option<i64>), String> {
+    let text = text.trim();
+    let Some(open) = text.find('[') else {
+        return Ok((text.to_string(), None));
+    };
+    if !text.ends_with(']') {
+        return Err(format!("Expected ']' in \"{}\"", text));
+    }
+
+    let expr = parse_expr(&text[(open + 1)..(text.len() - 1)], scope)?;
+    let value = expr.eval(&|name| Err(format!("Undefined symbol: \"{}\"", name)), 0)?;
+
+    Ok((text[..open].trim().to_string(), Some(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cart::header::CartHeader,
+        cpu::{
+            disasm::DecodedInstr,
+            instr::{lookup, Instr},
+        },
+    };
+
+    use super::*;
+
+    /// Assembles a single line of code at $1000 and returns its bytes.
+    fn assemble_line(line: &str) -> Vec<u8> {
+        let src = format!("SECTION \"Test\", ROM0[$1000]\n{}\nEnd:", line);
+        let program = assemble(&src).unwrap();
+        let len = program.label_addr("End").unwrap().addr as usize - 0x1000;
+
+        program.rom[0x1000..(0x1000 + len)].to_vec()
+    }
+
+    #[test]
+    fn test_round_trip_with_disassembler() {
+        for idx in 0..512u16 {
+            let has_cb_prefix = idx >= 0x100;
+            let op = idx as u8;
+            if !has_cb_prefix && op == Instr::CB_PREFIX {
+                continue;
+            }
+
+            let mut bytes = if has_cb_prefix {
+                vec![Instr::CB_PREFIX, op]
+            } else {
+                vec![op]
+            };
+            bytes.extend_from_slice(&[0x12, 0x34]);
+
+            let read = |addr: Addr| *bytes.get(addr as usize - 0x1000).unwrap_or(&0);
+            let decoded = DecodedInstr::read(read, 0x1000);
+            let text = decoded.format(0x1000, |_| None);
+            let expected = &bytes[..(decoded.len as usize)];
+
+            let assembled = assemble_line(&text);
+            if lookup(op, has_cb_prefix) == Instr::Stop {
+                // STOP is always followed by a padding byte.
+                assert_eq!(assembled, [0x10, 0x00], "{}", text);
+            } else {
+                assert_eq!(assembled, expected, "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternate_syntax() {
+        assert_eq!(assemble_line("LD A, [HLI]"), [0x2A]);
+        assert_eq!(assemble_line("ldi [hl], a"), [0x22]);
+        assert_eq!(assemble_line("ld [$FF00+c], a"), [0xE2]);
+        assert_eq!(assemble_line("sub b"), [0x90]);
+        assert_eq!(assemble_line("cp $10"), [0xFE, 0x10]);
+        assert_eq!(assemble_line("ld hl, sp - 2"), [0xF8, 0xFE]);
+        assert_eq!(assemble_line("ldh [$40], a"), [0xE0, 0x40]);
+        assert_eq!(assemble_line("jp [hl]"), [0xE9]);
+        assert_eq!(assemble_line("ld a, HIGH($1234) + %11"), [0x3E, 0x15]);
+    }
+
+    #[test]
+    fn test_labels_and_data() {
+        let src = r#"
+COUNT EQU 3
+DEF BASE EQU $C000 + COUNT
+
+SECTION "Vars", WRAM0
+wCounter: ds 1
+wBuffer:: ds 2
+
+SECTION "Main", ROM0[$150]
+Main:
+    ld b, COUNT
+.loop:
+    dec b
+    jr nz, .loop
+    call Func
+    jp Main
+
+SECTION "Func", ROMX[$4000], BANK[2]
+Func:
+    ld [wBuffer], a
+    ret
+Table:
+    db "AB", 1, -1
+    dw Table, BASE
+    ds 2, $FF
+"#;
+        let program = assemble(src).unwrap();
+        let rom = &program.rom;
+
+        let label_addr = |name| program.label_addr(name).unwrap();
+        assert_eq!(
+            label_addr("wCounter"),
+            BankAddr {
+                bank: 0,
+                addr: 0xC000
+            }
+        );
+        assert_eq!(
+            label_addr("wBuffer"),
+            BankAddr {
+                bank: 0,
+                addr: 0xC001
+            }
+        );
+        assert_eq!(
+            label_addr("Main.loop"),
+            BankAddr {
+                bank: 0,
+                addr: 0x0152
+            }
+        );
+        assert_eq!(
+            label_addr("Table"),
+            BankAddr {
+                bank: 2,
+                addr: 0x4004
+            }
+        );
+        assert_eq!(
+            rom[0x0150..0x015D],
+            [0x06, 0x03, 0x05, 0x20, 0xFD, 0xCD, 0x00, 0x40, 0xC3, 0x50, 0x01, 0x00, 0x00]
+        );
+
+        // Bank 2 forces a 4 bank ROM.
+        assert_eq!(rom.len(), 4 * ROM_BANK_SIZE);
+        let func = 2 * ROM_BANK_SIZE;
+        assert_eq!(
+            rom[func..(func + 16)],
+            [
+                0xEA, 0x01, 0xC0, 0xC9, b'A', b'B', 0x01, 0xFF, 0x04, 0x40, 0x03, 0xC0, 0xFF, 0xFF,
+                0x00, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_is_valid() {
+        let program = assemble("SECTION \"Entry\", ROM0[$100]\n    nop\n    jp $150").unwrap();
+        let header = CartHeader::parse(&program.rom).unwrap();
+
+        assert!(header.is_nintendo_logo_matching);
+        assert!(header.is_checksum_matching);
+        assert_eq!(header.rom_bank_count, 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |src: &str| assemble(src).err().unwrap();
+
+        assert!(err("    nop").contains("outside of a section"));
+        assert!(err("SECTION \"A\", ROM0\n    foo a").starts_with("line 2: Unknown mnemonic"));
+        assert!(err("SECTION \"A\", ROM0\n    ld [hl], [hl]").contains("Invalid operands"));
+        assert!(err("SECTION \"A\", ROM0\n    jp Nowhere").contains("Undefined symbol"));
+        assert!(err("SECTION \"A\", ROM0\n    ld a, 256").contains("out of range"));
+        assert!(err("SECTION \"A\", ROM0\nX:\n    ds 200\n    jr X").contains("too far"));
+        assert!(err("SECTION \"A\", WRAM0\n    ds $10000").contains("out of range"));
+        assert!(
+            err("SECTION \"A\", ROM0[0]\n nop\nSECTION \"B\", ROM0[0]\n nop").contains("Overlaps")
+        );
+    }
+}
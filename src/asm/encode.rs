@@ -0,0 +1,281 @@
+use crate::cpu::instr::{Cond, Instr, R16Mem, R8};
+
+use super::{
+    expr::Expr,
+    operand::{Operand as Op, Reg16},
+};
+
+/// An operand value that is only known once every label has an address.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Patch {
+    None,
+    /// An 8-bit value, signed or unsigned.
+    U8(Expr),
+    /// A signed 8-bit offset.
+    I8(Expr),
+    U16(Expr),
+    /// A `jr` target, stored as an offset from the next instruction.
+    Rel(Expr),
+    /// An `ldh` address in $FF00-$FFFF, stored as its low byte.
+    HighPage(Expr),
+    /// An `rst` vector, merged into the opcode.
+    RstVec(Expr),
+    /// A bit index (0-7), merged into the opcode.
+    BitIdx(Expr),
+}
+
+impl Patch {
+    /// Number of bytes the value adds after the opcode.
+    pub fn len(&self) -> u16 {
+        match self {
+            Patch::None | Patch::RstVec(_) | Patch::BitIdx(_) => 0,
+            Patch::U8(_) | Patch::I8(_) | Patch::Rel(_) | Patch::HighPage(_) => 1,
+            Patch::U16(_) => 2,
+        }
+    }
+}
+
+/// An encoded instruction, still missing the value of its operand.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Encoded {
+    pub opcode: Vec<u8>,
+    pub patch: Patch,
+}
+
+impl Encoded {
+    fn new(opcode: u8) -> Self {
+        Self {
+            opcode: vec![opcode],
+            patch: Patch::None,
+        }
+    }
+
+    fn with(opcode: u8, patch: Patch) -> Self {
+        Self {
+            opcode: vec![opcode],
+            patch,
+        }
+    }
+
+    fn cb(opcode: u8) -> Self {
+        Self {
+            opcode: vec![Instr::CB_PREFIX, opcode],
+            patch: Patch::None,
+        }
+    }
+
+    pub fn len(&self) -> u16 {
+        self.opcode.len() as u16 + self.patch.len()
+    }
+}
+
+fn r8_idx(r: R8) -> u8 {
+    r as u8
+}
+
+fn r16_idx(r: Reg16) -> Option<u8> {
+    match r {
+        Reg16::BC => Some(0),
+        Reg16::DE => Some(1),
+        Reg16::HL => Some(2),
+        Reg16::SP => Some(3),
+        Reg16::AF => None,
+    }
+}
+
+fn r16stk_idx(r: Reg16) -> Option<u8> {
+    match r {
+        Reg16::BC => Some(0),
+        Reg16::DE => Some(1),
+        Reg16::HL => Some(2),
+        Reg16::AF => Some(3),
+        Reg16::SP => None,
+    }
+}
+
+fn r16mem_idx(r: R16Mem) -> u8 {
+    r as u8
+}
+
+fn cond_idx(c: Cond) -> u8 {
+    c as u8
+}
+
+/// Encodes an instruction from its (lower case) mnemonic and operands.
+pub fn encode(mnemonic: &str, operands: &[Op]) -> Result<Encoded, String> {
+    let bad_operands = || {
+        Err(format!(
+            "Invalid operands for \"{}\": {:?}",
+            mnemonic, operands
+        ))
+    };
+
+    // ALU ops can be written with or without the "a," operand.
+    let alu_base = match mnemonic {
+        "add" => Some(0),
+        "adc" => Some(1),
+        "sub" => Some(2),
+        "sbc" => Some(3),
+        "and" => Some(4),
+        "xor" => Some(5),
+        "or" => Some(6),
+        "cp" => Some(7),
+        _ => None,
+    };
+
+    // CB prefixed rotates and shifts.
+    let shift_base = match mnemonic {
+        "rlc" => Some(0),
+        "rrc" => Some(1),
+        "rl" => Some(2),
+        "rr" => Some(3),
+        "sla" => Some(4),
+        "sra" => Some(5),
+        "swap" => Some(6),
+        "srl" => Some(7),
+        _ => None,
+    };
+
+    let encoded = match (mnemonic, operands) {
+        // Operands-less instructions.
+        ("nop", []) => Encoded::new(0x00),
+        ("stop", []) => Encoded {
+            opcode: vec![0x10, 0x00],
+            patch: Patch::None,
+        },
+        ("halt", []) => Encoded::new(0x76),
+        ("di", []) => Encoded::new(0xF3),
+        ("ei", []) => Encoded::new(0xFB),
+        ("rlca", []) => Encoded::new(0x07),
+        ("rrca", []) => Encoded::new(0x0F),
+        ("rla", []) => Encoded::new(0x17),
+        ("rra", []) => Encoded::new(0x1F),
+        ("daa", []) => Encoded::new(0x27),
+        ("cpl", []) => Encoded::new(0x2F),
+        ("scf", []) => Encoded::new(0x37),
+        ("ccf", []) => Encoded::new(0x3F),
+        ("ret", []) => Encoded::new(0xC9),
+        ("reti", []) => Encoded::new(0xD9),
+
+        // Loads.
+        ("ld", [Op::R8(R8::HlMem), Op::R8(R8::HlMem)]) => return bad_operands(),
+        ("ld", [Op::R8(dst), Op::R8(src)]) => {
+            Encoded::new(0x40 | (r8_idx(*dst) << 3) | r8_idx(*src))
+        }
+        ("ld", [Op::R8(dst), Op::Imm(e)]) => {
+            Encoded::with(0x06 | (r8_idx(*dst) << 3), Patch::U8(e.clone()))
+        }
+        ("ld", [Op::R16(Reg16::SP), Op::R16(Reg16::HL)]) => Encoded::new(0xF9),
+        ("ld", [Op::R16(Reg16::HL), Op::SpOffset(e)]) => Encoded::with(0xF8, Patch::I8(e.clone())),
+        ("ld", [Op::R16(dst), Op::Imm(e)]) => {
+            let Some(idx) = r16_idx(*dst) else {
+                return bad_operands();
+            };
+            Encoded::with(0x01 | (idx << 4), Patch::U16(e.clone()))
+        }
+        ("ld", [Op::R16Mem(dst), Op::R8(R8::A)]) => Encoded::new(0x02 | (r16mem_idx(*dst) << 4)),
+        ("ld", [Op::R8(R8::A), Op::R16Mem(src)]) => Encoded::new(0x0A | (r16mem_idx(*src) << 4)),
+        ("ld", [Op::Mem(e), Op::R16(Reg16::SP)]) => Encoded::with(0x08, Patch::U16(e.clone())),
+        ("ld", [Op::Mem(e), Op::R8(R8::A)]) => Encoded::with(0xEA, Patch::U16(e.clone())),
+        ("ld", [Op::R8(R8::A), Op::Mem(e)]) => Encoded::with(0xFA, Patch::U16(e.clone())),
+        ("ld" | "ldh", [Op::MemC, Op::R8(R8::A)]) => Encoded::new(0xE2),
+        ("ld" | "ldh", [Op::R8(R8::A), Op::MemC]) => Encoded::new(0xF2),
+        ("ldh", [Op::Mem(e), Op::R8(R8::A)]) => Encoded::with(0xE0, Patch::HighPage(e.clone())),
+        ("ldh", [Op::R8(R8::A), Op::Mem(e)]) => Encoded::with(0xF0, Patch::HighPage(e.clone())),
+        ("ldi", [Op::R8(R8::HlMem), Op::R8(R8::A)]) => Encoded::new(0x22),
+        ("ldi", [Op::R8(R8::A), Op::R8(R8::HlMem)]) => Encoded::new(0x2A),
+        ("ldd", [Op::R8(R8::HlMem), Op::R8(R8::A)]) => Encoded::new(0x32),
+        ("ldd", [Op::R8(R8::A), Op::R8(R8::HlMem)]) => Encoded::new(0x3A),
+
+        // Increments and decrements.
+        ("inc", [Op::R8(r)]) => Encoded::new(0x04 | (r8_idx(*r) << 3)),
+        ("dec", [Op::R8(r)]) => Encoded::new(0x05 | (r8_idx(*r) << 3)),
+        ("inc" | "dec", [Op::R16(r)]) => {
+            let Some(idx) = r16_idx(*r) else {
+                return bad_operands();
+            };
+            let base = if mnemonic == "inc" { 0x03 } else { 0x0B };
+            Encoded::new(base | (idx << 4))
+        }
+
+        // 16-bit arithmetic.
+        ("add", [Op::R16(Reg16::HL), Op::R16(r)]) => {
+            let Some(idx) = r16_idx(*r) else {
+                return bad_operands();
+            };
+            Encoded::new(0x09 | (idx << 4))
+        }
+        ("add", [Op::R16(Reg16::SP), Op::Imm(e)]) => Encoded::with(0xE8, Patch::I8(e.clone())),
+
+        // 8-bit arithmetic.
+        (_, [Op::R8(R8::A), Op::R8(r)]) | (_, [Op::R8(r)]) if alu_base.is_some() => {
+            Encoded::new(0x80 | (alu_base.unwrap() << 3) | r8_idx(*r))
+        }
+        (_, [Op::R8(R8::A), Op::Imm(e)]) | (_, [Op::Imm(e)]) if alu_base.is_some() => {
+            Encoded::with(0xC6 | (alu_base.unwrap() << 3), Patch::U8(e.clone()))
+        }
+
+        // Jumps, calls and returns.
+        ("jr", [Op::Imm(e)]) => Encoded::with(0x18, Patch::Rel(e.clone())),
+        ("jr", [c, Op::Imm(e)]) if c.as_cond().is_some() => Encoded::with(
+            0x20 | (cond_idx(c.as_cond().unwrap()) << 3),
+            Patch::Rel(e.clone()),
+        ),
+        ("jp", [Op::R16(Reg16::HL)]) | ("jp", [Op::R8(R8::HlMem)]) => Encoded::new(0xE9),
+        ("jp", [Op::Imm(e)]) => Encoded::with(0xC3, Patch::U16(e.clone())),
+        ("jp", [c, Op::Imm(e)]) if c.as_cond().is_some() => Encoded::with(
+            0xC2 | (cond_idx(c.as_cond().unwrap()) << 3),
+            Patch::U16(e.clone()),
+        ),
+        ("call", [Op::Imm(e)]) => Encoded::with(0xCD, Patch::U16(e.clone())),
+        ("call", [c, Op::Imm(e)]) if c.as_cond().is_some() => Encoded::with(
+            0xC4 | (cond_idx(c.as_cond().unwrap()) << 3),
+            Patch::U16(e.clone()),
+        ),
+        ("ret", [c]) if c.as_cond().is_some() => {
+            Encoded::new(0xC0 | (cond_idx(c.as_cond().unwrap()) << 3))
+        }
+        ("rst", [Op::Imm(e)]) => Encoded::with(0xC7, Patch::RstVec(e.clone())),
+
+        // Stack.
+        ("push" | "pop", [Op::R16(r)]) => {
+            let Some(idx) = r16stk_idx(*r) else {
+                return bad_operands();
+            };
+            let base = if mnemonic == "push" { 0xC5 } else { 0xC1 };
+            Encoded::new(base | (idx << 4))
+        }
+
+        // 0xCB prefix instructions.
+        (_, [Op::R8(r)]) if shift_base.is_some() => {
+            Encoded::cb((shift_base.unwrap() << 3) | r8_idx(*r))
+        }
+        ("bit" | "res" | "set", [Op::Imm(e), Op::R8(r)]) => {
+            let base = match mnemonic {
+                "bit" => 0x40,
+                "res" => 0x80,
+                _ => 0xC0,
+            };
+            Encoded {
+                opcode: vec![Instr::CB_PREFIX, base | r8_idx(*r)],
+                patch: Patch::BitIdx(e.clone()),
+            }
+        }
+
+        _ if is_mnemonic(mnemonic) => return bad_operands(),
+        _ => return Err(format!("Unknown mnemonic: \"{}\"", mnemonic)),
+    };
+
+    Ok(encoded)
+}
+
+fn is_mnemonic(name: &str) -> bool {
+    const MNEMONICS: &[&str] = &[
+        "nop", "stop", "halt", "di", "ei", "rlca", "rrca", "rla", "rra", "daa", "cpl", "scf",
+        "ccf", "ret", "reti", "ld", "ldh", "ldi", "ldd", "inc", "dec", "add", "adc", "sub", "sbc",
+        "and", "xor", "or", "cp", "jr", "jp", "call", "rst", "push", "pop", "rlc", "rrc", "rl",
+        "rr", "sla", "sra", "swap", "srl", "bit", "res", "set",
+    ];
+
+    MNEMONICS.contains(&name)
+}
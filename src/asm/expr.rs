@@ -0,0 +1,276 @@
+/// A numeric expression in an assembly operand.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expr {
+    Num(i64),
+    /// A label or constant, with local labels already expanded to their
+    /// full name (e.g. `Main.loop`).
+    Symbol(String),
+    /// The address of the current instruction (`@`).
+    Pc,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    High(Box<Expr>),
+    Low(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BinOp {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl BinOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::Xor => 2,
+            BinOp::And => 3,
+            BinOp::Shl | BinOp::Shr => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Mod => 6,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> Result<i64, String> {
+        let value = match self {
+            BinOp::Or => a | b,
+            BinOp::Xor => a ^ b,
+            BinOp::And => a & b,
+            BinOp::Shl => a.wrapping_shl(b as u32),
+            BinOp::Shr => a.wrapping_shr(b as u32),
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::Div | BinOp::Mod if b == 0 => {
+                return Err("Division by zero.".to_string());
+            }
+            BinOp::Div => a / b,
+            BinOp::Mod => a % b,
+        };
+
+        Ok(value)
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression. `resolve` returns the value of a symbol,
+    /// and `pc` is the address of the current instruction.
+    pub fn eval(
+        &self,
+        resolve: &impl Fn(&str) -> Result<i64, String>,
+        pc: u16,
+    ) -> Result<i64, String> {
+        let value = match self {
+            Expr::Num(n) => *n,
+            Expr::Symbol(name) => resolve(name)?,
+            Expr::Pc => pc as i64,
+            Expr::Neg(e) => -e.eval(resolve, pc)?,
+            Expr::Not(e) => !e.eval(resolve, pc)?,
+            Expr::High(e) => (e.eval(resolve, pc)? >> 8) & 0xFF,
+            Expr::Low(e) => e.eval(resolve, pc)? & 0xFF,
+            Expr::Binary(op, a, b) => op.apply(a.eval(resolve, pc)?, b.eval(resolve, pc)?)?,
+        };
+
+        Ok(value)
+    }
+}
+
+/// Returns true if `c` can appear in a symbol name.
+pub fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Parses `text` as an expression. Local labels (starting with `.`) are
+/// expanded using the enclosing global label `scope`.
+pub fn parse_expr(text: &str, scope: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser {
+        chars: text.chars().collect(),
+        pos: 0,
+        scope,
+    };
+
+    let expr = parser.parse_binary(0)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(format!("Unexpected characters in expression: \"{}\"", text));
+    }
+
+    Ok(expr)
+}
+
+/// Expands a local label name (e.g. `.loop`) to its full name.
+pub fn full_symbol_name(name: &str, scope: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    scope: &'a str,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn next_binop(&mut self) -> Option<BinOp> {
+        self.skip_whitespace();
+        let c = self.peek()?;
+        let next = self.chars.get(self.pos + 1).copied();
+
+        let (op, len) = match (c, next) {
+            ('<', Some('<')) => (BinOp::Shl, 2),
+            ('>', Some('>')) => (BinOp::Shr, 2),
+            ('|', _) => (BinOp::Or, 1),
+            ('^', _) => (BinOp::Xor, 1),
+            ('&', _) => (BinOp::And, 1),
+            ('+', _) => (BinOp::Add, 1),
+            ('-', _) => (BinOp::Sub, 1),
+            ('*', _) => (BinOp::Mul, 1),
+            ('/', _) => (BinOp::Div, 1),
+            ('%', _) => (BinOp::Mod, 1),
+            _ => return None,
+        };
+        self.pos += len;
+
+        Some(op)
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            let start = self.pos;
+            let Some(op) = self.next_binop() else {
+                break;
+            };
+            if op.precedence() <= min_precedence {
+                self.pos = start;
+                break;
+            }
+
+            let rhs = self.parse_binary(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.parse_unary()?)))
+            }
+            Some('~') => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some('+') => {
+                self.pos += 1;
+                self.parse_unary()
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        let Some(c) = self.peek() else {
+            return Err("Expected an expression.".to_string());
+        };
+
+        match c {
+            '(' => {
+                self.pos += 1;
+                let expr = self.parse_binary(0)?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            '@' => {
+                self.pos += 1;
+                Ok(Expr::Pc)
+            }
+            '$' => {
+                self.pos += 1;
+                self.parse_number(16)
+            }
+            '%' => {
+                self.pos += 1;
+                self.parse_number(2)
+            }
+            '0' if matches!(self.chars.get(self.pos + 1), Some('x') | Some('X')) => {
+                self.pos += 2;
+                self.parse_number(16)
+            }
+            _ if c.is_ascii_digit() => self.parse_number(10),
+            _ if is_symbol_char(c) => {
+                let name = self.take_while(is_symbol_char);
+                self.skip_whitespace();
+                let func = name.to_ascii_lowercase();
+                if self.peek() == Some('(') && (func == "high" || func == "low") {
+                    self.pos += 1;
+                    let arg = Box::new(self.parse_binary(0)?);
+                    self.expect(')')?;
+                    return Ok(if func == "high" {
+                        Expr::High(arg)
+                    } else {
+                        Expr::Low(arg)
+                    });
+                }
+
+                Ok(Expr::Symbol(full_symbol_name(&name, self.scope)))
+            }
+            _ => Err(format!("Unexpected character in expression: '{}'", c)),
+        }
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Result<Expr, String> {
+        let digits = self.take_while(|c| c.is_digit(radix) || c == '_');
+        let digits = digits.replace('_', "");
+
+        i64::from_str_radix(&digits, radix)
+            .map(Expr::Num)
+            .map_err(|_| format!("Invalid number: \"{}\"", digits))
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' in expression.", c))
+        }
+    }
+}
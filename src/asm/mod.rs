@@ -0,0 +1,9 @@
+//! A small SM83 assembler for writing test programs inline, using the same
+//! syntax as RGBDS.
+
+mod assembler;
+mod encode;
+mod expr;
+mod operand;
+
+pub use assembler::{assemble, Program};
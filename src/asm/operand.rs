@@ -0,0 +1,92 @@
+use crate::cpu::instr::{Cond, R16Mem, R8};
+
+use super::expr::{parse_expr, Expr};
+
+/// The 16-bit registers that can be named in an operand.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reg16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+/// A parsed instruction operand.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Operand {
+    /// An 8-bit register, or `[hl]`.
+    R8(R8),
+    R16(Reg16),
+    /// `[bc]`, `[de]`, `[hl+]` or `[hl-]`.
+    R16Mem(R16Mem),
+    /// `[c]`, i.e. `[$FF00+c]`.
+    MemC,
+    /// A condition other than `c`, which is parsed as a register.
+    Cond(Cond),
+    /// `sp+e8`.
+    SpOffset(Expr),
+    /// `[n16]`.
+    Mem(Expr),
+    Imm(Expr),
+}
+
+impl Operand {
+    /// Returns the condition this operand names, if it can be one.
+    pub fn as_cond(&self) -> Option<Cond> {
+        match self {
+            Operand::Cond(cond) => Some(*cond),
+            Operand::R8(R8::C) => Some(Cond::C),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a single operand. `scope` is the enclosing global label.
+pub fn parse_operand(text: &str, scope: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    let compact: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    let operand = match compact.as_str() {
+        "a" => Operand::R8(R8::A),
+        "b" => Operand::R8(R8::B),
+        "c" => Operand::R8(R8::C),
+        "d" => Operand::R8(R8::D),
+        "e" => Operand::R8(R8::E),
+        "h" => Operand::R8(R8::H),
+        "l" => Operand::R8(R8::L),
+        "[hl]" => Operand::R8(R8::HlMem),
+
+        "af" => Operand::R16(Reg16::AF),
+        "bc" => Operand::R16(Reg16::BC),
+        "de" => Operand::R16(Reg16::DE),
+        "hl" => Operand::R16(Reg16::HL),
+        "sp" => Operand::R16(Reg16::SP),
+
+        "[bc]" => Operand::R16Mem(R16Mem::BC),
+        "[de]" => Operand::R16Mem(R16Mem::DE),
+        "[hl+]" | "[hli]" => Operand::R16Mem(R16Mem::HlInc),
+        "[hl-]" | "[hld]" => Operand::R16Mem(R16Mem::HlDec),
+
+        "[c]" | "[$ff00+c]" | "[0xff00+c]" => Operand::MemC,
+
+        "nz" => Operand::Cond(Cond::NZ),
+        "z" => Operand::Cond(Cond::Z),
+        "nc" => Operand::Cond(Cond::NC),
+
+        _ if compact.starts_with("sp+") || compact.starts_with("sp-") => {
+            // Keep the sign as part of the offset expression.
+            Operand::SpOffset(parse_expr(&text.trim()[2..], scope)?)
+        }
+        _ if text.starts_with('[') && text.ends_with(']') => {
+            Operand::Mem(parse_expr(&text[1..(text.len() - 1)], scope)?)
+        }
+        _ => Operand::Imm(parse_expr(text, scope)?),
+    };
+
+    Ok(operand)
+}
@@ -41,6 +41,18 @@ impl Cart {
             return Err(format!("Unable to read file {}.", file_path));
         };
 
+        Self::from_rom(&rom, verbose)
+    }
+
+    /// Creates a new `Cart` instance from the contents of a gb file.
+    pub fn from_rom(rom: &[u8], verbose: bool) -> Result<Self, String> {
+        if rom.len() < 0x0150 {
+            return Err(format!(
+                "Rom is too small to contain a header ({} bytes).",
+                rom.len()
+            ));
+        }
+
         let cart_type_id = rom[0x0147];
         let Some(cart_type) = CartType::from_u8(cart_type_id) else {
             return Err(format!("Invalid cart type ID in header: {}.", cart_type_id));
@@ -53,12 +65,12 @@ impl Cart {
             ));
         }
 
-        let header = CartHeader::parse(&rom)?;
+        let header = CartHeader::parse(rom)?;
         if verbose {
             header.print();
         }
 
-        let hw = Self::create_hw(&header, &rom);
+        let hw = Self::create_hw(&header, rom);
 
         Ok(Self { header, hw })
     }
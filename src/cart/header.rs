@@ -4,7 +4,7 @@ use crate::cart::consts::{RAM_BANK_SIZE, ROM_BANK_SIZE};
 
 use super::type_::CartType;
 
-const NINTENDO_LOGO: &[u8] = include_bytes!("..\\..\\assets\\files\\nintendo_logo.txt");
+const NINTENDO_LOGO: &[u8] = include_bytes!("../../assets/files/nintendo_logo.txt");
 
 /// The interpretation of the data in the cartridge ROM header (addresses 0x0100-0x014F).
 pub struct CartHeader {
@@ -147,14 +147,19 @@ fn check_header_checksum(rom: &[u8]) -> (u8, bool) {
     (checksum, is_matching)
 }
 
-fn check_nintendo_logo(rom: &[u8]) -> bool {
+/// Returns the logo bitmap that every cartridge header must contain.
+pub fn nintendo_logo() -> Vec<u8> {
     let logo_text =
         String::from_utf8(NINTENDO_LOGO.to_vec()).expect("Unable to read nintendo logo file.");
 
-    let logo_bytes = logo_text
+    logo_text
         .split_ascii_whitespace()
         .map(|s| u8::from_str_radix(s, 16).unwrap())
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+fn check_nintendo_logo(rom: &[u8]) -> bool {
+    let logo_bytes = nintendo_logo();
 
     let cart_rom_span = &rom[0x104..0x134];
 
@@ -0,0 +1,661 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{self, Display},
+};
+
+use crate::{cart::consts::ROM_BANK_SIZE, mem::Addr, util::math::join_16};
+
+use super::instr::{lookup, Cond, ImmType, Instr, R16Mem, R16Stk, R16, R8};
+
+/// How the ROM is traversed when disassembling.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisasmMode {
+    /// Every byte is decoded as an instruction, from the start to the end
+    /// of each bank (except the cartridge header).
+    Linear,
+
+    /// Only bytes reachable from the entry points are decoded as
+    /// instructions. Everything else is emitted as data.
+    Recursive,
+}
+
+/// A location in a banked ROM.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct BankAddr {
+    pub bank: usize,
+    pub addr: Addr,
+}
+
+impl BankAddr {
+    /// Returns the RGBDS label name for this location.
+    pub fn label(self) -> String {
+        format!("L{:0>2X}_{:0>4X}", self.bank, self.addr)
+    }
+
+    /// Returns the offset of this location in the ROM file.
+    fn rom_offset(self) -> usize {
+        let bank_offs = self.bank * ROM_BANK_SIZE;
+        bank_offs + (self.addr as usize % ROM_BANK_SIZE)
+    }
+}
+
+impl Display for BankAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:0>2X}:{:0>4X}", self.bank, self.addr)
+    }
+}
+
+/// An instruction and its immediate operand, decoded from memory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DecodedInstr {
+    pub instr: Instr,
+    /// The raw immediate value (zero if the instruction has none).
+    pub imm: u16,
+    /// Total length in bytes, including the 0xCB prefix and immediate.
+    pub len: u16,
+}
+
+impl DecodedInstr {
+    /// Decodes the instruction at `addr` using the given memory reader.
+    pub fn read(read: impl Fn(Addr) -> u8, addr: Addr) -> Self {
+        let mut op = read(addr);
+        let has_cb_prefix = op == Instr::CB_PREFIX;
+        let mut len = 1;
+        if has_cb_prefix {
+            op = read(addr.wrapping_add(1));
+            len += 1;
+        }
+        let instr = lookup(op, has_cb_prefix);
+
+        let imm_addr = addr.wrapping_add(len);
+        let (imm, imm_len) = match instr.imm_type() {
+            ImmType::None => (0, 0),
+            ImmType::Imm8 => (read(imm_addr) as u16, 1),
+            ImmType::Imm16 => {
+                let lo = read(imm_addr);
+                let hi = read(imm_addr.wrapping_add(1));
+                (join_16(hi, lo), 2)
+            }
+        };
+        // STOP is followed by a padding byte, which the CPU skips.
+        let pad_len = if instr == Instr::Stop { 1 } else { 0 };
+
+        Self {
+            instr,
+            imm,
+            len: len + imm_len + pad_len,
+        }
+    }
+
+    /// Returns the address that this instruction (at `addr`) may jump to.
+    pub fn branch_target(&self, addr: Addr) -> Option<Addr> {
+        match self.instr {
+            Instr::Jr_Imm8 | Instr::Jr_Cond_Imm8 { .. } => {
+                let next = addr.wrapping_add(self.len);
+                Some(next.wrapping_add_signed(self.imm as u8 as i8 as i16))
+            }
+            Instr::Jp_Imm16
+            | Instr::Jp_Cond_Imm16 { .. }
+            | Instr::Call_Imm16
+            | Instr::Call_Cond_Imm16 { .. } => Some(self.imm),
+            Instr::Rst_Tgt3 { tgt3 } => Some(tgt3 as Addr * 8),
+            _ => None,
+        }
+    }
+
+    /// Returns true if execution never continues to the next instruction.
+    pub fn ends_flow(&self) -> bool {
+        matches!(
+            self.instr,
+            Instr::Jr_Imm8
+                | Instr::Jp_Imm16
+                | Instr::Jp_Hl
+                | Instr::Ret
+                | Instr::Reti
+                | Instr::Invalid(_)
+        )
+    }
+
+    /// Formats the instruction (at `addr`) in RGBDS syntax. Jump targets are
+    /// named using `label_of`, or written as an address if it returns `None`.
+    pub fn format(&self, addr: Addr, label_of: impl Fn(Addr) -> Option<String>) -> String {
+        let imm8 = || format!("${:0>2X}", self.imm);
+        let imm16 = || format!("${:0>4X}", self.imm);
+        let target = || {
+            let target = self.branch_target(addr).unwrap_or_default();
+            label_of(target).unwrap_or_else(|| format!("${:0>4X}", target))
+        };
+        let signed = || {
+            let e8 = self.imm as u8 as i8;
+            if e8 < 0 {
+                format!("-{}", -(e8 as i16))
+            } else {
+                format!("+{}", e8)
+            }
+        };
+
+        match self.instr {
+            // Block 0.
+            Instr::Nop => "nop".into(),
+            Instr::Ld_R16_Imm16 { dst } => format!("ld {}, {}", r16(dst), imm16()),
+            Instr::Ld_R16MemP_A { dst } => format!("ld {}, a", r16mem(dst)),
+            Instr::Ld_A_R16MemP { src } => format!("ld a, {}", r16mem(src)),
+            Instr::Ld_Imm16P_Sp => format!("ld [{}], sp", imm16()),
+            Instr::Inc_R16 { operand } => format!("inc {}", r16(operand)),
+            Instr::Dec_R16 { operand } => format!("dec {}", r16(operand)),
+            Instr::Add_Hl_R16 { operand } => format!("add hl, {}", r16(operand)),
+            Instr::Inc_R8 { operand } => format!("inc {}", r8(operand)),
+            Instr::Dec_R8 { operand } => format!("dec {}", r8(operand)),
+            Instr::Ld_R8_Imm8 { dst } => format!("ld {}, {}", r8(dst), imm8()),
+
+            Instr::Rlca => "rlca".into(),
+            Instr::RRca => "rrca".into(),
+            Instr::Rla => "rla".into(),
+            Instr::Rra => "rra".into(),
+            Instr::Daa => "daa".into(),
+            Instr::Cpl => "cpl".into(),
+            Instr::Scf => "scf".into(),
+            Instr::Ccf => "ccf".into(),
+
+            Instr::Jr_Imm8 => format!("jr {}", target()),
+            Instr::Jr_Cond_Imm8 { cond: c } => format!("jr {}, {}", cond(c), target()),
+            Instr::Stop => "stop".into(),
+
+            // Block 1.
+            Instr::Ld_R8_R8 { dst, src } => format!("ld {}, {}", r8(dst), r8(src)),
+            Instr::Halt => "halt".into(),
+
+            // Block 2.
+            Instr::Add_A_R8 { operand } => format!("add a, {}", r8(operand)),
+            Instr::Adc_A_R8 { operand } => format!("adc a, {}", r8(operand)),
+            Instr::Sub_A_R8 { operand } => format!("sub a, {}", r8(operand)),
+            Instr::Sbc_A_R8 { operand } => format!("sbc a, {}", r8(operand)),
+            Instr::And_A_R8 { operand } => format!("and a, {}", r8(operand)),
+            Instr::Xor_A_R8 { operand } => format!("xor a, {}", r8(operand)),
+            Instr::Or_A_R8 { operand } => format!("or a, {}", r8(operand)),
+            Instr::Cp_A_R8 { operand } => format!("cp a, {}", r8(operand)),
+
+            // Block 3.
+            Instr::Add_A_Imm8 => format!("add a, {}", imm8()),
+            Instr::Adc_A_Imm8 => format!("adc a, {}", imm8()),
+            Instr::Sub_A_Imm8 => format!("sub a, {}", imm8()),
+            Instr::Sbc_A_Imm8 => format!("sbc a, {}", imm8()),
+            Instr::And_A_Imm8 => format!("and a, {}", imm8()),
+            Instr::Xor_A_Imm8 => format!("xor a, {}", imm8()),
+            Instr::Or_A_Imm8 => format!("or a, {}", imm8()),
+            Instr::Cp_A_Imm8 => format!("cp a, {}", imm8()),
+
+            Instr::Ret_Cond { cond: c } => format!("ret {}", cond(c)),
+            Instr::Ret => "ret".into(),
+            Instr::Reti => "reti".into(),
+            Instr::Jp_Cond_Imm16 { cond: c } => format!("jp {}, {}", cond(c), target()),
+            Instr::Jp_Imm16 => format!("jp {}", target()),
+            Instr::Jp_Hl => "jp hl".into(),
+            Instr::Call_Cond_Imm16 { cond: c } => format!("call {}, {}", cond(c), target()),
+            Instr::Call_Imm16 => format!("call {}", target()),
+            Instr::Rst_Tgt3 { tgt3 } => format!("rst ${:0>2X}", tgt3 * 8),
+
+            Instr::Pop_R16Stk { reg } => format!("pop {}", r16stk(reg)),
+            Instr::Push_R16Stk { reg } => format!("push {}", r16stk(reg)),
+
+            Instr::Ldh_CP_A => "ldh [c], a".into(),
+            Instr::Ldh_Imm8P_A => format!("ldh [$FF{:0>2X}], a", self.imm),
+            Instr::Ld_Imm16P_A => format!("ld [{}], a", imm16()),
+            Instr::Ldh_A_CP => "ldh a, [c]".into(),
+            Instr::Ldh_A_Imm8P => format!("ldh a, [$FF{:0>2X}]", self.imm),
+            Instr::Ld_A_Imm16P => format!("ld a, [{}]", imm16()),
+
+            Instr::Add_Sp_Imm8 => format!("add sp, {}", signed().trim_start_matches('+')),
+            Instr::Ld_Hl_SpImm8 => format!("ld hl, sp{}", signed()),
+            Instr::Ld_Sp_Hl => "ld sp, hl".into(),
+
+            Instr::Di => "di".into(),
+            Instr::Ei => "ei".into(),
+
+            // 0xCB prefix.
+            Instr::Rlc_R8 { operand } => format!("rlc {}", r8(operand)),
+            Instr::Rrc_R8 { operand } => format!("rrc {}", r8(operand)),
+            Instr::Rl_R8 { operand } => format!("rl {}", r8(operand)),
+            Instr::Rr_R8 { operand } => format!("rr {}", r8(operand)),
+            Instr::Sla_R8 { operand } => format!("sla {}", r8(operand)),
+            Instr::Sra_R8 { operand } => format!("sra {}", r8(operand)),
+            Instr::Swap_R8 { operand } => format!("swap {}", r8(operand)),
+            Instr::Srl_R8 { operand } => format!("srl {}", r8(operand)),
+
+            Instr::Bit_B3_R8 { b3, operand } => format!("bit {}, {}", b3, r8(operand)),
+            Instr::Res_B3_R8 { b3, operand } => format!("res {}, {}", b3, r8(operand)),
+            Instr::Set_B3_R8 { b3, operand } => format!("set {}, {}", b3, r8(operand)),
+
+            // Misc.
+            Instr::Invalid(op) => format!("db ${:0>2X}", op),
+        }
+    }
+}
+
+fn r8(r: R8) -> &'static str {
+    match r {
+        R8::B => "b",
+        R8::C => "c",
+        R8::D => "d",
+        R8::E => "e",
+        R8::H => "h",
+        R8::L => "l",
+        R8::HlMem => "[hl]",
+        R8::A => "a",
+    }
+}
+
+fn r16(r: R16) -> &'static str {
+    match r {
+        R16::BC => "bc",
+        R16::DE => "de",
+        R16::HL => "hl",
+        R16::SP => "sp",
+    }
+}
+
+fn r16stk(r: R16Stk) -> &'static str {
+    match r {
+        R16Stk::BC => "bc",
+        R16Stk::DE => "de",
+        R16Stk::HL => "hl",
+        R16Stk::AF => "af",
+    }
+}
+
+fn r16mem(r: R16Mem) -> &'static str {
+    match r {
+        R16Mem::BC => "[bc]",
+        R16Mem::DE => "[de]",
+        R16Mem::HlInc => "[hl+]",
+        R16Mem::HlDec => "[hl-]",
+    }
+}
+
+fn cond(c: Cond) -> &'static str {
+    match c {
+        Cond::NZ => "nz",
+        Cond::Z => "z",
+        Cond::NC => "nc",
+        Cond::C => "c",
+    }
+}
+
+/// Locations where execution may start: the RST and interrupt vectors,
+/// and the cartridge entry point.
+const ENTRY_POINTS: [Addr; 14] = [
+    0x0000, 0x0008, 0x0010, 0x0018, 0x0020, 0x0028, 0x0030, 0x0038, // RST
+    0x0040, 0x0048, 0x0050, 0x0058, 0x0060, // Interrupts
+    0x0100, // Entry
+];
+
+/// The cartridge header, which is always data.
+const HEADER_START: Addr = 0x0104;
+const HEADER_END: Addr = 0x0150;
+
+/// Maximum number of bytes on a single `db` line.
+const DB_LINE_LEN: usize = 8;
+
+/// A ROM image split into 16KB banks.
+struct BankedRom<'a> {
+    rom: &'a [u8],
+}
+
+impl BankedRom<'_> {
+    fn bank_count(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    /// Returns the CPU address range that `bank` is mapped to.
+    fn bank_range(&self, bank: usize) -> std::ops::Range<usize> {
+        let start = if bank == 0 { 0x0000 } else { 0x4000 };
+        let len = usize::min(ROM_BANK_SIZE, self.rom.len() - bank * ROM_BANK_SIZE);
+
+        start..(start + len)
+    }
+
+    /// Reads a byte as the CPU would see it while `bank` is mapped.
+    fn read(&self, bank: usize, addr: Addr) -> u8 {
+        let loc = BankAddr {
+            bank: if addr < 0x4000 { 0 } else { bank },
+            addr,
+        };
+        *self.rom.get(loc.rom_offset()).unwrap_or(&0)
+    }
+
+    /// Resolves a jump target from code in `bank` to a location in the ROM.
+    /// Bank 0 code is assumed to see bank 1 in the switchable area, since the
+    /// selected bank isn't known statically.
+    fn resolve(&self, bank: usize, target: Addr) -> Option<BankAddr> {
+        let bank = match target {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => usize::max(bank, 1),
+            _ => return None,
+        };
+
+        (bank < self.bank_count()).then_some(BankAddr { bank, addr: target })
+    }
+
+    /// Returns true if the decoded instruction fits inside the bank.
+    fn fits(&self, bank: usize, addr: Addr, decoded: &DecodedInstr) -> bool {
+        addr as usize + decoded.len as usize <= self.bank_range(bank).end
+    }
+}
+
+/// Disassembles an entire ROM image into RGBDS syntax text.
+pub fn disassemble_rom(rom: &[u8], mode: DisasmMode) -> String {
+    let rom = BankedRom { rom };
+
+    let code_starts = match mode {
+        DisasmMode::Linear => find_code_linear(&rom),
+        DisasmMode::Recursive => find_code_recursive(&rom),
+    };
+
+    // Label every jump target that lands on an instruction.
+    let mut labels = HashSet::new();
+    for loc in &code_starts {
+        let decoded = DecodedInstr::read(|addr| rom.read(loc.bank, addr), loc.addr);
+        if let Some(target) = decoded.branch_target(loc.addr) {
+            if let Some(target) = rom.resolve(loc.bank, target) {
+                if code_starts.contains(&target) {
+                    labels.insert(target);
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for bank in 0..rom.bank_count() {
+        write_bank(&mut out, &rom, bank, &code_starts, &labels);
+    }
+
+    out
+}
+
+/// Treats every location outside the header as the start of an instruction,
+/// one after the other.
+fn find_code_linear(rom: &BankedRom) -> BTreeSet<BankAddr> {
+    let mut code_starts = BTreeSet::new();
+    for bank in 0..rom.bank_count() {
+        let range = rom.bank_range(bank);
+        let mut addr = range.start as Addr;
+        while (addr as usize) < range.end {
+            if bank == 0 && (HEADER_START..HEADER_END).contains(&addr) {
+                addr = HEADER_END;
+                continue;
+            }
+
+            let decoded = DecodedInstr::read(|addr| rom.read(bank, addr), addr);
+            if !rom.fits(bank, addr, &decoded) {
+                break;
+            }
+            code_starts.insert(BankAddr { bank, addr });
+            addr += decoded.len;
+        }
+    }
+
+    code_starts
+}
+
+/// Follows the control flow from the entry points to find every reachable
+/// instruction.
+fn find_code_recursive(rom: &BankedRom) -> BTreeSet<BankAddr> {
+    let mut code_starts = BTreeSet::new();
+    let mut pending: Vec<BankAddr> = ENTRY_POINTS
+        .iter()
+        .filter_map(|&addr| rom.resolve(0, addr))
+        .collect();
+
+    while let Some(loc) = pending.pop() {
+        let in_header = loc.bank == 0 && (HEADER_START..HEADER_END).contains(&loc.addr);
+        if in_header || code_starts.contains(&loc) {
+            continue;
+        }
+
+        let decoded = DecodedInstr::read(|addr| rom.read(loc.bank, addr), loc.addr);
+        if !rom.fits(loc.bank, loc.addr, &decoded) {
+            continue;
+        }
+        code_starts.insert(loc);
+
+        if let Some(target) = decoded.branch_target(loc.addr) {
+            if let Some(target) = rom.resolve(loc.bank, target) {
+                pending.push(target);
+            }
+        }
+        if !decoded.ends_flow() {
+            pending.push(BankAddr {
+                bank: loc.bank,
+                addr: loc.addr + decoded.len,
+            });
+        }
+    }
+
+    // Drop instructions that overlap an earlier one (e.g. a jump into the
+    // middle of an instruction), so that every byte is only emitted once.
+    let mut code_end = BankAddr { bank: 0, addr: 0 };
+    code_starts.retain(|loc| {
+        if loc.bank == code_end.bank && loc.addr < code_end.addr {
+            return false;
+        }
+        let decoded = DecodedInstr::read(|addr| rom.read(loc.bank, addr), loc.addr);
+        code_end = BankAddr {
+            bank: loc.bank,
+            addr: loc.addr + decoded.len,
+        };
+        true
+    });
+
+    code_starts
+}
+
+fn write_bank(
+    out: &mut String,
+    rom: &BankedRom,
+    bank: usize,
+    code_starts: &BTreeSet<BankAddr>,
+    labels: &HashSet<BankAddr>,
+) {
+    use std::fmt::Write;
+
+    if bank == 0 {
+        writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+    } else {
+        writeln!(
+            out,
+            "\nSECTION \"ROM Bank ${:0>3X}\", ROMX[$4000], BANK[${:X}]",
+            bank, bank
+        )
+        .unwrap();
+    }
+
+    let label_of = |target: Addr| {
+        rom.resolve(bank, target)
+            .filter(|target| labels.contains(target))
+            .map(|target| target.label())
+    };
+
+    let range = rom.bank_range(bank);
+    let mut addr = range.start as Addr;
+    let mut data: Vec<u8> = vec![];
+    let mut data_start = addr;
+    while (addr as usize) < range.end {
+        let loc = BankAddr { bank, addr };
+        let is_code = code_starts.contains(&loc);
+        let is_label = labels.contains(&loc);
+
+        if !data.is_empty() && (is_code || is_label || data.len() == DB_LINE_LEN) {
+            write_data(out, bank, data_start, &data);
+            data.clear();
+        }
+
+        if is_label {
+            writeln!(out, "{}:", loc.label()).unwrap();
+        }
+
+        if is_code {
+            let decoded = DecodedInstr::read(|addr| rom.read(bank, addr), addr);
+            let text = decoded.format(addr, label_of);
+            writeln!(out, "    {:<24} ; {}", text, loc).unwrap();
+            addr += decoded.len;
+        } else {
+            if data.is_empty() {
+                data_start = addr;
+            }
+            data.push(rom.read(bank, addr));
+            addr += 1;
+        }
+    }
+
+    if !data.is_empty() {
+        write_data(out, bank, data_start, &data);
+    }
+}
+
+fn write_data(out: &mut String, bank: usize, addr: Addr, data: &[u8]) {
+    use std::fmt::Write;
+
+    let bytes: Vec<String> = data.iter().map(|b| format!("${:0>2X}", b)).collect();
+    let text = format!("db {}", bytes.join(", "));
+    writeln!(out, "    {:<24} ; {}", text, BankAddr { bank, addr }).unwrap();
+}
+
+/// Disassembles the code around `pc` as seen through `read`. Returns up to
+/// `before` instructions preceding `pc`, the instruction at `pc`, and up to
+/// `after` instructions following it.
+pub fn disassemble_around(
+    read: impl Fn(Addr) -> u8,
+    pc: Addr,
+    before: usize,
+    after: usize,
+) -> Vec<(Addr, String)> {
+    // Instructions are variable length, so find an earlier address that
+    // decodes forward in step with `pc`. The furthest one gives the most
+    // context.
+    const MAX_INSTR_LEN: usize = 3;
+    let max_back = (before * MAX_INSTR_LEN) as u16;
+
+    let mut preceding = vec![];
+    for back in (1..=max_back).rev() {
+        let Some(start) = pc.checked_sub(back) else {
+            continue;
+        };
+
+        let mut addrs = vec![];
+        let mut addr = Some(start);
+        while let Some(curr) = addr.filter(|&addr| addr < pc) {
+            addrs.push(curr);
+            addr = curr.checked_add(DecodedInstr::read(&read, curr).len);
+        }
+
+        if addr == Some(pc) {
+            preceding = addrs;
+            break;
+        }
+    }
+
+    let skip = preceding.len().saturating_sub(before);
+    let mut addrs: Vec<Addr> = preceding.into_iter().skip(skip).collect();
+    // Stop at the end of the address space rather than wrap around.
+    let mut addr = Some(pc);
+    for _ in 0..=after {
+        let Some(curr) = addr else {
+            break;
+        };
+        addrs.push(curr);
+        addr = curr.checked_add(DecodedInstr::read(&read, curr).len);
+    }
+
+    addrs
+        .into_iter()
+        .map(|addr| {
+            let decoded = DecodedInstr::read(&read, addr);
+            (addr, decoded.format(addr, |_| None))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bytes(bytes: &[u8]) -> String {
+        let read = |addr: Addr| *bytes.get(addr as usize).unwrap_or(&0);
+        DecodedInstr::read(read, 0).format(0, |_| None)
+    }
+
+    #[test]
+    fn test_format_instrs() {
+        assert_eq!(decode_bytes(&[0x00]), "nop");
+        assert_eq!(decode_bytes(&[0x01, 0x34, 0x12]), "ld bc, $1234");
+        assert_eq!(decode_bytes(&[0x22]), "ld [hl+], a");
+        assert_eq!(decode_bytes(&[0x18, 0xFE]), "jr $0000");
+        assert_eq!(decode_bytes(&[0x20, 0x02]), "jr nz, $0004");
+        assert_eq!(decode_bytes(&[0x7E]), "ld a, [hl]");
+        assert_eq!(decode_bytes(&[0xE0, 0x40]), "ldh [$FF40], a");
+        assert_eq!(decode_bytes(&[0xE8, 0xFD]), "add sp, -3");
+        assert_eq!(decode_bytes(&[0xF8, 0x05]), "ld hl, sp+5");
+        assert_eq!(decode_bytes(&[0xFF]), "rst $38");
+        assert_eq!(decode_bytes(&[0xCB, 0x7C]), "bit 7, h");
+        assert_eq!(decode_bytes(&[0xD3]), "db $D3");
+        assert_eq!(decode_bytes(&[0x10, 0x00]), "stop");
+    }
+
+    #[test]
+    fn test_recursive_skips_data() {
+        let mut rom = vec![0xFF; 2 * ROM_BANK_SIZE];
+        // 0x0000: jp $0100
+        rom[0x0000..0x0003].copy_from_slice(&[0xC3, 0x00, 0x01]);
+        // 0x0100: jp $0150
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        // 0x0150: call $4000, then loop forever.
+        rom[0x0150..0x0155].copy_from_slice(&[0xCD, 0x00, 0x40, 0x18, 0xFE]);
+        // 0x4000 (bank 1): ret
+        rom[ROM_BANK_SIZE] = 0xC9;
+
+        let text = disassemble_rom(&rom, DisasmMode::Recursive);
+
+        assert!(text.contains("jp L00_0100"));
+        assert!(text.contains("L00_0150:\n    call L01_4000"));
+        assert!(text.contains("jr L00_0153"));
+        assert!(text.contains("L01_4000:\n    ret"));
+        assert!(text.contains("ROMX[$4000], BANK[$1]"));
+        // Unreachable bytes are data.
+        assert!(text.contains("db $FF, $FF"));
+    }
+
+    #[test]
+    fn test_around_pc() {
+        let bytes = [0x00, 0x3E, 0x12, 0xCB, 0x37, 0x00, 0x00];
+        let read = |addr: Addr| *bytes.get(addr as usize).unwrap_or(&0);
+
+        let lines = disassemble_around(read, 3, 2, 1);
+        let addrs: Vec<Addr> = lines.iter().map(|(addr, _)| *addr).collect();
+
+        assert_eq!(addrs, vec![0, 1, 3, 5]);
+        assert_eq!(lines[2].1, "swap a");
+    }
+
+    #[test]
+    fn test_around_pc_skips_stop_padding() {
+        let bytes = [0x00, 0x10, 0x00, 0x00];
+        let read = |addr: Addr| *bytes.get(addr as usize).unwrap_or(&0);
+
+        let lines = disassemble_around(read, 1, 1, 1);
+        let addrs: Vec<Addr> = lines.iter().map(|(addr, _)| *addr).collect();
+
+        assert_eq!(addrs, vec![0, 1, 3]);
+        assert_eq!(lines[1].1, "stop");
+    }
+
+    #[test]
+    fn test_around_pc_stops_at_end_of_memory() {
+        // ld bc, $0101 everywhere.
+        let read = |_| 0x01;
+
+        let lines = disassemble_around(read, 0xFFFD, 3, 3);
+        let addrs: Vec<Addr> = lines.iter().map(|(addr, _)| *addr).collect();
+
+        assert_eq!(addrs, vec![0xFFF4, 0xFFF7, 0xFFFA, 0xFFFD]);
+    }
+}
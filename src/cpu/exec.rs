@@ -1,15 +1,16 @@
 use std::mem::transmute;
 
 use crate::{
-    debug::{self, debug_state},
+    debug,
     mem::Addr,
+    ppu::oam_bug::{trigger_oam_bug, OamBugAccess},
     sys::Sys,
     util::math::{add16_ui, add16_uu, bit8, bits8, join_16, set_bit8, split_16},
 };
 
 use super::{
     exec_math::{add_2_u8, add_3_u8, add_sp_i8, sub_2_u8, sub_3_u8},
-    instr::{decode, Cond, Instr, R16Mem, R16Stk, R16, R8},
+    instr::{lookup, Cond, Instr, R16Mem, R16Stk, R16, R8},
     regs::{CpuFlag, CpuReg16, CpuReg8},
 };
 
@@ -17,28 +18,22 @@ use super::{
 /// Returns the number of machine cycles needed to execute
 /// the instruction.
 pub fn execute_next_instr(sys: &mut Sys) -> u32 {
-    debug::record_curr_instr(sys);
-
     let mut pc = sys.regs.pc();
-    let mut op = sys.mem.read(pc);
-    let has_cb_prefix;
+    let mut op = sys.read(pc);
+    let has_cb_prefix = op == Instr::CB_PREFIX;
 
-    if op == Instr::CB_PREFIX {
+    if has_cb_prefix {
         pc += 1;
-        op = sys.mem.read(pc);
-        has_cb_prefix = true;
-    } else {
-        has_cb_prefix = false;
+        op = sys.read(pc);
+    }
+    let instr = lookup(op, has_cb_prefix);
+
+    sys.debug.count_nops(instr);
+    if sys.debug.is_recording_instrs() {
+        debug::record_curr_instr(sys, instr);
     }
-    let instr = match decode(op, has_cb_prefix) {
-        Ok(instr) => instr,
-        Err(msg) => {
-            debug::fail(msg);
-            return 1;
-        }
-    };
 
-    if debug_state().config.enable_debug_print {
+    if sys.debug.is_debug_print_enabled() {
         println!("[{:#02x}] {:?}", pc, instr);
     }
 
@@ -143,11 +138,6 @@ pub fn execute_next_instr(sys: &mut Sys) -> u32 {
 
     //print_if_ld_a_a(sys, instr);
 
-    if debug::debug_state().request_print_last_instr > 0 {
-        //debug::print_last_instr();
-        debug::debug_state().request_print_last_instr -= 1;
-    }
-
     cycles as u32
 }
 
@@ -179,10 +169,10 @@ fn dec_sp(sys: &mut Sys) {
 }
 
 fn take_imm_u8(sys: &mut Sys) -> u8 {
-    let imm8 = sys.mem.read(sys.regs.pc());
+    let imm8 = sys.read(sys.regs.pc());
     inc_pc(sys);
 
-    if debug_state().config.enable_debug_print {
+    if sys.debug.is_debug_print_enabled() {
         println!("  imm8: {:0>2X} ({})", imm8, imm8);
     }
 
@@ -195,14 +185,14 @@ fn take_imm_i8(sys: &mut Sys) -> i8 {
 }
 
 fn take_imm_u16(sys: &mut Sys) -> u16 {
-    let lo = sys.mem.read(sys.regs.pc());
+    let lo = sys.read(sys.regs.pc());
     inc_pc(sys);
-    let hi = sys.mem.read(sys.regs.pc());
+    let hi = sys.read(sys.regs.pc());
     inc_pc(sys);
 
     let imm16 = join_16(hi, lo);
 
-    if debug_state().config.enable_debug_print {
+    if sys.debug.is_debug_print_enabled() {
         println!("  imm16: {:0>4X} ({})", imm16, imm16);
     }
 
@@ -226,7 +216,8 @@ fn get_r8_data(sys: &mut Sys, operand: R8) -> u8 {
         sys.regs.get_8(reg)
     } else {
         let addr = sys.regs.get_16(CpuReg16::HL);
-        sys.mem.read(addr)
+        trigger_oam_bug(sys, addr, OamBugAccess::Read);
+        sys.read(addr)
     }
 }
 
@@ -235,25 +226,32 @@ fn set_r8_data(sys: &mut Sys, operand: R8, data: u8) {
         sys.regs.set_8(reg, data);
     } else {
         let addr = sys.regs.get_16(CpuReg16::HL);
-        sys.mem.write(addr, data);
+        trigger_oam_bug(sys, addr, OamBugAccess::Write);
+        sys.write(addr, data);
     }
 }
 
 fn push_16(sys: &mut Sys, data: u16) {
     let (hi, lo) = split_16(data);
 
+    // SP is decremented once on its own, then once along with each write.
+    trigger_oam_bug(sys, sys.regs.sp(), OamBugAccess::Write);
     dec_sp(sys);
-    sys.mem.write(sys.regs.sp(), hi);
+    trigger_oam_bug(sys, sys.regs.sp(), OamBugAccess::Write);
+    sys.write(sys.regs.sp(), hi);
 
     dec_sp(sys);
-    sys.mem.write(sys.regs.sp(), lo);
+    trigger_oam_bug(sys, sys.regs.sp(), OamBugAccess::Write);
+    sys.write(sys.regs.sp(), lo);
 }
 
 fn pop_16(sys: &mut Sys) -> u16 {
-    let lo = sys.mem.read(sys.regs.sp());
+    trigger_oam_bug(sys, sys.regs.sp(), OamBugAccess::ReadIncDec);
+    let lo = sys.read(sys.regs.sp());
     inc_sp(sys);
 
-    let hi = sys.mem.read(sys.regs.sp());
+    trigger_oam_bug(sys, sys.regs.sp(), OamBugAccess::ReadIncDec);
+    let hi = sys.read(sys.regs.sp());
     inc_sp(sys);
 
     join_16(hi, lo)
@@ -282,7 +280,9 @@ fn ld_r16memp_a(sys: &mut Sys, dst: R16Mem) -> u8 {
     let (dstp, inc) = dst.get_reg_inc();
 
     let addr = sys.regs.get_16(dstp);
-    sys.mem.write(addr, data);
+    // The write and the increment happen together and corrupt OAM once.
+    trigger_oam_bug(sys, addr, OamBugAccess::Write);
+    sys.write(addr, data);
     sys.regs.set_16(dstp, add16_ui(addr, inc));
 
     2
@@ -292,7 +292,12 @@ fn ld_a_r16memp(sys: &mut Sys, src: R16Mem) -> u8 {
     let (srcp, inc) = src.get_reg_inc();
 
     let addr = sys.regs.get_16(srcp);
-    let data = sys.mem.read(addr);
+    let access = match inc {
+        0 => OamBugAccess::Read,
+        _ => OamBugAccess::ReadIncDec,
+    };
+    trigger_oam_bug(sys, addr, access);
+    let data = sys.read(addr);
     sys.regs.set_16(srcp, add16_ui(addr, inc));
 
     sys.regs.set_8(CpuReg8::A, data);
@@ -304,14 +309,15 @@ fn ld_imm16_sp(sys: &mut Sys) -> u8 {
     let addr = take_imm_u16(sys);
     let sp_data = sys.regs.get_16(CpuReg16::SP);
     let (hi, lo) = split_16(sp_data);
-    sys.mem.write(addr, lo);
-    sys.mem.write(addr + 1, hi);
+    sys.write(addr, lo);
+    sys.write(addr + 1, hi);
 
     5
 }
 
 fn inc_dec_r16(sys: &mut Sys, operand: R16, inc: i16) -> u8 {
     let mut data = sys.regs.get_16(operand.get_reg());
+    trigger_oam_bug(sys, data, OamBugAccess::Write);
     data = add16_ui(data, inc);
     sys.regs.set_16(operand.get_reg(), data);
 
@@ -494,10 +500,6 @@ fn ccf(sys: &mut Sys) -> u8 {
 
 fn jr_imm8(sys: &mut Sys) -> u8 {
     let rel = take_imm_i8(sys);
-    if (rel == -2) && sys.options.kill_on_infinite_loop {
-        //debug::fail("Ininite loop.");
-        sys.hard_lock = true;
-    }
     let mut pc = sys.regs.pc();
 
     pc = add16_ui(pc, rel as i16);
@@ -522,8 +524,9 @@ fn jr_cond_imm8(sys: &mut Sys, cond: Cond) -> u8 {
     }
 }
 
-fn stop(_: &mut Sys) -> u8 {
+fn stop(sys: &mut Sys) -> u8 {
     //sys.cpu_enable = false;
+    sys.mem.io_regs.div_reset_requested = true;
 
     1
 }
@@ -534,7 +537,7 @@ fn ld_r8_r8(sys: &mut Sys, dst: R8, src: R8) -> u8 {
     set_r8_data(sys, dst, data);
 
     if dst == R8::B && src == R8::B {
-        debug::request_breakpoint();
+        sys.debug.request_breakpoint();
     }
 
     if dst == R8::HlMem || src == R8::HlMem {
@@ -926,7 +929,7 @@ fn ldh_cp_a(sys: &mut Sys) -> u8 {
     let c_data = sys.regs.get_8(CpuReg8::C);
     let addr = join_16(0xFF, c_data);
 
-    sys.mem.write(addr, a_data);
+    sys.write(addr, a_data);
 
     2
 }
@@ -936,7 +939,7 @@ fn ldh_imm8p_a(sys: &mut Sys) -> u8 {
     let a_data = sys.regs.get_8(CpuReg8::A);
     let addr = join_16(0xFF, offset);
 
-    sys.mem.write(addr, a_data);
+    sys.write(addr, a_data);
 
     3
 }
@@ -946,7 +949,7 @@ fn ld_imm16p_a(sys: &mut Sys) -> u8 {
     let data = sys.regs.get_8(CpuReg8::A);
     let addr = imm16;
 
-    sys.mem.write(addr, data);
+    sys.write(addr, data);
 
     4
 }
@@ -954,7 +957,7 @@ fn ld_imm16p_a(sys: &mut Sys) -> u8 {
 fn ldh_a_cp(sys: &mut Sys) -> u8 {
     let c_data = sys.regs.get_8(CpuReg8::C);
     let addr = join_16(0xFF, c_data);
-    let data = sys.mem.read(addr);
+    let data = sys.read(addr);
 
     sys.regs.set_8(CpuReg8::A, data);
 
@@ -964,7 +967,7 @@ fn ldh_a_cp(sys: &mut Sys) -> u8 {
 fn ldh_a_imm8p(sys: &mut Sys) -> u8 {
     let imm8 = take_imm_u8(sys);
     let addr = join_16(0xFF, imm8);
-    let data = sys.mem.read(addr);
+    let data = sys.read(addr);
 
     sys.regs.set_8(CpuReg8::A, data);
 
@@ -973,7 +976,7 @@ fn ldh_a_imm8p(sys: &mut Sys) -> u8 {
 
 fn ld_a_imm16p(sys: &mut Sys) -> u8 {
     let addr = take_imm_u16(sys);
-    let data = sys.mem.read(addr);
+    let data = sys.read(addr);
 
     sys.regs.set_8(CpuReg8::A, data);
 
@@ -1198,6 +1201,7 @@ fn set_b3_r8(sys: &mut Sys, b3: u8, operand: R8) -> u8 {
 // Misc functions.
 fn hard_lock(sys: &mut Sys, opcode: u8) -> u8 {
     sys.hard_lock = true;
-    debug::fail(format!("Invalid instr occurred ({:0>2X}).", opcode));
+    sys.debug
+        .fail(format!("Invalid instr occurred ({:0>2X}).", opcode));
     1
 }
@@ -0,0 +1,545 @@
+use crate::{
+    cpu::{
+        disasm::DecodedInstr,
+        exec::execute_next_instr,
+        instr::{Instr, R8},
+        regs::{CpuReg16, CpuReg8, CpuRegs},
+    },
+    mem::{io_regs::IoReg, Addr},
+    other::joypad::is_joypad_idle,
+    ppu::ppu::{ppu_idle_m_cycles, skip_ppu_m_cycles},
+    serial::serial::{serial_idle_m_cycles, skip_serial_m_cycles},
+    sys::Sys,
+    time::timers::{skip_timer_m_cycles, timer_idle_m_cycles},
+};
+
+/// The longest backward jump that is considered as a possible idle loop.
+const MAX_LOOP_BYTES: u16 = 16;
+/// The most instructions an idle loop iteration can have.
+const MAX_LOOP_INSTRS: usize = 8;
+/// How many times a loop that didn't qualify is passed over before it is
+/// recorded again.
+const REJECTED_LOOP_SKIPS: u8 = 16;
+
+/// One instruction of a recorded idle loop iteration.
+#[derive(Clone, Debug)]
+struct LoopStep {
+    regs_before: CpuRegs,
+    /// The data address the instruction reads and the value it got.
+    read: Option<(Addr, u8)>,
+    regs_after: CpuRegs,
+    cycles: u32,
+}
+
+#[derive(Default, Clone, Debug)]
+enum IdleState {
+    /// Waiting for a short backward jump.
+    #[default]
+    Searching,
+    /// Executing the loop once, starting from `head`.
+    Recording { head: Addr, steps: Vec<LoopStep> },
+    /// Replaying the recorded loop; `next` is the step to replay next.
+    Replaying { steps: Vec<LoopStep>, next: usize },
+}
+
+/// Detects loops that only poll memory (e.g. `jr @`, or waiting on LY or
+/// IF) and fast-forwards through them.
+///
+/// A loop qualifies if it is at most `MAX_LOOP_INSTRS` instructions and
+/// `MAX_LOOP_BYTES` bytes long, its instructions only read memory and change
+/// CPU registers, and one iteration brings the registers back to the state
+/// they had at the loop head. Every iteration is then identical as long as
+/// the polled addresses read the same values.
+///
+/// At the loop head, the emulator jumps ahead by as many whole iterations
+/// as fit before the next event: the PPU changing mode or line, TIMA
+/// overflowing, a serial transfer, a joypad change or a DMA transfer.
+/// Nothing the loop reads or IF can change before then, so no interrupt
+/// can be taken either. Loops that read DIV or TIMA, which change all the
+/// time, are not jumped over. Otherwise, the recorded register state is
+/// applied instruction by instruction, with the reads checked at the
+/// M-cycle they would have been made. The loop is left on the first read
+/// that differs (or when an interrupt moves PC), at which point the
+/// instruction is executed normally.
+#[derive(Default, Clone, Debug)]
+pub struct IdleLoop {
+    state: IdleState,
+    /// Head of the last loop that was recorded but didn't qualify, and how
+    /// many more times it is passed over before being tried again. A polling
+    /// loop can fail once, when the value it waits for changes while it is
+    /// being recorded.
+    rejected: Option<(Addr, u8)>,
+}
+
+impl IdleLoop {
+    /// Returns true if the CPU is currently fast-forwarding through a loop.
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.state, IdleState::Replaying { .. })
+    }
+}
+
+/// Executes the next instruction, or replays it if the CPU is in an idle
+/// loop. Returns the number of M-cycles it takes.
+pub fn execute_next_instr_or_replay(sys: &mut Sys) -> u32 {
+    // Keep instruction traces complete when debugging.
+    if !sys.options.skip_idle_loops
+        || sys.debug.is_recording_instrs()
+        || sys.debug.is_debug_print_enabled()
+    {
+        return execute_next_instr(sys);
+    }
+
+    let mut idle = std::mem::take(&mut sys.idle_loop);
+    let cycles = match try_replay(sys, &mut idle) {
+        Some(cycles) => cycles,
+        None => execute_and_observe(sys, &mut idle),
+    };
+    sys.idle_loop = idle;
+
+    cycles
+}
+
+fn try_replay(sys: &mut Sys, idle: &mut IdleLoop) -> Option<u32> {
+    let IdleState::Replaying { steps, next } = &mut idle.state else {
+        return None;
+    };
+    if *next == 0 {
+        skip_iterations(sys, steps);
+    }
+
+    let step = &steps[*next];
+    let reads_match = match step.read {
+        Some((addr, data)) => sys.read(addr) == data,
+        None => true,
+    };
+    if sys.regs != step.regs_before || !reads_match {
+        idle.state = IdleState::Searching;
+        return None;
+    }
+
+    sys.regs = step.regs_after;
+    let cycles = step.cycles;
+    *next = (*next + 1) % steps.len();
+
+    Some(cycles)
+}
+
+/// Jumps ahead by as many whole iterations of the loop as can be done before
+/// the next event. The CPU must be at the loop head.
+fn skip_iterations(sys: &mut Sys, steps: &[LoopStep]) {
+    if sys.regs != steps[0].regs_before {
+        return;
+    }
+    for step in steps {
+        let Some((addr, data)) = step.read else {
+            continue;
+        };
+        let is_timer_reg = addr == IoReg::Div as Addr || addr == IoReg::Tima as Addr;
+        if is_timer_reg || sys.read(addr) != data {
+            return;
+        }
+    }
+    if !is_joypad_idle(sys) {
+        return;
+    }
+
+    let idle_m_cycles = ppu_idle_m_cycles(sys)
+        .min(timer_idle_m_cycles(sys))
+        .min(serial_idle_m_cycles(sys));
+    let loop_m_cycles: u32 = steps.iter().map(|step| step.cycles).sum();
+    let m_cycles = idle_m_cycles - idle_m_cycles % loop_m_cycles;
+    if m_cycles == 0 {
+        return;
+    }
+
+    // This is where the CPU will be after that many iterations. The
+    // registers are already in that state.
+    sys.cpu_clock.skip(m_cycles);
+    skip_ppu_m_cycles(sys, m_cycles);
+    skip_timer_m_cycles(sys, m_cycles);
+    skip_serial_m_cycles(sys, m_cycles);
+}
+
+fn execute_and_observe(sys: &mut Sys, idle: &mut IdleLoop) -> u32 {
+    let regs_before = sys.regs;
+    let pc = regs_before.pc();
+
+    // Work out what the instruction reads before executing it, since reads
+    // have no side effects the value is the same one it will get.
+    let read = match idle.state {
+        IdleState::Recording { .. } => {
+            let decoded = DecodedInstr::read(|addr| sys.read(addr), pc);
+            match pure_read_addr(decoded.instr, decoded.imm, &regs_before) {
+                Some(addr) => addr.map(|addr| (addr, sys.read(addr))),
+                None => {
+                    idle.state = IdleState::Searching;
+                    return execute_next_instr(sys);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let cycles = execute_next_instr(sys);
+    let next_pc = sys.regs.pc();
+
+    match &mut idle.state {
+        IdleState::Searching => {
+            let is_short_backward_jump = next_pc <= pc && pc - next_pc <= MAX_LOOP_BYTES;
+            if !is_short_backward_jump || pc >= 0x8000 {
+                return cycles;
+            }
+            match &mut idle.rejected {
+                Some((head, skips_left)) if *head == next_pc && *skips_left > 0 => {
+                    *skips_left -= 1;
+                }
+                _ => {
+                    idle.state = IdleState::Recording {
+                        head: next_pc,
+                        steps: Vec::new(),
+                    };
+                }
+            }
+        }
+        IdleState::Recording { head, steps } => {
+            steps.push(LoopStep {
+                regs_before,
+                read,
+                regs_after: sys.regs,
+                cycles,
+            });
+
+            if next_pc == *head {
+                if steps[0].regs_before == sys.regs {
+                    let steps = std::mem::take(steps);
+                    check_dead_loop(sys, &steps);
+                    idle.state = IdleState::Replaying { steps, next: 0 };
+                } else {
+                    idle.rejected = Some((*head, REJECTED_LOOP_SKIPS));
+                    idle.state = IdleState::Searching;
+                }
+            } else if steps.len() >= MAX_LOOP_INSTRS {
+                idle.rejected = Some((*head, REJECTED_LOOP_SKIPS));
+                idle.state = IdleState::Searching;
+            }
+        }
+        IdleState::Replaying { .. } => unreachable!(),
+    }
+
+    cycles
+}
+
+/// Stops the emulator if the loop can never be left: it reads nothing, and
+/// no interrupt can be taken.
+fn check_dead_loop(sys: &mut Sys, steps: &[LoopStep]) {
+    if !sys.options.kill_on_dead_loop {
+        return;
+    }
+
+    let polls_memory = steps.iter().any(|step| step.read.is_some());
+    let ie = sys.mem.io_regs.get(IoReg::Ie);
+    if !polls_memory && (!sys.interrupt_master_enable || ie == 0) {
+        sys.hard_lock = true;
+    }
+}
+
+/// Returns the data address `instr` reads (if any), or None if the
+/// instruction does anything other than reading memory and changing CPU
+/// registers.
+fn pure_read_addr(instr: Instr, imm: u16, regs: &CpuRegs) -> Option<Option<Addr>> {
+    let hl = regs.get_16(CpuReg16::HL);
+    let r8_read = |operand: R8| (operand == R8::HlMem).then_some(hl);
+    let r8_write = |operand: R8| (operand != R8::HlMem).then_some(None);
+
+    let addr = match instr {
+        Instr::Nop
+        | Instr::Ld_R16_Imm16 { .. }
+        | Instr::Add_Hl_R16 { .. }
+        | Instr::Rlca
+        | Instr::RRca
+        | Instr::Rla
+        | Instr::Rra
+        | Instr::Daa
+        | Instr::Cpl
+        | Instr::Scf
+        | Instr::Ccf
+        | Instr::Jr_Imm8
+        | Instr::Jr_Cond_Imm8 { .. }
+        | Instr::Jp_Imm16
+        | Instr::Jp_Cond_Imm16 { .. }
+        | Instr::Jp_Hl
+        | Instr::Add_A_Imm8
+        | Instr::Adc_A_Imm8
+        | Instr::Sub_A_Imm8
+        | Instr::Sbc_A_Imm8
+        | Instr::And_A_Imm8
+        | Instr::Xor_A_Imm8
+        | Instr::Or_A_Imm8
+        | Instr::Cp_A_Imm8
+        | Instr::Add_Sp_Imm8
+        | Instr::Ld_Hl_SpImm8
+        | Instr::Ld_Sp_Hl => None,
+
+        // Replaying wouldn't trigger the OAM bug.
+        Instr::Inc_R16 { operand } | Instr::Dec_R16 { operand } => {
+            if is_oam_bug_addr(regs.get_16(operand.get_reg())) {
+                return None;
+            }
+            None
+        }
+
+        Instr::Ld_A_R16MemP { src } => Some(regs.get_16(src.get_reg_inc().0)),
+        Instr::Ldh_A_CP => Some(0xFF00 | regs.get_8(CpuReg8::C) as u16),
+        Instr::Ldh_A_Imm8P => Some(0xFF00 | (imm & 0xFF)),
+        Instr::Ld_A_Imm16P => Some(imm),
+
+        Instr::Inc_R8 { operand }
+        | Instr::Dec_R8 { operand }
+        | Instr::Ld_R8_Imm8 { dst: operand }
+        | Instr::Rlc_R8 { operand }
+        | Instr::Rrc_R8 { operand }
+        | Instr::Rl_R8 { operand }
+        | Instr::Rr_R8 { operand }
+        | Instr::Sla_R8 { operand }
+        | Instr::Sra_R8 { operand }
+        | Instr::Swap_R8 { operand }
+        | Instr::Srl_R8 { operand }
+        | Instr::Res_B3_R8 { operand, .. }
+        | Instr::Set_B3_R8 { operand, .. } => r8_write(operand)?,
+
+        Instr::Ld_R8_R8 { dst, src } => {
+            r8_write(dst)?;
+            r8_read(src)
+        }
+
+        Instr::Add_A_R8 { operand }
+        | Instr::Adc_A_R8 { operand }
+        | Instr::Sub_A_R8 { operand }
+        | Instr::Sbc_A_R8 { operand }
+        | Instr::And_A_R8 { operand }
+        | Instr::Xor_A_R8 { operand }
+        | Instr::Or_A_R8 { operand }
+        | Instr::Cp_A_R8 { operand }
+        | Instr::Bit_B3_R8 { operand, .. } => r8_read(operand),
+
+        // Writes, stack accesses and control flow changes.
+        _ => return None,
+    };
+
+    if addr.is_some_and(is_oam_bug_addr) {
+        return None;
+    }
+    // Reads from Echo RAM and the unusable region are reported as bad
+    // accesses when `FAIL_ON_BAD_RW` is set, so leave those to the CPU.
+    if addr.is_some_and(|addr| (0xE000..0xFE00).contains(&addr)) {
+        return None;
+    }
+
+    Some(addr)
+}
+
+/// True if putting `addr` on the bus can trigger the OAM bug.
+fn is_oam_bug_addr(addr: Addr) -> bool {
+    (0xFE00..=0xFEFF).contains(&addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::regs::{CpuReg8, CpuRegs},
+        mem::io_regs::IoReg,
+        sys::Sys,
+        test::program::{idle_sys, run_until_label, sys_from_asm},
+    };
+
+    /// Turns the LCD on and waits for LY to reach 144 a few times, counting
+    /// frames in WRAM.
+    const WAIT_LY_SRC: &str = "
+        SECTION \"Entry\", ROM0[$100]
+            nop
+            jp Main
+
+        SECTION \"Main\", ROM0[$0150]
+        Main:
+            ld a, $80
+            ldh [$40], a
+            ld b, 0
+        .frame:
+            ldh a, [$44]
+            cp 144
+            jr nz, .frame
+            inc b
+            ld a, b
+            ld [$C000], a
+        .wait_end:
+            ldh a, [$44]
+            cp 144
+            jr z, .wait_end
+            ld a, b
+            cp 3
+            jr nz, .frame
+        Done:
+            jr Done
+    ";
+
+    /// Counts timer interrupts in WRAM, until there have been 20.
+    const WAIT_TIMER_SRC: &str = "
+        SECTION \"Timer\", ROM0[$50]
+            ld a, [$C001]
+            inc a
+            ld [$C001], a
+            reti
+
+        SECTION \"Entry\", ROM0[$100]
+            nop
+            jp Main
+
+        SECTION \"Main\", ROM0[$0150]
+        Main:
+            ld a, $80
+            ldh [$40], a
+            ld a, $FC
+            ldh [$06], a
+            ld a, $04
+            ldh [$07], a
+            ldh [$FF], a
+            xor a
+            ldh [$0F], a
+            ei
+        .wait:
+            ld a, [$C001]
+            cp 20
+            jr nz, .wait
+        Done:
+            jr Done
+    ";
+
+    type Snapshot = (CpuRegs, u64, u8, u8, u8, u16, u8, u8, bool);
+
+    fn snapshot(sys: &Sys) -> Snapshot {
+        (
+            sys.regs,
+            sys.cpu_clock.debug_total_ticks,
+            sys.mem.io_regs.get(IoReg::Ly),
+            sys.mem.io_regs.get(IoReg::Stat),
+            sys.mem.io_regs.get(IoReg::If),
+            sys.timer.sys_counter,
+            sys.mem.io_regs.get(IoReg::Tima),
+            sys.mem.read(0xC000) ^ sys.mem.read(0xC001),
+            sys.interrupt_master_enable,
+        )
+    }
+
+    /// Runs `src` with and without skipping idle loops, and checks that both
+    /// are in the same state whenever they have run for the same time.
+    /// Returns the system that skipped idle loops, once it reached `Done`.
+    fn run_both_until_done(src: &str) -> Sys {
+        let (mut fast, program) = sys_from_asm(src);
+        let (mut slow, _) = sys_from_asm(src);
+        slow.options.skip_idle_loops = false;
+        let done = program.label_addr("Done").unwrap().addr;
+
+        let mut calls = 0;
+        while fast.regs.pc() != done {
+            fast.run_one_m_cycle();
+            while slow.cpu_clock.debug_total_ticks < fast.cpu_clock.debug_total_ticks {
+                slow.run_one_m_cycle();
+            }
+            assert_eq!(snapshot(&fast), snapshot(&slow));
+
+            calls += 1;
+            assert!(calls < 200_000, "Done wasn't reached");
+        }
+
+        fast
+    }
+
+    #[test]
+    fn test_skipping_idle_loops_keeps_behavior() {
+        let sys = run_both_until_done(WAIT_LY_SRC);
+        assert_eq!(sys.regs.get_8(CpuReg8::B), 3);
+    }
+
+    #[test]
+    fn test_skipping_idle_loops_keeps_timer_behavior() {
+        let sys = run_both_until_done(WAIT_TIMER_SRC);
+        assert_eq!(sys.mem.read(0xC001), 20);
+    }
+
+    #[test]
+    fn test_idle_loops_are_jumped_over() {
+        let mut sys = idle_sys();
+
+        // With the LCD off, nothing happens until the end of the frame.
+        for _ in 0..100 {
+            sys.run_one_m_cycle();
+        }
+        assert!(sys.cpu_clock.debug_total_ticks > 10_000);
+        assert!(!sys.hard_lock);
+    }
+
+    #[test]
+    fn test_polling_loop_is_replayed() {
+        let (mut sys, program) = sys_from_asm(WAIT_LY_SRC);
+        assert!(run_until_label(&mut sys, &program, "Main.frame", 100));
+
+        for _ in 0..100 {
+            sys.run_one_m_cycle();
+        }
+        assert!(sys.idle_loop.is_replaying());
+        assert!(!sys.hard_lock);
+    }
+
+    #[test]
+    fn test_loop_near_oam_is_not_replayed() {
+        // INC HL and DEC HL trigger the OAM bug while the PPU scans OAM.
+        let src = "
+            SECTION \"Entry\", ROM0[$100]
+                nop
+                jp Main
+
+            SECTION \"Main\", ROM0[$0150]
+            Main:
+                ld a, $80
+                ldh [$40], a
+                ld hl, $FE40
+            .wait:
+                inc hl
+                dec hl
+                ldh a, [$44]
+                cp 144
+                jr nz, .wait
+            Done:
+                jr Done
+        ";
+        let (mut sys, program) = sys_from_asm(src);
+        assert!(run_until_label(&mut sys, &program, "Main.wait", 100));
+
+        for _ in 0..100 {
+            sys.run_one_m_cycle();
+        }
+        assert!(!sys.idle_loop.is_replaying());
+    }
+
+    #[test]
+    fn test_dead_loop_stops() {
+        let src = "
+            SECTION \"Entry\", ROM0[$100]
+                nop
+                jp Main
+
+            SECTION \"Main\", ROM0[$0150]
+            Main:
+                di
+            .forever:
+                jr .forever
+        ";
+        let (mut sys, _) = sys_from_asm(src);
+        sys.options.kill_on_dead_loop = true;
+
+        for _ in 0..100 {
+            sys.run_one_m_cycle();
+        }
+        assert!(sys.hard_lock);
+    }
+}
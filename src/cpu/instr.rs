@@ -1,3 +1,5 @@
+use std::sync::LazyLock;
+
 use crate::{
     cpu::regs::{CpuReg16, CpuReg8},
     util::math::{bit8, bits8},
@@ -279,6 +281,28 @@ pub enum ImmType {
 
 pub type DecodeResult = Result<Instr, String>;
 
+/// Every opcode decoded ahead of time. Entries 0x000-0x0FF hold the
+/// unprefixed opcodes and entries 0x100-0x1FF hold the 0xCB prefixed ones.
+static INSTR_TABLE: LazyLock<[Instr; 512]> = LazyLock::new(build_instr_table);
+
+/// Returns the decoded instruction for `op` from the precomputed table.
+#[inline]
+pub fn lookup(op: u8, has_cb_prefix: bool) -> Instr {
+    let idx = ((has_cb_prefix as usize) << 8) | (op as usize);
+    INSTR_TABLE[idx]
+}
+
+fn build_instr_table() -> [Instr; 512] {
+    let mut table = [Instr::Nop; 512];
+    for op in 0x00..=0xFF {
+        // 0xCB is never looked up without a prefix, since it is the prefix.
+        table[op as usize] = decode(op, false).unwrap_or(Instr::Invalid(op));
+        table[0x100 | op as usize] = decode(op, true).unwrap_or(Instr::Invalid(op));
+    }
+
+    table
+}
+
 pub fn decode(op: u8, has_cb_prefix: bool) -> DecodeResult {
     if has_cb_prefix {
         return Ok(decode_cp_prefix_opcode(op));
@@ -553,3 +577,21 @@ fn decode_cp_prefix_opcode(op: u8) -> Instr {
         _ => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_matches_decode() {
+        for op in 0x00..=0xFF {
+            if let Ok(instr) = decode(op, false) {
+                assert_eq!(lookup(op, false), instr);
+            } else {
+                assert_eq!(op, Instr::CB_PREFIX);
+            }
+
+            assert_eq!(lookup(op, true), decode(op, true).unwrap());
+        }
+    }
+}
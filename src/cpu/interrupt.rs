@@ -2,7 +2,6 @@ use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::{
-    debug,
     mem::{io_regs::IoReg, Addr},
     sys::Sys,
     util::math::{bit8, set_bit8},
@@ -70,7 +69,7 @@ pub fn try_handle_interrupts(sys: &mut Sys) {
 }
 
 fn handle_interrupt(sys: &mut Sys, type_: InterruptType) {
-    debug::record_handled_interrupt(type_);
+    sys.debug.record_handled_interrupt(type_);
 
     sys.interrupt_master_enable = false;
     sys.cpu_enable = true;
@@ -1,5 +1,7 @@
+pub mod disasm;
 pub mod exec;
 mod exec_math;
+pub mod idle;
 pub mod instr;
 pub mod interrupt;
 pub mod regs;
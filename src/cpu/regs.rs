@@ -51,7 +51,7 @@ pub enum CpuFlag {
     C = 4,
 }
 
-#[derive(Default, Clone, PartialEq, Eq, Debug)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct CpuRegs {
     regs8: [u8; 8],
 
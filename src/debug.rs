@@ -1,12 +1,12 @@
-#![allow(static_mut_refs)]
+use std::{cell::RefCell, collections::HashMap, mem::transmute};
 
-use std::{collections::HashMap, mem::transmute};
+use num::FromPrimitive;
 
 use strum::IntoEnumIterator;
 
 use crate::{
     cpu::{
-        instr::{decode, ImmType, Instr},
+        instr::{ImmType, Instr},
         interrupt::InterruptType,
         regs::CpuRegs,
     },
@@ -18,13 +18,35 @@ use crate::{
 #[derive(Clone)]
 pub struct DebugConfig {
     pub enable_debug_print: bool,
+    pub record_instrs: bool,
+    pub record_io_reg_usage: bool,
     pub kill_after_cpu_ticks: Option<u64>,
     pub kill_after_nop_count: Option<u64>,
     pub last_instr_count: usize,
 }
 
-static mut DEBUG_STATE: Option<DebugState> = None;
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            enable_debug_print: false,
+            record_instrs: false,
+            record_io_reg_usage: false,
+            kill_after_cpu_ticks: None,
+            kill_after_nop_count: None,
+            last_instr_count: 15,
+        }
+    }
+}
+
+/// How the CPU has used an IO register.
+#[derive(Default, Clone, Copy)]
+pub struct IoRegUsage {
+    pub reads: u64,
+    pub writes: u64,
+    pub last_write_data: u8,
+}
 
+/// The debugging state of one `Sys`.
 pub struct DebugState {
     failure: Option<String>,
     pending_breakpoint: bool,
@@ -33,18 +55,15 @@ pub struct DebugState {
     pub total_instrs_executed: u64,
     instr_ring_buffer: RingBuffer<InstrRecord>,
     used_instrs: HashMap<Instr, u64>,
-    used_instr_variants: HashMap<String, u64>,
-    used_io_regs: HashMap<IoReg, IoRegRecord>,
-    pub request_print_last_instr: u64,
-    serial_out_log: String,
     interrupt_counts: HashMap<InterruptType, u64>,
+    io_reg_usage: RefCell<HashMap<IoReg, IoRegUsage>>,
 }
 
-pub fn initialize_debug(config: DebugConfig) {
-    unsafe {
+impl DebugState {
+    pub fn new(config: DebugConfig) -> Self {
         let last_instr_count = config.last_instr_count;
 
-        DEBUG_STATE = Some(DebugState {
+        Self {
             failure: None,
             pending_breakpoint: false,
             config,
@@ -52,51 +71,96 @@ pub fn initialize_debug(config: DebugConfig) {
             total_instrs_executed: 0,
             instr_ring_buffer: RingBuffer::new(last_instr_count),
             used_instrs: HashMap::new(),
-            used_instr_variants: HashMap::new(),
-            used_io_regs: HashMap::new(),
-            request_print_last_instr: 0,
-            serial_out_log: String::new(),
             interrupt_counts: HashMap::new(),
-        });
+            io_reg_usage: RefCell::new(HashMap::new()),
+        }
     }
-}
 
-pub fn debug_state() -> &'static mut DebugState {
-    unsafe {
-        let Some(debug) = &mut DEBUG_STATE else {
-            unreachable!();
-        };
-        debug
+    /// True if the `debug_hooks` feature is compiled in and instruction
+    /// recording is enabled. When the feature is off this is a constant `false`.
+    #[inline(always)]
+    pub fn is_recording_instrs(&self) -> bool {
+        cfg!(feature = "debug_hooks") && self.config.record_instrs
     }
-}
 
-pub fn get_failure() -> Option<String> {
-    debug_state().failure.clone()
-}
+    /// True if the `debug_hooks` feature is compiled in and IO register usage
+    /// recording is enabled. When the feature is off this is a constant `false`.
+    #[inline(always)]
+    pub fn is_recording_io_reg_usage(&self) -> bool {
+        cfg!(feature = "debug_hooks") && self.config.record_io_reg_usage
+    }
 
-pub fn request_breakpoint() {
-    debug_state().pending_breakpoint = true;
-}
+    /// True if the `debug_hooks` feature is compiled in and debug printing
+    /// is enabled. When the feature is off this is a constant `false`.
+    #[inline(always)]
+    pub fn is_debug_print_enabled(&self) -> bool {
+        cfg!(feature = "debug_hooks") && self.config.enable_debug_print
+    }
+
+    pub fn get_failure(&self) -> Option<String> {
+        self.failure.clone()
+    }
 
-// pub fn take_pending_breakpoint() -> bool {
-//     let pending_breakpoint = debug_state().pending_breakpoint;
-//     debug_state().pending_breakpoint = false;
+    pub fn fail(&mut self, msg: impl Into<String>) {
+        self.failure = Some(msg.into());
+    }
 
-//     return pending_breakpoint;
-// }
+    pub fn request_breakpoint(&mut self) {
+        self.pending_breakpoint = true;
+    }
 
-pub fn push_serial_char(c: char) {
-    debug_state().serial_out_log.push(c);
-}
+    // pub fn take_pending_breakpoint(&mut self) -> bool {
+    //     let pending_breakpoint = self.pending_breakpoint;
+    //     self.pending_breakpoint = false;
 
-pub fn flush_serial_char() {
-    let s = std::mem::take(&mut debug_state().serial_out_log);
-    println!("{}", s);
-}
+    //     return pending_breakpoint;
+    // }
 
-pub fn record_handled_interrupt(type_: InterruptType) {
-    let count = *debug_state().interrupt_counts.get(&type_).unwrap_or(&0);
-    debug_state().interrupt_counts.insert(type_, count + 1);
+    pub fn record_handled_interrupt(&mut self, type_: InterruptType) {
+        *self.interrupt_counts.entry(type_).or_insert(0) += 1;
+    }
+
+    /// Counts the NOPs executed in a row, for `kill_after_nop_count`. Does
+    /// nothing unless the `debug_hooks` feature is compiled in and that
+    /// limit is set.
+    #[inline(always)]
+    pub fn count_nops(&mut self, instr: Instr) {
+        if !cfg!(feature = "debug_hooks") || self.config.kill_after_nop_count.is_none() {
+            return;
+        }
+
+        if instr == Instr::Nop {
+            self.nop_count += 1;
+        } else {
+            self.nop_count = 0;
+        }
+    }
+
+    /// Records a CPU access to `addr` if it is an IO register. Does nothing
+    /// unless `is_recording_io_reg_usage` is true.
+    #[inline(always)]
+    pub fn record_io_reg_access(&self, addr: Addr, is_write: bool, data: u8) {
+        if !self.is_recording_io_reg_usage() || addr < 0xFF00 {
+            return;
+        }
+        let Some(reg) = IoReg::from_u16(addr) else {
+            return;
+        };
+
+        let mut usage = self.io_reg_usage.borrow_mut();
+        let record = usage.entry(reg).or_default();
+        if is_write {
+            record.writes += 1;
+            record.last_write_data = data;
+        } else {
+            record.reads += 1;
+        }
+    }
+
+    /// Returns how the CPU has used `reg`, if it has at all.
+    pub fn io_reg_usage(&self, reg: IoReg) -> Option<IoRegUsage> {
+        self.io_reg_usage.borrow().get(&reg).copied()
+    }
 }
 
 struct InstrRecord {
@@ -109,10 +173,12 @@ struct InstrRecord {
     stack_record: StackRecord,
 }
 
+const STACK_RECORD_LEN: usize = 5;
+
 struct StackRecord {
     pub offset: Addr,
     pub sp: Addr,
-    pub items: Vec<u8>,
+    pub items: [u8; STACK_RECORD_LEN],
 }
 
 enum ImmValue {
@@ -121,52 +187,29 @@ enum ImmValue {
     Imm16(u16),
 }
 
-struct IoRegRecord {
-    reads: u64,
-    writes: u64,
-    last_write_data: u8,
-}
-
 const DO_RECORD_NOP: bool = false;
 
-pub fn record_curr_instr(sys: &Sys) {
-    debug_state().total_instrs_executed += 1;
-
-    let mut pc = sys.regs.pc();
-    let addr = pc;
-    let mut op = sys.mem.read(pc);
-    let mut has_cb_prefix = false;
-    if op == Instr::CB_PREFIX {
-        pc += 1;
-        op = sys.mem.read(pc);
-        has_cb_prefix = true;
-    }
-    let instr = match decode(op, has_cb_prefix) {
-        Ok(instr) => instr,
-        Err(_) => {
-            return;
-        }
-    };
+/// Records the already decoded instruction at PC. Only called when
+/// `is_recording_instrs` is true. Memory is peeked, so recording never
+/// shows up as IO register usage or as an invalid access.
+pub fn record_curr_instr(sys: &mut Sys, instr: Instr) {
+    sys.debug.total_instrs_executed += 1;
 
-    if let Instr::Nop = instr {
+    if instr == Instr::Nop && !DO_RECORD_NOP {
         // Don't record NOPs.
-        debug_state().nop_count += 1;
-        if !DO_RECORD_NOP {
-            return;
-        }
-    } else {
-        debug_state().nop_count = 0;
+        return;
     }
 
+    let addr = sys.regs.pc();
     let imm_value = match instr.imm_type() {
         ImmType::None => ImmValue::None,
         ImmType::Imm8 => {
-            let imm8 = sys.mem.read(pc + 1);
+            let imm8 = sys.mem.peek(addr + 1);
             ImmValue::Imm8(imm8)
         }
         ImmType::Imm16 => {
-            let lo = sys.mem.read(pc + 1);
-            let hi = sys.mem.read(pc + 2);
+            let lo = sys.mem.peek(addr + 1);
+            let hi = sys.mem.peek(addr + 2);
             let imm16 = join_16(hi, lo);
             ImmValue::Imm16(imm16)
         }
@@ -174,13 +217,11 @@ pub fn record_curr_instr(sys: &Sys) {
 
     let stack_record = {
         let sp = sys.regs.sp();
-        let range_min = u16::saturating_sub(sp, 2);
-        let range_max = u16::saturating_add(sp, 2);
-        let offset = range_min;
-        let mut items = vec![];
-        for addr in range_min..=range_max {
-            let data = sys.mem.read(addr);
-            items.push(data);
+        let offset = u16::saturating_sub(sp, 2);
+        let mut items = [0; STACK_RECORD_LEN];
+        for (i, item) in items.iter_mut().enumerate() {
+            let addr = u16::saturating_add(offset, i as u16);
+            *item = sys.mem.peek(addr);
         }
 
         StackRecord { offset, sp, items }
@@ -191,52 +232,14 @@ pub fn record_curr_instr(sys: &Sys) {
         addr,
         instr,
         imm: imm_value,
-        regs: sys.regs.clone(),
+        regs: sys.regs,
 
         stack_record,
     };
 
-    debug_state().instr_ring_buffer.add(record);
-
-    debug_state().used_instrs.entry(instr).or_insert(0);
-
-    let count = debug_state().used_instrs.get(&instr).unwrap();
-    debug_state().used_instrs.insert(instr, count + 1);
+    sys.debug.instr_ring_buffer.add(record);
 
-    let variant_str = format!("{:?}", instr).split("{").collect::<Vec<_>>()[0].to_owned();
-    if !debug_state().used_instr_variants.contains_key(&variant_str) {
-        debug_state()
-            .used_instr_variants
-            .insert(variant_str.clone(), 0);
-    }
-    let count = debug_state().used_instr_variants.get(&variant_str).unwrap();
-    debug_state()
-        .used_instr_variants
-        .insert(variant_str, count + 1);
-}
-
-/// is_write: false for read, true for write.
-pub fn record_io_reg_usage(reg: IoReg, is_write: bool, data: u8) {
-    debug_state()
-        .used_io_regs
-        .entry(reg)
-        .or_insert(IoRegRecord {
-            reads: 0,
-            writes: 0,
-            last_write_data: 0,
-        });
-    let record = debug_state().used_io_regs.get_mut(&reg).unwrap();
-    if !is_write {
-        record.reads += 1;
-    }
-    if is_write {
-        record.writes += 1;
-        record.last_write_data = data;
-    }
-}
-
-pub fn fail(msg: impl Into<String>) {
-    debug_state().failure = Some(msg.into());
+    *sys.debug.used_instrs.entry(instr).or_insert(0) += 1;
 }
 
 const PRINT_LAST_INSTRS: bool = true;
@@ -247,36 +250,33 @@ const PRINT_INTERRUPT_COUNTS: bool = true;
 const PRINT_STACK_RECORDS: bool = true;
 
 pub fn print_system_state(sys: &Sys) {
+    let debug = &sys.debug;
+
     if PRINT_LAST_INSTRS {
         // Print Instr record
-        println!(
-            "last {} instrs executed:",
-            debug_state().instr_ring_buffer.len()
-        );
-        for record in debug_state().instr_ring_buffer.iter() {
+        println!("last {} instrs executed:", debug.instr_ring_buffer.len());
+        for record in debug.instr_ring_buffer.iter() {
             print_instr_record(record);
         }
     }
 
     if PRINT_TOTAL_INSTRS {
-        println!(
-            "  total instrs executed: {}",
-            debug_state().total_instrs_executed
-        );
+        println!("  total instrs executed: {}", debug.total_instrs_executed);
 
         // Print all used instructions and counts.
-        println!(
-            "\n  unique instrs executed: {}",
-            debug_state().used_instrs.len()
-        );
-        for (instr, count) in &debug_state().used_instrs {
+        println!("\n  unique instrs executed: {}", debug.used_instrs.len());
+        let mut used_instr_variants: HashMap<String, u64> = HashMap::new();
+        for (instr, count) in &debug.used_instrs {
             println!("    {:?}: {}", instr, count);
+
+            let variant_str = format!("{:?}", instr).split("{").collect::<Vec<_>>()[0].to_owned();
+            *used_instr_variants.entry(variant_str).or_insert(0) += count;
         }
         println!(
             "\n  unique instr variants executed: {}",
-            debug_state().used_instr_variants.len()
+            used_instr_variants.len()
         );
-        for (variant_str, count) in &debug_state().used_instr_variants {
+        for (variant_str, count) in &used_instr_variants {
             println!("    {}: {}", variant_str, count);
         }
     }
@@ -285,7 +285,7 @@ pub fn print_system_state(sys: &Sys) {
         // Print all IO reg usage.
         println!("\nIO Reg usage:");
         for reg in IoReg::iter() {
-            if let Some(record) = debug_state().used_io_regs.get(&reg) {
+            if let Some(record) = debug.io_reg_usage(reg) {
                 println!(
                     "  {:?}: {} reads, {} writes, [last write = 0b{:0>8b}]",
                     reg, record.reads, record.writes, record.last_write_data
@@ -297,7 +297,7 @@ pub fn print_system_state(sys: &Sys) {
     if PRINT_INTERRUPT_COUNTS {
         // Print interrupt counts.
         println!("\nInterrupts:");
-        for (type_, count) in debug_state().interrupt_counts.iter() {
+        for (type_, count) in debug.interrupt_counts.iter() {
             println!("  {:?}: ran {} times", *type_, *count);
         }
     }
@@ -348,3 +348,72 @@ fn print_instr_record(record: &InstrRecord) {
         println!();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{mem::io_regs::IoReg, test::program::sys_from_asm};
+
+    /// Runs 3 NOPs in a row, then loops.
+    const NOPS_SRC: &str = "
+        SECTION \"Entry\", ROM0[$100]
+            jp Main
+
+        SECTION \"Main\", ROM0[$150]
+        Main:
+            nop
+            nop
+            nop
+        Done:
+            jr Done
+    ";
+
+    #[test]
+    fn test_kill_after_nop_count_without_recording() {
+        for (max_nops, is_killed) in [(3, true), (4, false)] {
+            let (mut sys, _) = sys_from_asm(NOPS_SRC);
+            sys.debug.config.kill_after_nop_count = Some(max_nops);
+            for _ in 0..100 {
+                sys.run_one_m_cycle();
+            }
+
+            assert_eq!(sys.hard_lock, is_killed);
+        }
+    }
+
+    #[test]
+    fn test_io_reg_usage_is_only_recorded_when_enabled() {
+        for is_enabled in [false, true] {
+            let (mut sys, _) = sys_from_asm(NOPS_SRC);
+            sys.debug.config.record_io_reg_usage = is_enabled;
+            sys.read(IoReg::Ly as u16);
+
+            let usage = sys.debug.io_reg_usage(IoReg::Ly);
+            assert_eq!(usage.map(|usage| usage.reads), is_enabled.then_some(1));
+        }
+    }
+
+    #[test]
+    fn test_recording_instrs_does_not_use_io_regs() {
+        // The recorded stack is around $FF46, which covers LY.
+        let (mut sys, _) = sys_from_asm(
+            "
+            SECTION \"Entry\", ROM0[$100]
+                jp Main
+
+            SECTION \"Main\", ROM0[$150]
+            Main:
+                ld sp, $FF46
+            Done:
+                jr Done
+            ",
+        );
+        sys.debug.config.record_instrs = true;
+        sys.debug.config.record_io_reg_usage = true;
+        for _ in 0..100 {
+            sys.run_one_m_cycle();
+        }
+
+        assert!(sys.debug.total_instrs_executed > 0);
+        assert!(sys.debug.io_reg_usage(IoReg::Ly).is_none());
+    }
+}
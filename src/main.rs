@@ -12,24 +12,34 @@ use std::{env, fs};
 
 use cart::cart::Cart;
 use consts::PIXEL_SCALE;
-use debug::{initialize_debug, DebugConfig};
-use macroquad::{
-    color::BLACK,
-    input::{is_key_pressed, KeyCode},
-    window::next_frame,
+use cpu::disasm::{disassemble_rom, DisasmMode};
+use macroquad::{color::BLACK, window::next_frame};
+use other::{
+    controls::{Action, Controls, Player},
+    controls_screen::ControlsScreen,
+    keyboard::KeyboardInput,
+    save::{load_state, save_state},
 };
-use other::save::{load_state, save_state};
-use ppu::{consts::window_size, ui::render_ui};
+use ppu::{
+    consts::{window_size, VIEWPORT_ORG, WINDOW_SIZE_NORMAL},
+    screen::Screen,
+    ui::render_ui,
+};
+use serial::{device::SerialDevice, link::LinkCable, local_link::LocalLink, printer::Printer};
 use sys::{Options, Sys};
-use xf::mq::{
-    draw::draw_rect,
-    window::{Window, WindowParams},
+use xf::{
+    mq::{
+        draw::draw_rect,
+        window::{Window, WindowParams},
+    },
+    num::ivec2::{i2, IVec2},
 };
 
 extern crate num;
 #[macro_use]
 extern crate num_derive;
 
+mod asm;
 mod cart;
 mod consts;
 mod cpu;
@@ -37,51 +47,145 @@ mod debug;
 mod mem;
 mod other;
 mod ppu;
+mod serial;
 mod sys;
 mod test;
 mod time;
 mod util;
 
-#[macroquad::main("rust_gb_emu")]
-async fn main() {
-    println!("*** RUST GAMEBOY EMU (Matthew Ducasse 2025) ***");
-
-    if let Some(rom_path) = validate_args(env::args().collect()) {
-        run_emu(&rom_path).await;
+fn main() {
+    match parse_args(env::args().collect()) {
+        Some(Command::Run { rom_path, serial }) => {
+            println!("*** RUST GAMEBOY EMU (Matthew Ducasse 2025) ***");
+            macroquad::Window::new(
+                "rust_gb_emu",
+                async move { run_emu(&rom_path, serial).await },
+            );
+        }
+        Some(Command::RunLinked { rom_paths }) => {
+            println!("*** RUST GAMEBOY EMU (Matthew Ducasse 2025) ***");
+            macroquad::Window::new(
+                "rust_gb_emu",
+                async move { run_linked_emu(&rom_paths).await },
+            );
+        }
+        Some(Command::Disasm { rom_path, mode }) => {
+            run_disasm(&rom_path, mode);
+        }
+        None => {}
     }
 }
 
-fn validate_args(mut args: Vec<String>) -> Option<String> {
-    const USAGE_STR: &str = "usage: rust_gb_2.exe <gb-rom-file-path>";
+/// What to plug into the serial port.
+enum SerialArg {
+    LinkHost(String),
+    LinkConnect(String),
+    Printer(String),
+}
 
-    if args.len() != 2 {
-        println!("Expected a file path to a .gb rom file.");
-        println!("{}", USAGE_STR);
-        return None;
-    }
+enum Command {
+    Run {
+        rom_path: String,
+        serial: Option<SerialArg>,
+    },
+    /// Runs two systems linked together, side by side in the same window.
+    RunLinked {
+        rom_paths: [String; 2],
+    },
+    Disasm {
+        rom_path: String,
+        mode: DisasmMode,
+    },
+}
+
+fn parse_args(mut args: Vec<String>) -> Option<Command> {
+    const USAGE_STR: &str = "usage: rust_gb_2.exe <gb-rom-file-path> [--link-host <addr> | --link-connect <addr> | --printer <output-dir> | --local-link [<second-gb-rom-file-path>]]\n       rust_gb_2.exe disasm <gb-rom-file-path> [--recursive]";
 
-    let rom_path = args.remove(1);
-    match fs::exists(&rom_path) {
-        Ok(true) => Some(rom_path),
-        Ok(false) => {
-            println!("File does not exist: {}", rom_path);
-            None
+    let command = match args.get(1).map(String::as_str) {
+        Some("disasm") if args.len() == 3 || args.len() == 4 => {
+            let mode = match args.get(3).map(String::as_str) {
+                None => DisasmMode::Linear,
+                Some("--recursive") => DisasmMode::Recursive,
+                Some(flag) => {
+                    println!("Unknown disasm option: {}", flag);
+                    println!("{}", USAGE_STR);
+                    return None;
+                }
+            };
+            Command::Disasm {
+                rom_path: args.remove(2),
+                mode,
+            }
         }
-        Err(msg) => {
-            println!("{}", msg);
-            None
+        _ if args.get(2).map(String::as_str) == Some("--local-link")
+            && (args.len() == 3 || args.len() == 4) =>
+        {
+            let second_rom_path = args.get(3).unwrap_or(&args[1]).clone();
+            Command::RunLinked {
+                rom_paths: [args.remove(1), second_rom_path],
+            }
+        }
+        _ if args.len() == 2 || args.len() == 4 => {
+            let serial = match args.get(2).map(String::as_str) {
+                None => None,
+                Some("--link-host") => Some(SerialArg::LinkHost(args.remove(3))),
+                Some("--link-connect") => Some(SerialArg::LinkConnect(args.remove(3))),
+                Some("--printer") => Some(SerialArg::Printer(args.remove(3))),
+                Some(flag) => {
+                    println!("Unknown option: {}", flag);
+                    println!("{}", USAGE_STR);
+                    return None;
+                }
+            };
+            Command::Run {
+                rom_path: args.remove(1),
+                serial,
+            }
+        }
+        _ => {
+            println!("Expected a file path to a .gb rom file.");
+            println!("{}", USAGE_STR);
+            return None;
+        }
+    };
+
+    let rom_paths = match &command {
+        Command::Run { rom_path, .. } => std::slice::from_ref(rom_path),
+        Command::RunLinked { rom_paths } => rom_paths.as_slice(),
+        Command::Disasm { rom_path, .. } => std::slice::from_ref(rom_path),
+    };
+    for rom_path in rom_paths {
+        match fs::exists(rom_path) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("File does not exist: {}", rom_path);
+                return None;
+            }
+            Err(msg) => {
+                println!("{}", msg);
+                return None;
+            }
         }
     }
+
+    Some(command)
 }
 
-async fn run_emu(rom_path: &str) {
-    initialize_debug(DebugConfig {
-        enable_debug_print: false,
-        kill_after_cpu_ticks: None,
-        kill_after_nop_count: None,
-        last_instr_count: 15,
-    });
+/// Prints the disassembly of the rom file to the console.
+fn run_disasm(rom_path: &str, mode: DisasmMode) {
+    let rom = match fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(msg) => {
+            println!("Unable to read file {}: {}", rom_path, msg);
+            return;
+        }
+    };
+
+    print!("{}", disassemble_rom(&rom, mode));
+}
 
+/// Loads the game cartridge and instantiates the emulator state.
+fn new_sys(rom_path: &str, show_vram_views: bool) -> Sys {
     // Instantiate the game cartridge.
     let cart = match Cart::load_from(rom_path, true) {
         Ok(cart) => cart,
@@ -91,14 +195,33 @@ async fn run_emu(rom_path: &str) {
     };
 
     // Set emulator options.
-    let show_vram_views = true;
     let options = Options {
-        kill_on_infinite_loop: true,
+        kill_on_dead_loop: false,
+        skip_idle_loops: true,
         show_vram_views,
     };
 
-    // Instantiate the emulator state.
-    let mut sys = Sys::new(options, cart);
+    Sys::new(options, cart)
+}
+
+async fn run_emu(rom_path: &str, serial: Option<SerialArg>) {
+    let show_vram_views = true;
+    let mut sys = new_sys(rom_path, show_vram_views);
+
+    // Plug in the serial device.
+    if let Some(serial) = serial {
+        let device: Result<Box<dyn SerialDevice>, String> = match serial {
+            SerialArg::LinkHost(addr) => LinkCable::host(&addr).map(|c| Box::new(c) as _),
+            SerialArg::LinkConnect(addr) => LinkCable::connect(&addr).map(|c| Box::new(c) as _),
+            SerialArg::Printer(dir) => Ok(Box::new(Printer::new(dir))),
+        };
+        match device {
+            Ok(device) => sys.serial.connect(device),
+            Err(msg) => {
+                panic!("{}", msg);
+            }
+        }
+    }
 
     // Instantiate the UI window.
     let window = Window::new(WindowParams {
@@ -109,55 +232,186 @@ async fn run_emu(rom_path: &str) {
     // Load the saved game state.
     load_state(&mut sys);
 
+    let mut keyboard = KeyboardInput::new(Controls::load(Player::One));
+    let mut controls_screen = ControlsScreen::default();
+    let mut screen = Screen::new();
+    sys.emu.turbo_period_frames = keyboard.controls.turbo_period_frames;
+    sys.input_macros.load(sys.emu.player);
+
     // Main loop.
     while !sys.hard_lock {
-        check_misc_inputs(&mut sys);
+        if controls_screen.is_open() {
+            controls_screen.update(&mut keyboard.controls);
+        } else {
+            check_misc_inputs(&mut sys, &keyboard.controls, &mut controls_screen);
+        }
 
         window.render_pass(|| {
             draw_rect(window.bounds(), BLACK);
-            let speed = sys.emu.speed();
-            for _ in 0..speed {
-                while !sys.is_render_pending && !sys.hard_lock {
-                    sys.run_one_m_cycle();
-                }
-                sys.is_render_pending = false;
+
+            // The emulator is paused while the controls are being changed.
+            if !controls_screen.is_open() {
+                sys.update_input(&mut keyboard);
+                sys.run_frames(sys.emu.speed());
             }
 
-            render_ui(&mut sys);
+            render_ui(&mut sys, IVec2::ZERO);
+            screen.draw(&sys.framebuffer, VIEWPORT_ORG);
             sys.is_render_pending = false;
+
+            if controls_screen.is_open() {
+                controls_screen.draw(&keyboard.controls, VIEWPORT_ORG);
+            }
         });
 
         next_frame().await;
     }
 
-    debug::flush_serial_char();
+    if let Err(msg) = sys.input_macros.save(sys.emu.player) {
+        println!("{}", msg);
+    }
+
+    sys.serial.flush();
     debug::print_system_state(&sys);
 
     loop {
         window.render_pass(|| {});
         next_frame().await;
-        if is_key_pressed(KeyCode::Escape) {
+        if keyboard.controls.is_pressed(Action::Quit) {
             return;
         }
     }
 }
 
-fn check_misc_inputs(sys: &mut Sys) {
-    if is_key_pressed(KeyCode::Escape) {
+/// Runs two systems connected by a local link cable, in exact M-cycle
+/// lock-step, with their screens side by side. Each player has their own
+/// key map, save file and macros, and the hotkeys are player 1's.
+async fn run_linked_emu(rom_paths: &[String; 2]) {
+    let mut systems = rom_paths.clone().map(|rom_path| new_sys(&rom_path, false));
+    systems[1].emu.player = Player::Two;
+
+    let (link_1, link_2) = LocalLink::pair();
+    systems[0].serial.connect(Box::new(link_1));
+    systems[1].serial.connect(Box::new(link_2));
+
+    // Player 2's screen is to the right of player 1's.
+    let orgs = [IVec2::ZERO, i2(WINDOW_SIZE_NORMAL.x, 0)];
+
+    let window = Window::new(WindowParams {
+        resolution: i2(2 * WINDOW_SIZE_NORMAL.x, WINDOW_SIZE_NORMAL.y),
+        scale: PIXEL_SCALE,
+    });
+
+    let mut keyboards = [Player::One, Player::Two].map(|player| {
+        let controls = Controls::load(player);
+        KeyboardInput::new(controls)
+    });
+    for conflict in keyboards[0].controls.conflicts_with(&keyboards[1].controls) {
+        println!("Warning: {}", conflict);
+    }
+    let mut controls_screen = ControlsScreen::default();
+    let mut screens = [Screen::new(), Screen::new()];
+
+    // Each player has their own save and macros files.
+    for (sys, keyboard) in systems.iter_mut().zip(&keyboards) {
+        load_state(sys);
+        sys.emu.turbo_period_frames = keyboard.controls.turbo_period_frames;
+        sys.input_macros.load(sys.emu.player);
+    }
+
+    // Main loop.
+    while !systems.iter().any(|sys| sys.hard_lock) {
+        if controls_screen.is_open() {
+            controls_screen.update(&mut keyboards[0].controls);
+        } else {
+            check_misc_inputs(
+                &mut systems[0],
+                &keyboards[0].controls,
+                &mut controls_screen,
+            );
+
+            // Player 1's save hotkeys apply to both games.
+            if keyboards[0].controls.is_pressed(Action::SaveState) {
+                save_state(&systems[1]);
+            }
+            if keyboards[0].controls.is_pressed(Action::LoadState) {
+                load_state(&mut systems[1]);
+            }
+        }
+
+        window.render_pass(|| {
+            draw_rect(window.bounds(), BLACK);
+
+            if !controls_screen.is_open() {
+                for (sys, keyboard) in systems.iter_mut().zip(&mut keyboards) {
+                    sys.update_input(keyboard);
+                }
+
+                let speed = systems[0].emu.speed();
+                for _ in 0..speed {
+                    // Player 1's frames set the pace. Both run the same
+                    // number of M-cycles, so player 2 stays in step.
+                    let [sys_1, sys_2] = &mut systems;
+                    while !sys_1.is_render_pending && !sys_1.hard_lock && !sys_2.hard_lock {
+                        sys_1.run_one_m_cycle();
+                        sys_2.run_one_m_cycle();
+                    }
+                    sys_1.is_render_pending = false;
+                    sys_2.is_render_pending = false;
+                }
+            }
+
+            for ((sys, screen), org) in systems.iter_mut().zip(&mut screens).zip(orgs) {
+                render_ui(sys, org);
+                screen.draw(&sys.framebuffer, org + VIEWPORT_ORG);
+                sys.is_render_pending = false;
+            }
+
+            if controls_screen.is_open() {
+                controls_screen.draw(&keyboards[0].controls, VIEWPORT_ORG);
+            }
+        });
+
+        next_frame().await;
+    }
+
+    for sys in &mut systems {
+        if let Err(msg) = sys.input_macros.save(sys.emu.player) {
+            println!("{}", msg);
+        }
+        sys.serial.flush();
+        debug::print_system_state(sys);
+    }
+
+    loop {
+        window.render_pass(|| {});
+        next_frame().await;
+        if keyboards[0].controls.is_pressed(Action::Quit) {
+            return;
+        }
+    }
+}
+
+fn check_misc_inputs(sys: &mut Sys, controls: &Controls, controls_screen: &mut ControlsScreen) {
+    if controls.is_pressed(Action::Quit) {
         sys.hard_lock = true;
     }
 
-    if is_key_pressed(KeyCode::Backspace) {
+    if controls.is_pressed(Action::SaveState) {
         save_state(sys);
     }
-    if is_key_pressed(KeyCode::Equal) {
+    if controls.is_pressed(Action::LoadState) {
         load_state(sys);
     }
 
-    if is_key_pressed(KeyCode::Space) {
+    if controls.is_pressed(Action::ToggleSpeedup) {
         sys.emu.is_speedup_enabled = !sys.emu.is_speedup_enabled;
     }
-    if is_key_pressed(KeyCode::T) {
+    if controls.is_pressed(Action::ToggleWinMap) {
         sys.emu.show_win_map = !sys.emu.show_win_map;
     }
+
+    if controls.is_pressed(Action::OpenControls) {
+        controls_screen.open();
+    }
 }
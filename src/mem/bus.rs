@@ -0,0 +1,54 @@
+use std::cell::RefCell;
+
+use super::Addr;
+
+/// A memory access made by the CPU.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BusAccess {
+    pub addr: Addr,
+    pub data: u8,
+    pub is_write: bool,
+}
+
+/// A flat 64KB RAM with no memory mapped hardware behind it.
+/// Used in place of `Mem` to test the CPU in isolation. It keeps a log of
+/// the accesses made.
+pub struct FlatBus {
+    data: Vec<u8>,
+    accesses: RefCell<Vec<BusAccess>>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self {
+            data: vec![0; 0x1_0000],
+            accesses: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl FlatBus {
+    pub fn read(&self, addr: Addr) -> u8 {
+        let data = self.data[addr as usize];
+        self.accesses.borrow_mut().push(BusAccess {
+            addr,
+            data,
+            is_write: false,
+        });
+        data
+    }
+
+    pub fn write(&mut self, addr: Addr, data: u8) {
+        self.data[addr as usize] = data;
+        self.accesses.get_mut().push(BusAccess {
+            addr,
+            data,
+            is_write: true,
+        });
+    }
+
+    /// Returns the accesses made since the last call.
+    pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+        std::mem::take(self.accesses.get_mut())
+    }
+}
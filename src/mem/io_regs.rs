@@ -5,7 +5,7 @@ use num::FromPrimitive;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::{debug, util::math::set_bits8_masked};
+use crate::util::math::set_bits8_masked;
 
 use super::{array::Array, sections::MemSection, Addr};
 
@@ -67,6 +67,10 @@ pub struct IoRegs {
     reg_datas: HashMap<IoReg, IoRegData>,
 
     pub dma_requested: bool,
+    pub div_reset_requested: bool,
+    pub tima_written: bool,
+    pub serial_transfer_requested: bool,
+    pub stat_written: bool,
 }
 
 impl IoRegs {
@@ -83,6 +87,10 @@ impl IoRegs {
             reg_datas,
 
             dma_requested: false,
+            div_reset_requested: false,
+            tima_written: false,
+            serial_transfer_requested: false,
+            stat_written: false,
         }
     }
 
@@ -95,7 +103,6 @@ impl IoRegs {
         };
 
         if let Some(reg) = IoReg::from_u16(addr) {
-            debug::record_io_reg_usage(reg, false, 0x00);
             let Some(reg_data) = self.reg_datas.get(&reg) else {
                 unreachable!();
             };
@@ -118,22 +125,24 @@ impl IoRegs {
     /// Writes to the writeable bits in the IO register.
     pub fn user_write(&mut self, addr: Addr, value: u8) {
         if addr == IoReg::Ie.as_addr() {
-            debug::record_io_reg_usage(IoReg::Ie, true, value);
             self.ie.write(addr, value);
         } else if let Some(reg) = IoReg::from_u16(addr) {
-            debug::record_io_reg_usage(reg, true, value);
             let Some(reg_data) = self.reg_datas.get(&reg) else {
                 unreachable!();
             };
 
             if reg == IoReg::Sc {
-                let serial_data = self.get(IoReg::Sb);
-                debug::push_serial_char(serial_data as char);
+                self.serial_transfer_requested = (value & 0x80) != 0;
             } else if reg == IoReg::Dma {
                 self.dma_requested = true;
+            } else if reg == IoReg::Tima {
+                self.tima_written = true;
+            } else if reg == IoReg::Stat {
+                self.stat_written = true;
             }
 
             if reg == IoReg::Div {
+                self.div_reset_requested = true;
                 self.mem.write(addr, 0x00);
             } else {
                 let data = self.mem.mut_(addr);
@@ -193,6 +202,7 @@ mod io_reg_data {
             };
 
             let write_mask = match reg {
+                IoReg::P1 => 0b0011_0000,
                 IoReg::If => 0b0001_1111,
                 IoReg::Stat => 0b1111_1000,
                 IoReg::Ly => 0b0000_0000,
@@ -1,4 +1,6 @@
-use crate::{cart::cart::Cart, consts::FAIL_ON_BAD_RW, debug};
+use std::cell::Cell;
+
+use crate::{cart::cart::Cart, consts::FAIL_ON_BAD_RW};
 
 use super::{array::Array, io_regs::IoRegs, sections::MemSection, Addr};
 
@@ -9,6 +11,8 @@ pub struct Mem {
     pub oam: Array,
     pub io_regs: IoRegs,
     pub hram: Array,
+    /// The last invalid access, when `FAIL_ON_BAD_RW` is set.
+    bad_access: Cell<Option<&'static str>>,
 }
 
 impl Mem {
@@ -20,10 +24,35 @@ impl Mem {
             oam: MemSection::into_array(MemSection::Oam),
             io_regs: IoRegs::new(),
             hram: MemSection::into_array(MemSection::Hram),
+            bad_access: Cell::new(None),
         }
     }
 
+    /// Returns the last invalid access since this was last called.
+    pub fn take_bad_access(&self) -> Option<&'static str> {
+        self.bad_access.take()
+    }
+
     pub fn read(&self, addr: Addr) -> u8 {
+        if FAIL_ON_BAD_RW {
+            match MemSection::from_abs_addr(addr) {
+                MemSection::EchoRam => {
+                    self.bad_access.set(Some("Attempted to read from Echo RAM"));
+                }
+                MemSection::UnusableMemory => {
+                    self.bad_access
+                        .set(Some("Attempted to read from unusable memory"));
+                }
+                _ => {}
+            }
+        }
+
+        self.peek(addr)
+    }
+
+    /// Reads like `read`, but never reports an invalid access. Used by
+    /// debugging code that inspects memory on the side.
+    pub fn peek(&self, addr: Addr) -> u8 {
         //println!("Addr = {} {:#04x}", addr, addr);
         let section = MemSection::from_abs_addr(addr);
         //println!("Rel Addr ({:?}) = {} {:#04x}", section, addr, addr);
@@ -33,19 +62,9 @@ impl Mem {
             MemSection::Vram => self.vram.read(addr),
             MemSection::ExtRam => self.cart.read(addr), // sys.ext_ram.rd(abs_addr),
             MemSection::Wram => self.wram.read(addr),
-            MemSection::EchoRam => {
-                if FAIL_ON_BAD_RW {
-                    debug::fail("Attempted to read from Echo RAM");
-                }
-                0x00
-            }
+            MemSection::EchoRam => 0x00,
             MemSection::Oam => self.oam.read(addr),
-            MemSection::UnusableMemory => {
-                if FAIL_ON_BAD_RW {
-                    debug::fail("Attempted to read from unusable memory");
-                }
-                0x00
-            }
+            MemSection::UnusableMemory => 0x00,
             MemSection::IoRegs => self.io_regs.user_read(addr),
             MemSection::Hram => self.hram.read(addr),
             MemSection::IeReg => self.io_regs.user_read(addr),
@@ -70,7 +89,7 @@ impl Mem {
             }
             MemSection::EchoRam => {
                 if FAIL_ON_BAD_RW {
-                    //debug::fail(sys, "Attempted to write to Echo RAM");
+                    self.bad_access.set(Some("Attempted to write to Echo RAM"));
                 }
             }
             MemSection::Oam => {
@@ -78,7 +97,8 @@ impl Mem {
             }
             MemSection::UnusableMemory => {
                 if FAIL_ON_BAD_RW {
-                    //debug::fail(sys, "Attempted to write to unusable memory");
+                    self.bad_access
+                        .set(Some("Attempted to write to unusable memory"));
                 }
             }
             MemSection::IoRegs => {
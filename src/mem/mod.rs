@@ -1,5 +1,7 @@
 mod addr;
-mod array;
+pub mod array;
+#[cfg(test)]
+pub mod bus;
 pub mod io_regs;
 pub mod mem;
 pub mod sections;
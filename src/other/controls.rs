@@ -0,0 +1,568 @@
+use std::{env, fs, path::PathBuf};
+
+use macroquad::input::{is_key_down, is_key_pressed, KeyCode};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use super::joypad::{Button, DEFAULT_TURBO_PERIOD_FRAMES};
+
+/// Something the user can do with the keyboard: press a Game Boy button, or
+/// use one of the emulator hotkeys.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, EnumIter)]
+pub enum Action {
+    Up,
+    Right,
+    Down,
+    Left,
+    B,
+    A,
+    Start,
+    Select,
+    TurboB,
+    TurboA,
+
+    RecordMacro,
+    Macro1,
+    Macro2,
+    Macro3,
+    Macro4,
+
+    Quit,
+    SaveState,
+    LoadState,
+    ToggleSpeedup,
+    ToggleWinMap,
+    OpenControls,
+}
+
+impl Action {
+    /// Returns the Game Boy button this action presses, if any.
+    pub fn button(self) -> Option<Button> {
+        match self {
+            Action::Up => Some(Button::Up),
+            Action::Right => Some(Button::Right),
+            Action::Down => Some(Button::Down),
+            Action::Left => Some(Button::Left),
+            Action::B => Some(Button::B),
+            Action::A => Some(Button::A),
+            Action::Start => Some(Button::Start),
+            Action::Select => Some(Button::Select),
+            _ => None,
+        }
+    }
+
+    /// The name shown on the controls screen.
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Up => "Up",
+            Action::Right => "Right",
+            Action::Down => "Down",
+            Action::Left => "Left",
+            Action::B => "B",
+            Action::A => "A",
+            Action::Start => "Start",
+            Action::Select => "Select",
+            Action::TurboB => "Turbo B",
+            Action::TurboA => "Turbo A",
+            Action::RecordMacro => "Record",
+            Action::Macro1 => "Macro 1",
+            Action::Macro2 => "Macro 2",
+            Action::Macro3 => "Macro 3",
+            Action::Macro4 => "Macro 4",
+            Action::Quit => "Quit",
+            Action::SaveState => "Save",
+            Action::LoadState => "Load",
+            Action::ToggleSpeedup => "Speedup",
+            Action::ToggleWinMap => "Map",
+            Action::OpenControls => "Controls",
+        }
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        let key = match self {
+            Action::Up => KeyCode::Up,
+            Action::Right => KeyCode::Right,
+            Action::Down => KeyCode::Down,
+            Action::Left => KeyCode::Left,
+            Action::B => KeyCode::Z,
+            Action::A => KeyCode::X,
+            Action::Start => KeyCode::Enter,
+            Action::Select => KeyCode::RightShift,
+            Action::TurboB => KeyCode::A,
+            Action::TurboA => KeyCode::S,
+            Action::RecordMacro => KeyCode::R,
+            Action::Macro1 => KeyCode::Key1,
+            Action::Macro2 => KeyCode::Key2,
+            Action::Macro3 => KeyCode::Key3,
+            Action::Macro4 => KeyCode::Key4,
+            Action::Quit => KeyCode::Escape,
+            Action::SaveState => KeyCode::Backspace,
+            Action::LoadState => KeyCode::Equal,
+            Action::ToggleSpeedup => KeyCode::Space,
+            Action::ToggleWinMap => KeyCode::T,
+            Action::OpenControls => KeyCode::F1,
+        };
+
+        vec![key]
+    }
+
+    /// Returns the Game Boy button this action presses with turbo, if any.
+    pub fn turbo_button(self) -> Option<Button> {
+        match self {
+            Action::TurboB => Some(Button::B),
+            Action::TurboA => Some(Button::A),
+            _ => None,
+        }
+    }
+
+    /// Returns the macro slot this action plays or records, if any.
+    pub fn macro_slot(self) -> Option<usize> {
+        match self {
+            Action::Macro1 => Some(0),
+            Action::Macro2 => Some(1),
+            Action::Macro3 => Some(2),
+            Action::Macro4 => Some(3),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Action::iter().find(|action| format!("{:?}", action) == name)
+    }
+}
+
+/// The keys that can be bound to actions.
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Semicolon,
+    KeyCode::Equal,
+    KeyCode::LeftBracket,
+    KeyCode::Backslash,
+    KeyCode::RightBracket,
+    KeyCode::GraveAccent,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpDecimal,
+    KeyCode::KpDivide,
+    KeyCode::KpMultiply,
+    KeyCode::KpSubtract,
+    KeyCode::KpAdd,
+    KeyCode::KpEnter,
+    KeyCode::LeftShift,
+    KeyCode::LeftControl,
+    KeyCode::LeftAlt,
+    KeyCode::RightShift,
+    KeyCode::RightControl,
+    KeyCode::RightAlt,
+];
+
+pub fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+/// Whether `key` can be bound to an action, and so saved to the config file.
+pub fn is_bindable(key: KeyCode) -> bool {
+    BINDABLE_KEYS.contains(&key)
+}
+
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|&key| key_name(key) == name)
+}
+
+/// Whose controls, saves and macros these are. The second player only plays
+/// when two systems are linked in the same window.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Player {
+    One,
+    Two,
+}
+
+impl Player {
+    /// Added to the names of the files each player has their own copy of.
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            Player::One => "",
+            Player::Two => "_p2",
+        }
+    }
+
+    fn file_name(self) -> String {
+        format!("controls{}.cfg", self.file_suffix())
+    }
+}
+
+/// The keys bound to each action. An action can have several keys, but a
+/// key can only be bound to one action.
+///
+/// The bindings are saved to a per-user config file, with one line per
+/// action, e.g. `A = X, K`, and a `TurboPeriod = <frames>` line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Controls {
+    /// Keys bound to each action, indexed by `Action as usize`.
+    bindings: Vec<Vec<KeyCode>>,
+    /// Frames per press of the turbo buttons.
+    pub turbo_period_frames: u32,
+    player: Player,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            bindings: Action::iter().map(Action::default_keys).collect(),
+            turbo_period_frames: DEFAULT_TURBO_PERIOD_FRAMES,
+            player: Player::One,
+        }
+    }
+}
+
+impl Controls {
+    /// Returns the default controls of `player`. The second player gets the
+    /// Game Boy buttons around IJKL, away from the first player's keys, and
+    /// no hotkeys.
+    pub fn default_for(player: Player) -> Self {
+        if player == Player::One {
+            return Self::default();
+        }
+
+        let mut controls = Self {
+            bindings: vec![Vec::new(); Action::iter().count()],
+            turbo_period_frames: DEFAULT_TURBO_PERIOD_FRAMES,
+            player,
+        };
+        let keys = [
+            (Action::Up, KeyCode::I),
+            (Action::Left, KeyCode::J),
+            (Action::Down, KeyCode::K),
+            (Action::Right, KeyCode::L),
+            (Action::B, KeyCode::N),
+            (Action::A, KeyCode::M),
+            (Action::Start, KeyCode::O),
+            (Action::Select, KeyCode::U),
+        ];
+        for (action, key) in keys {
+            controls.bindings[action as usize].push(key);
+        }
+
+        controls
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        &self.bindings[action as usize]
+    }
+
+    /// Returns the action `key` is bound to.
+    pub fn action_of(&self, key: KeyCode) -> Option<Action> {
+        Action::iter().find(|&action| self.keys(action).contains(&key))
+    }
+
+    /// Adds `key` to the keys of `action`. Fails if the key can't be bound,
+    /// or is already bound to another action.
+    pub fn bind(&mut self, action: Action, key: KeyCode) -> Result<(), String> {
+        if !is_bindable(key) {
+            return Err(format!("{} can't be bound", key_name(key)));
+        }
+
+        match self.action_of(key) {
+            Some(other) if other == action => Ok(()),
+            Some(other) => Err(format!(
+                "{} is already bound to {}",
+                key_name(key),
+                other.label()
+            )),
+            None => {
+                self.bindings[action as usize].push(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes all the keys of `action`.
+    pub fn clear(&mut self, action: Action) {
+        self.bindings[action as usize].clear();
+    }
+
+    /// Returns true if any key of `action` is held down.
+    pub fn is_down(&self, action: Action) -> bool {
+        self.keys(action).iter().any(|&key| is_key_down(key))
+    }
+
+    /// Returns true if any key of `action` was pressed this frame.
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.keys(action).iter().any(|&key| is_key_pressed(key))
+    }
+
+    /// Parses the controls config file of `player`. Actions that aren't
+    /// listed keep their default keys.
+    pub fn parse(text: &str, player: Player) -> Result<Self, String> {
+        let mut controls = Self::default_for(player);
+        let mut listed = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = |msg: String| format!("Line {}: {}", i + 1, msg);
+            let Some((name, keys)) = line.split_once('=') else {
+                return Err(err(format!("Expected '<action> = <keys>': {}", line)));
+            };
+            let name = name.trim();
+            if name == "TurboPeriod" {
+                controls.turbo_period_frames = match keys.trim().parse() {
+                    Ok(frames) if frames >= 2 => frames,
+                    _ => return Err(err(format!("Invalid turbo period: {}", keys.trim()))),
+                };
+                continue;
+            }
+
+            let action =
+                Action::from_name(name).ok_or_else(|| err(format!("Unknown action: {}", name)))?;
+            if listed.contains(&action) {
+                return Err(err(format!("{} is listed twice", name)));
+            }
+            listed.push(action);
+
+            controls.clear(action);
+            for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+                let key = key_from_name(key).ok_or_else(|| err(format!("Unknown key: {}", key)))?;
+                controls.bindings[action as usize].push(key);
+            }
+        }
+
+        controls.check_conflicts()?;
+
+        Ok(controls)
+    }
+
+    /// Fails if a key is bound to more than one action, e.g. because an
+    /// action left out of the config file kept a default key.
+    fn check_conflicts(&self) -> Result<(), String> {
+        for action in Action::iter() {
+            for &key in self.keys(action) {
+                let first = self.action_of(key).unwrap();
+                if first != action {
+                    return Err(format!(
+                        "{} is bound to both {:?} and {:?}",
+                        key_name(key),
+                        first,
+                        action
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_config_string(&self) -> String {
+        let mut text = String::from("# Keys bound to each action, separated by commas.\n");
+        for action in Action::iter() {
+            let keys: Vec<_> = self.keys(action).iter().map(|&key| key_name(key)).collect();
+            text += &format!("{:?} = {}\n", action, keys.join(", "));
+        }
+        text += &format!("TurboPeriod = {}\n", self.turbo_period_frames);
+
+        text
+    }
+
+    /// Returns a message for each key that is bound in both `self` and
+    /// `other`, since it would press both players' buttons at once.
+    pub fn conflicts_with(&self, other: &Controls) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for action in Action::iter() {
+            for &key in self.keys(action) {
+                if let Some(other_action) = other.action_of(key) {
+                    conflicts.push(format!(
+                        "{} is bound to {:?} for {:?} and to {:?} for {:?}",
+                        key_name(key),
+                        action,
+                        self.player,
+                        other_action,
+                        other.player
+                    ));
+                }
+            }
+        }
+
+        conflicts
+    }
+
+    /// Loads the controls of `player` from the user's config file. Falls
+    /// back to the defaults if there is no config file or it is invalid.
+    pub fn load(player: Player) -> Self {
+        let Some(path) = config_path(&player.file_name()) else {
+            return Self::default_for(player);
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default_for(player);
+        };
+
+        match Self::parse(&text, player) {
+            Ok(controls) => controls,
+            Err(msg) => {
+                println!("Invalid controls config {:?}: {}", path, msg);
+                println!("Using the default controls.");
+                Self::default_for(player)
+            }
+        }
+    }
+
+    /// Saves the controls to the user's config file.
+    pub fn save(&self) -> Result<(), String> {
+        let path =
+            config_path(&self.player.file_name()).ok_or("Unable to find the user config folder")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Unable to create {:?}: {}", dir, e))?;
+        }
+        fs::write(&path, self.to_config_string())
+            .map_err(|e| format!("Unable to write {:?}: {}", path, e))?;
+
+        println!("Saved controls to: {:?}", path);
+
+        Ok(())
+    }
+}
+
+/// Returns the path of a config file in the user's config folder.
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    let config_dir = env::var_os("APPDATA")
+        .or_else(|| env::var_os("XDG_CONFIG_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("rust_gb_emu").join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut controls = Controls::default();
+        controls.bind(Action::A, KeyCode::K).unwrap();
+        controls.clear(Action::Quit);
+        controls.turbo_period_frames = 6;
+
+        let parsed = Controls::parse(&controls.to_config_string(), Player::One).unwrap();
+        assert_eq!(parsed, controls);
+        assert_eq!(parsed.keys(Action::A), &[KeyCode::X, KeyCode::K]);
+        assert!(parsed.keys(Action::Quit).is_empty());
+    }
+
+    #[test]
+    fn test_unlisted_actions_keep_defaults() {
+        let controls = Controls::parse("# Comment\nB = J\n\nA = K, L\n", Player::One).unwrap();
+        assert_eq!(controls.keys(Action::B), &[KeyCode::J]);
+        assert_eq!(controls.keys(Action::A), &[KeyCode::K, KeyCode::L]);
+        assert_eq!(controls.keys(Action::Start), &[KeyCode::Enter]);
+        assert_eq!(controls.action_of(KeyCode::L), Some(Action::A));
+    }
+
+    #[test]
+    fn test_player_keymaps_dont_overlap() {
+        let player_1 = Controls::default();
+        let player_2 = Controls::default_for(Player::Two);
+        assert!(player_1.conflicts_with(&player_2).is_empty());
+
+        let player_2 = Controls::parse("A = X", Player::Two).unwrap();
+        assert_eq!(player_2.keys(Action::Start), &[KeyCode::O]);
+        assert_eq!(player_1.conflicts_with(&player_2).len(), 1);
+    }
+
+    #[test]
+    fn test_conflicts_are_rejected() {
+        let mut controls = Controls::default();
+        assert!(controls.bind(Action::A, KeyCode::Z).is_err());
+        assert!(controls.bind(Action::A, KeyCode::X).is_ok());
+        assert_eq!(controls.keys(Action::A), &[KeyCode::X]);
+        assert!(controls.bind(Action::A, KeyCode::CapsLock).is_err());
+        assert_eq!(controls.keys(Action::A), &[KeyCode::X]);
+
+        // Z is still bound to B by default.
+        assert!(Controls::parse("A = Z", Player::One).is_err());
+        assert!(Controls::parse("A = Z\nB = X", Player::One).is_ok());
+        assert!(Controls::parse("A = Nope", Player::One).is_err());
+        assert!(Controls::parse("Jump = Z", Player::One).is_err());
+        assert!(Controls::parse("TurboPeriod = 1", Player::One).is_err());
+    }
+}
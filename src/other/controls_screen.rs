@@ -0,0 +1,123 @@
+use macroquad::{
+    color::{BLACK, DARKBLUE},
+    input::{get_last_key_pressed, is_key_pressed, KeyCode},
+};
+use strum::IntoEnumIterator;
+use xf::{
+    mq::draw::draw_rect,
+    num::{
+        irect::ir,
+        ivec2::{i2, IVec2},
+    },
+};
+
+use crate::{
+    consts::P8,
+    ppu::{consts::VIEWPORT_P8_SIZE, text::draw_text},
+};
+
+use super::controls::{is_bindable, key_name, Action, Controls};
+
+/// Width (in characters) of the action names column.
+const LABEL_WIDTH: usize = 9;
+/// How many actions fit between the title and the help lines.
+const VISIBLE_ACTIONS: usize = VIEWPORT_P8_SIZE.y as usize - 3;
+
+/// An in-window screen for rebinding the controls.
+///
+/// Its own keys are fixed so it can't be locked out of: Up/Down select an
+/// action, Enter waits for a key to add to it, Delete clears its keys, and
+/// Escape saves the controls and closes the screen.
+#[derive(Default)]
+pub struct ControlsScreen {
+    is_open: bool,
+    selected: usize,
+    is_waiting_for_key: bool,
+    message: Option<String>,
+}
+
+impl ControlsScreen {
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    pub fn open(&mut self) {
+        self.is_open = true;
+        self.selected = 0;
+        self.is_waiting_for_key = false;
+        self.message = None;
+    }
+
+    /// Handles this frame's key presses.
+    pub fn update(&mut self, controls: &mut Controls) {
+        let action = Action::iter().nth(self.selected).unwrap();
+
+        if self.is_waiting_for_key {
+            // Keys that can't be saved (e.g. CapsLock) are ignored.
+            let Some(key) = get_last_key_pressed().filter(|&key| is_bindable(key)) else {
+                return;
+            };
+            self.is_waiting_for_key = false;
+            self.message = match key {
+                KeyCode::Escape => None,
+                _ => controls.bind(action, key).err(),
+            };
+            return;
+        }
+
+        let action_count = Action::iter().count();
+        if is_key_pressed(KeyCode::Up) {
+            self.selected = (self.selected + action_count - 1) % action_count;
+            self.message = None;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1) % action_count;
+            self.message = None;
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            self.is_waiting_for_key = true;
+            self.message = Some(String::from("Press a key..."));
+        }
+        if is_key_pressed(KeyCode::Delete) {
+            controls.clear(action);
+            self.message = None;
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            if let Err(msg) = controls.save() {
+                println!("{}", msg);
+            }
+            self.is_open = false;
+        }
+    }
+
+    /// Draws the screen over the viewport.
+    pub fn draw(&self, controls: &Controls, org: IVec2) {
+        draw_rect(ir(org, VIEWPORT_P8_SIZE * P8), BLACK);
+        draw_text("CONTROLS", org);
+
+        let width = VIEWPORT_P8_SIZE.x as usize;
+        // Scroll the list so the selected action is visible.
+        let first = (self.selected + 1).saturating_sub(VISIBLE_ACTIONS);
+        for (i, action) in Action::iter().enumerate().skip(first).take(VISIBLE_ACTIONS) {
+            let pos = org + i2(0, (i - first) as i32 + 1) * P8;
+            if i == self.selected {
+                draw_rect(ir(pos, i2(VIEWPORT_P8_SIZE.x, 1) * P8), DARKBLUE);
+            }
+
+            let keys: Vec<_> = controls
+                .keys(action)
+                .iter()
+                .map(|&key| key_name(key))
+                .collect();
+            let line = format!("{:<w$}{}", action.label(), keys.join(","), w = LABEL_WIDTH);
+            draw_text(line.chars().take(width).collect::<String>(), pos);
+        }
+
+        let bottom = org + i2(0, VIEWPORT_P8_SIZE.y - 2) * P8;
+        match &self.message {
+            Some(msg) => draw_text(msg, bottom),
+            None => draw_text("Enter add, Del clear", bottom),
+        }
+        draw_text("Esc save and close", bottom + i2(0, 1) * P8);
+    }
+}
@@ -1,11 +1,29 @@
+use super::{controls::Player, joypad::DEFAULT_TURBO_PERIOD_FRAMES};
+
 // Emulator user settings.
-#[derive(Default)]
 pub struct Emu {
     pub is_speedup_enabled: bool,
 
     /// False: the background tilemap is shown.
     /// True: the window tilemap is shown.
     pub show_win_map: bool,
+
+    /// Frames per press of the turbo buttons.
+    pub turbo_period_frames: u32,
+
+    /// Whose save and macro files are used.
+    pub player: Player,
+}
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self {
+            is_speedup_enabled: false,
+            show_win_map: false,
+            turbo_period_frames: DEFAULT_TURBO_PERIOD_FRAMES,
+            player: Player::One,
+        }
+    }
 }
 
 impl Emu {
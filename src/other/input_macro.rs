@@ -0,0 +1,288 @@
+use std::fs;
+
+use super::{
+    controls::{config_path, Player},
+    joypad::JoypadState,
+};
+
+/// How many macros can be recorded, each triggered by its own hotkey.
+pub const MACRO_SLOTS: usize = 4;
+
+/// Records and plays back input macros: sequences of joypad states, one per
+/// frame.
+///
+/// Pressing the record hotkey, then a macro hotkey, starts recording that
+/// macro. Pressing either hotkey again stops it. What gets recorded is what
+/// the game saw, so turbo buttons and other macros played meanwhile are
+/// part of the recording. Pressing a macro hotkey otherwise plays it back,
+/// with its buttons held down on top of the user's.
+#[derive(Default)]
+pub struct InputMacros {
+    slots: [Vec<JoypadState>; MACRO_SLOTS],
+    /// The frame that was last handled.
+    frame: u64,
+    /// Whether the next macro hotkey starts recording.
+    is_armed: bool,
+    recording: Option<(usize, Vec<JoypadState>)>,
+    /// The macro being played, and which of its frames is the current one.
+    playing: Option<(usize, usize)>,
+}
+
+impl InputMacros {
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Moves on to `frame`. `finished_frame` is the joypad state the game
+    /// saw during the previous frame, and the keys are the hotkeys the user
+    /// pressed since.
+    pub fn next_frame(
+        &mut self,
+        frame: u64,
+        finished_frame: JoypadState,
+        macro_key: Option<usize>,
+        record_key: bool,
+    ) {
+        self.frame = frame;
+
+        if let Some((_, frames)) = &mut self.recording {
+            frames.push(finished_frame);
+        }
+
+        if let Some((slot, next)) = &mut self.playing {
+            *next += 1;
+            if *next >= self.slots[*slot].len() {
+                self.playing = None;
+            }
+        }
+
+        if let Some((slot, _)) = &self.recording {
+            if record_key || macro_key == Some(*slot) {
+                let (slot, frames) = self.recording.take().unwrap();
+                println!("Recorded macro {} ({} frames).", slot + 1, frames.len());
+                self.slots[slot] = frames;
+                return;
+            }
+        } else if record_key {
+            self.is_armed = !self.is_armed;
+            return;
+        }
+
+        let Some(slot) = macro_key.filter(|&slot| slot < MACRO_SLOTS) else {
+            return;
+        };
+        if self.is_armed {
+            self.is_armed = false;
+            self.recording = Some((slot, Vec::new()));
+        } else if !self.slots[slot].is_empty() {
+            self.playing = Some((slot, 0));
+        }
+    }
+
+    /// Returns the current frame of the macro being played.
+    pub fn playing_joypad(&self) -> Option<JoypadState> {
+        let (slot, next) = self.playing?;
+        self.slots[slot].get(next).copied()
+    }
+
+    /// Loads the macros of `player` from the user's config file, if there
+    /// is one.
+    pub fn load(&mut self, player: Player) {
+        let Some(path) = config_path(&macros_file_name(player)) else {
+            return;
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return;
+        };
+
+        match parse_macros(&text) {
+            Ok(slots) => self.slots = slots,
+            Err(msg) => println!("Invalid macros file {:?}: {}", path, msg),
+        }
+    }
+
+    /// Saves the macros of `player` to the user's config file.
+    pub fn save(&self, player: Player) -> Result<(), String> {
+        let path = config_path(&macros_file_name(player))
+            .ok_or("Unable to find the user config folder")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Unable to create {:?}: {}", dir, e))?;
+        }
+        fs::write(&path, macros_to_string(&self.slots))
+            .map_err(|e| format!("Unable to write {:?}: {}", path, e))
+    }
+}
+
+fn macros_file_name(player: Player) -> String {
+    format!("macros{}.cfg", player.file_suffix())
+}
+
+/// Writes one line per macro: its number, then the buttons held on each
+/// frame as hex bytes.
+fn macros_to_string(slots: &[Vec<JoypadState>; MACRO_SLOTS]) -> String {
+    let mut text = String::new();
+    for (slot, frames) in slots.iter().enumerate() {
+        let frames: Vec<_> = frames
+            .iter()
+            .map(|joypad| format!("{:0>2X}", joypad.bits()))
+            .collect();
+        text += &format!("{} = {}\n", slot + 1, frames.join(" "));
+    }
+
+    text
+}
+
+fn parse_macros(text: &str) -> Result<[Vec<JoypadState>; MACRO_SLOTS], String> {
+    let mut slots: [Vec<JoypadState>; MACRO_SLOTS] = Default::default();
+
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((number, frames)) = line.split_once('=') else {
+            return Err(format!("Expected '<macro> = <frames>': {}", line));
+        };
+        let slot = match number.trim().parse::<usize>() {
+            Ok(number) if (1..=MACRO_SLOTS).contains(&number) => number - 1,
+            _ => return Err(format!("Invalid macro number: {}", number.trim())),
+        };
+
+        slots[slot] = frames
+            .split_whitespace()
+            .map(|frame| {
+                u8::from_str_radix(frame, 16)
+                    .map(JoypadState::from_bits)
+                    .map_err(|_| format!("Invalid frame: {}", frame))
+            })
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mem::io_regs::IoReg,
+        other::joypad::{Button, InputSource, InputState},
+        sys::Sys,
+        test::program::idle_sys,
+    };
+
+    use super::*;
+
+    /// Gives the same input on every frame, with hotkeys on a few frames.
+    struct Script {
+        held: JoypadState,
+        turbo: JoypadState,
+        hotkeys: Vec<(u64, Option<usize>, bool)>,
+    }
+
+    impl InputSource for Script {
+        fn input_state(&mut self, frame: u64) -> InputState {
+            let (macro_key, record_key) = self
+                .hotkeys
+                .iter()
+                .find(|(f, _, _)| *f == frame)
+                .map(|&(_, macro_key, record_key)| (macro_key, record_key))
+                .unwrap_or_default();
+            InputState {
+                held: self.held,
+                turbo: self.turbo,
+                macro_key,
+                record_key,
+            }
+        }
+    }
+
+    /// Runs the emulator until the next VBlank, like the frontend does, and
+    /// returns the joypad state the game saw at its end. The input is given
+    /// at the previous VBlank, so it applies from the next frame.
+    fn run_frame(sys: &mut Sys, script: &mut Script) -> JoypadState {
+        sys.update_input(script);
+        sys.run_frames(1);
+
+        sys.joypad
+    }
+
+    fn lcd_sys() -> Sys {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x91);
+        sys
+    }
+
+    #[test]
+    fn test_turbo_toggles_every_half_period() {
+        let mut sys = lcd_sys();
+        sys.emu.turbo_period_frames = 4;
+        let mut script = Script {
+            held: JoypadState::default(),
+            turbo: JoypadState::default().with(Button::A),
+            hotkeys: Vec::new(),
+        };
+
+        let pressed: Vec<_> = (0..8)
+            .map(|_| run_frame(&mut sys, &mut script).is_pressed(Button::A))
+            .collect();
+        assert_eq!(
+            pressed,
+            [true, true, false, false, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_macro_records_and_plays_back() {
+        let mut sys = lcd_sys();
+        let a = JoypadState::default().with(Button::A);
+        let mut script = Script {
+            held: a,
+            turbo: JoypadState::default(),
+            hotkeys: vec![(0, None, true), (1, Some(2), false), (4, Some(2), false)],
+        };
+
+        // Record 3 frames with A held.
+        for _ in 0..6 {
+            run_frame(&mut sys, &mut script);
+        }
+        assert!(!sys.input_macros.is_recording());
+        assert_eq!(sys.input_macros.slots[2], [a, a, a]);
+
+        // Play it back with nothing held.
+        script.held = JoypadState::default();
+        script.hotkeys = vec![(6, Some(2), false)];
+        let frames: Vec<_> = (0..5).map(|_| run_frame(&mut sys, &mut script)).collect();
+        let none = JoypadState::default();
+        assert_eq!(frames, [none, a, a, a, none]);
+    }
+
+    #[test]
+    fn test_hotkeys_apply_once_when_sped_up() {
+        let mut sys = lcd_sys();
+
+        // At 2x speed, the input is read once for two frames, like the
+        // keyboard hotkeys are during one frame of the frontend.
+        sys.input.record_key = true;
+        sys.run_frames(2);
+        assert!(sys.input_macros.is_armed);
+
+        sys.input.macro_key = Some(1);
+        sys.run_frames(2);
+        assert!(sys.input_macros.is_recording());
+    }
+
+    #[test]
+    fn test_macros_file_round_trip() {
+        let mut slots: [Vec<JoypadState>; MACRO_SLOTS] = Default::default();
+        slots[0] = vec![
+            JoypadState::default().with(Button::Start),
+            JoypadState::default(),
+        ];
+        slots[3] = vec![JoypadState::default().with(Button::A).with(Button::Up)];
+
+        let text = macros_to_string(&slots);
+        assert_eq!(parse_macros(&text).unwrap(), slots);
+        assert!(parse_macros("5 = 00").is_err());
+        assert!(parse_macros("1 = ZZ").is_err());
+    }
+}
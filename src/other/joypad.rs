@@ -1,7 +1,5 @@
-use macroquad::{
-    color::{BLACK, RED, WHITE},
-    input::{is_key_down, KeyCode},
-};
+use macroquad::color::{BLACK, RED, WHITE};
+use strum_macros::EnumIter;
 use xf::{
     mq::draw::draw_rect,
     num::{
@@ -12,17 +10,18 @@ use xf::{
 
 use crate::{
     consts::P8,
+    cpu::interrupt::{request_interrupt, InterruptType},
     mem::io_regs::IoReg,
     sys::Sys,
     util::{
         draw::draw_empty_rect,
-        math::{bit8, set_bit8, set_bits8},
+        math::{bit8, bits8, set_bit8, set_bits8},
     },
 };
 
 /// Represents a Game Boy button.
-#[derive(Clone, Copy)]
-enum Button {
+#[derive(Clone, Copy, PartialEq, Eq, Debug, EnumIter)]
+pub enum Button {
     Up,
     Right,
     Down,
@@ -35,39 +34,169 @@ enum Button {
     Select,
 }
 
-impl Button {
-    pub fn key_code(self) -> KeyCode {
-        match self {
-            Button::Up => KeyCode::Up,
-            Button::Right => KeyCode::Right,
-            Button::Down => KeyCode::Down,
-            Button::Left => KeyCode::Left,
+/// Which Game Boy buttons are held down.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct JoypadState {
+    pressed: u8,
+}
+
+impl JoypadState {
+    pub fn is_pressed(&self, button: Button) -> bool {
+        (self.pressed & (1 << button as u8)) != 0
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let mask = 1 << button as u8;
+        if pressed {
+            self.pressed |= mask;
+        } else {
+            self.pressed &= !mask;
+        }
+    }
 
-            Button::B => KeyCode::Z,
-            Button::A => KeyCode::X,
+    /// Returns one bit per button, in the order of `Button`.
+    pub fn bits(&self) -> u8 {
+        self.pressed
+    }
+
+    pub fn from_bits(pressed: u8) -> Self {
+        Self { pressed }
+    }
 
-            Button::Start => KeyCode::Enter,
-            Button::Select => KeyCode::RightShift,
+    /// Returns the buttons held down in either state.
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            pressed: self.pressed | other.pressed,
         }
     }
+
+    /// Returns this state with `button` held down too.
+    #[cfg(test)]
+    pub fn with(mut self, button: Button) -> Self {
+        self.set(button, true);
+        self
+    }
+}
+
+/// M-cycles that the P1 input lines take to settle after the select lines
+/// change.
+const SELECT_SETTLE_MCYCLES: u8 = 1;
+
+/// The state of the P1 input lines.
+#[derive(Default)]
+pub struct JoypadLines {
+    /// The select bits of P1 that the lines were last read with.
+    select: u8,
+    settle_m_cycles: u8,
+}
+
+/// Frames per turbo cycle by default: held for half of them, released for
+/// the other half (15 presses per second).
+pub const DEFAULT_TURBO_PERIOD_FRAMES: u32 = 4;
+
+/// What the user is doing with the controls on a frame.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InputState {
+    /// The buttons held down.
+    pub held: JoypadState,
+    /// The buttons held down with turbo, which are pressed and released
+    /// every few frames.
+    pub turbo: JoypadState,
+    /// The macro slot whose hotkey was pressed.
+    pub macro_key: Option<usize>,
+    /// Whether the record macro hotkey was pressed.
+    pub record_key: bool,
+}
+
+/// Provides the input state for each frame, e.g. from the keyboard, a
+/// script or a recording.
+pub trait InputSource {
+    fn input_state(&mut self, frame: u64) -> InputState;
+}
+
+/// Updates the buttons the game sees held down: the ones held by the user,
+/// turbo buttons on their pressed frames, and the frame of the macro being
+/// played.
+fn update_joypad(sys: &mut Sys) {
+    let frame = sys.ppu.total_frames_drawn();
+    if sys.input_macros.frame() != frame {
+        // Done once per frame, so macros play and record frame by frame.
+        let finished_frame = sys.joypad;
+        let macro_key = std::mem::take(&mut sys.input.macro_key);
+        let record_key = std::mem::take(&mut sys.input.record_key);
+        sys.input_macros
+            .next_frame(frame, finished_frame, macro_key, record_key);
+    }
+
+    sys.joypad = held_joypad(sys, frame);
+}
+
+fn held_joypad(sys: &Sys, frame: u64) -> JoypadState {
+    let mut joypad = sys.input.held;
+
+    let period = sys.emu.turbo_period_frames.max(2) as u64;
+    if frame % period < period / 2 {
+        joypad = joypad.union(sys.input.turbo);
+    }
+
+    if let Some(macro_joypad) = sys.input_macros.playing_joypad() {
+        joypad = joypad.union(macro_joypad);
+    }
+
+    joypad
+}
+
+/// Returns the levels of the P1 input lines (0 = pressed) for the selected
+/// button groups.
+fn input_lines(joypad: &JoypadState, p1: u8) -> u8 {
+    // With both groups selected, a line is low if a button from either is pressed.
+    let mut lo_4 = 0xF;
+    if bit8(&p1, 5) == 0 {
+        read_button(joypad, &mut lo_4, 0, Button::A);
+        read_button(joypad, &mut lo_4, 1, Button::B);
+        read_button(joypad, &mut lo_4, 2, Button::Select);
+        read_button(joypad, &mut lo_4, 3, Button::Start);
+    }
+
+    if bit8(&p1, 4) == 0 {
+        read_button(joypad, &mut lo_4, 0, Button::Right);
+        read_button(joypad, &mut lo_4, 1, Button::Left);
+        read_button(joypad, &mut lo_4, 2, Button::Up);
+        read_button(joypad, &mut lo_4, 3, Button::Down);
+    }
+
+    lo_4
 }
 
-pub fn draw_joypad_state(org: IVec2) {
-    draw_button(Button::Up, i2(4, 1), org);
-    draw_button(Button::Right, i2(5, 2), org);
-    draw_button(Button::Down, i2(4, 3), org);
-    draw_button(Button::Left, i2(3, 2), org);
+/// Whether `handle_joypad_inputs` would leave the joypad, P1 and IF as they
+/// are, so it can be skipped for a while.
+pub fn is_joypad_idle(sys: &Sys) -> bool {
+    let frame = sys.ppu.total_frames_drawn();
+    let p1 = sys.mem.io_regs.get(IoReg::P1);
+
+    sys.input_macros.frame() == frame
+        && held_joypad(sys, frame) == sys.joypad
+        && sys.joypad_lines.settle_m_cycles == 0
+        && (p1 & 0b0011_0000) == sys.joypad_lines.select
+        && input_lines(&sys.joypad, p1) == bits8(&p1, 3, 0)
+}
 
-    draw_button(Button::B, i2(13, 3), org);
-    draw_button(Button::A, i2(14, 2), org);
+pub fn draw_joypad_state(joypad: &JoypadState, org: IVec2) {
+    draw_button(joypad, Button::Up, i2(4, 1), org);
+    draw_button(joypad, Button::Right, i2(5, 2), org);
+    draw_button(joypad, Button::Down, i2(4, 3), org);
+    draw_button(joypad, Button::Left, i2(3, 2), org);
 
-    draw_button(Button::Start, i2(8, 4), org);
-    draw_button(Button::Select, i2(10, 4), org);
+    draw_button(joypad, Button::B, i2(13, 3), org);
+    draw_button(joypad, Button::A, i2(14, 2), org);
+
+    draw_button(joypad, Button::Start, i2(8, 4), org);
+    draw_button(joypad, Button::Select, i2(10, 4), org);
 }
 
-fn draw_button(button: Button, pos: IVec2, org: IVec2) {
+fn draw_button(joypad: &JoypadState, button: Button, pos: IVec2, org: IVec2) {
     let bounds = ir(org + (pos * P8), P8);
-    if is_key_down(button.key_code()) {
+    if joypad.is_pressed(button) {
         draw_rect(bounds, RED);
     } else {
         draw_rect(bounds, BLACK);
@@ -76,34 +205,160 @@ fn draw_button(button: Button, pos: IVec2, org: IVec2) {
 }
 
 pub fn handle_joypad_inputs(sys: &mut Sys) {
+    // P1: .7-6: unused (read 1); .5: select buttons; .4: select d-pad; .3-0: input lines (0 = pressed);
+
+    update_joypad(sys);
+
     let p1 = sys.mem.io_regs.get(IoReg::P1);
-    let select_btns = bit8(&p1, 5) == 0;
-    let select_dpad = bit8(&p1, 4) == 0;
+    let select = p1 & 0b0011_0000;
+    if select != sys.joypad_lines.select {
+        sys.joypad_lines.select = select;
+        sys.joypad_lines.settle_m_cycles = SELECT_SETTLE_MCYCLES;
+    }
 
-    let mut lo_4 = 0xF;
-    if select_btns {
-        read_button(&mut lo_4, 0, Button::A);
-        read_button(&mut lo_4, 1, Button::B);
-        read_button(&mut lo_4, 2, Button::Select);
-        read_button(&mut lo_4, 3, Button::Start);
+    // The input lines keep their old levels until they settle.
+    if sys.joypad_lines.settle_m_cycles > 0 {
+        sys.joypad_lines.settle_m_cycles -= 1;
+        return;
     }
 
-    if select_dpad {
-        read_button(&mut lo_4, 0, Button::Right);
-        read_button(&mut lo_4, 1, Button::Left);
-        read_button(&mut lo_4, 2, Button::Up);
-        read_button(&mut lo_4, 3, Button::Down);
+    let lo_4 = input_lines(&sys.joypad, p1);
+
+    // Any line going from high to low requests the interrupt.
+    let prev_lo_4 = bits8(&p1, 3, 0);
+    if (prev_lo_4 & !lo_4) != 0 {
+        request_interrupt(sys, InterruptType::Joypad);
     }
 
     sys.mem.io_regs.mut_(IoReg::P1, |p1| {
+        set_bits8(p1, 7, 6, 0b11);
         set_bits8(p1, 3, 0, lo_4);
     });
 }
 
-fn read_button(p1: &mut u8, idx: u8, button: Button) {
-    let value = if is_key_down(button.key_code()) { 0 } else { 1 };
+fn read_button(joypad: &JoypadState, p1: &mut u8, idx: u8, button: Button) {
+    let value = if joypad.is_pressed(button) { 0 } else { 1 };
     let mut mask = 0xFF;
     set_bit8(&mut mask, 0, value);
     mask = u8::rotate_left(mask, idx as u32);
     *p1 &= mask;
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::regs::CpuReg8,
+        test::program::{idle_sys, run_until_label, sys_from_asm},
+    };
+
+    use super::*;
+
+    /// Presses the buttons from a list, one entry per frame.
+    struct Script(Vec<JoypadState>);
+
+    impl InputSource for Script {
+        fn input_state(&mut self, frame: u64) -> InputState {
+            InputState {
+                held: self.0.get(frame as usize).copied().unwrap_or_default(),
+                ..Default::default()
+            }
+        }
+    }
+
+    fn select(sys: &mut Sys, p1: u8) {
+        sys.write(IoReg::P1.as_addr(), p1);
+        handle_joypad_inputs(sys);
+        handle_joypad_inputs(sys);
+    }
+
+    fn p1_lines(sys: &Sys) -> u8 {
+        sys.read(IoReg::P1.as_addr()) & 0x0F
+    }
+
+    fn joypad_irq_requested(sys: &Sys) -> bool {
+        bit8(&sys.mem.io_regs.get(IoReg::If), 4) == 1
+    }
+
+    #[test]
+    fn test_joypad_interrupt_on_falling_line() {
+        let mut sys = idle_sys();
+        select(&mut sys, 0x10);
+        sys.mem.io_regs.set(IoReg::If, 0x00);
+
+        sys.input.held = JoypadState::default().with(Button::Down);
+        handle_joypad_inputs(&mut sys);
+        assert!(!joypad_irq_requested(&sys));
+
+        sys.input.held = JoypadState::default().with(Button::Start);
+        handle_joypad_inputs(&mut sys);
+        assert!(joypad_irq_requested(&sys));
+
+        // Releasing doesn't request it.
+        sys.mem.io_regs.set(IoReg::If, 0x00);
+        sys.input.held = JoypadState::default();
+        handle_joypad_inputs(&mut sys);
+        assert!(!joypad_irq_requested(&sys));
+    }
+
+    #[test]
+    fn test_p1_select_lines() {
+        let mut sys = idle_sys();
+        sys.input.held = JoypadState::default().with(Button::A).with(Button::Left);
+
+        // Both groups selected.
+        select(&mut sys, 0x00);
+        assert_eq!(p1_lines(&sys), 0b1100);
+        // Unused bits read 1, and the input lines can't be written.
+        assert_eq!(sys.read(IoReg::P1.as_addr()) & 0xF0, 0xC0);
+
+        // Neither selected.
+        select(&mut sys, 0x3F);
+        assert_eq!(p1_lines(&sys), 0b1111);
+    }
+
+    #[test]
+    fn test_p1_lines_settle_after_select() {
+        let mut sys = idle_sys();
+        sys.input.held = JoypadState::default().with(Button::B);
+        select(&mut sys, 0x20);
+        assert_eq!(p1_lines(&sys), 0b1111);
+
+        sys.write(IoReg::P1.as_addr(), 0x10);
+        handle_joypad_inputs(&mut sys);
+        assert_eq!(p1_lines(&sys), 0b1111);
+        handle_joypad_inputs(&mut sys);
+        assert_eq!(p1_lines(&sys), 0b1101);
+    }
+
+    #[test]
+    fn test_joypad_state_is_read_through_p1() {
+        let src = "
+            SECTION \"Entry\", ROM0[$100]
+                nop
+                jp Main
+
+            SECTION \"Main\", ROM0[$150]
+            Main:
+                ld a, $10
+                ldh [$00], a
+                nop
+                ldh a, [$00]
+                ld b, a
+                ld a, $20
+                ldh [$00], a
+                nop
+                ldh a, [$00]
+            Done:
+                jr Done
+        ";
+        let (mut sys, program) = sys_from_asm(src);
+        let mut script = Script(vec![JoypadState::default()
+            .with(Button::A)
+            .with(Button::Down)]);
+        sys.update_input(&mut script);
+
+        assert!(run_until_label(&mut sys, &program, "Done", 100));
+        assert_eq!(sys.regs.get_8(CpuReg8::B) & 0x0F, 0b1110);
+        assert_eq!(sys.regs.get_8(CpuReg8::A) & 0x0F, 0b0111);
+    }
+}
@@ -0,0 +1,39 @@
+use strum::IntoEnumIterator;
+
+use super::{
+    controls::{Action, Controls},
+    joypad::{InputSource, InputState},
+};
+
+/// Reads the joypad state from the keyboard of the emulator window.
+pub struct KeyboardInput {
+    pub controls: Controls,
+}
+
+impl KeyboardInput {
+    pub fn new(controls: Controls) -> Self {
+        Self { controls }
+    }
+}
+
+impl InputSource for KeyboardInput {
+    fn input_state(&mut self, _frame: u64) -> InputState {
+        let mut input = InputState::default();
+        for action in Action::iter() {
+            if let Some(button) = action.button() {
+                input.held.set(button, self.controls.is_down(action));
+            }
+            if let Some(button) = action.turbo_button() {
+                input.turbo.set(button, self.controls.is_down(action));
+            }
+            if let Some(slot) = action.macro_slot() {
+                if self.controls.is_pressed(action) {
+                    input.macro_key = Some(slot);
+                }
+            }
+        }
+        input.record_key = self.controls.is_pressed(Action::RecordMacro);
+
+        input
+    }
+}
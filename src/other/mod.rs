@@ -1,3 +1,7 @@
+pub mod controls;
+pub mod controls_screen;
 pub mod emu;
+pub mod input_macro;
 pub mod joypad;
+pub mod keyboard;
 pub mod save;
@@ -4,12 +4,18 @@ use crate::{sys::Sys, util::slice::copy_from_safe};
 
 const SAVE_FOLDER_PATH: &str = "C:\\Users\\matth\\Desktop";
 
+/// Returns the save file of the currently running game, for the player of `sys`.
+fn save_path(sys: &Sys) -> String {
+    let file_name = sys.mem.cart.header().title();
+    let suffix = sys.emu.player.file_suffix();
+    format!("{}\\{}{}.sav", SAVE_FOLDER_PATH, file_name, suffix)
+}
+
 /// Saves the contents of cartridge RAM to a file named after the currently running game.
 pub fn save_state(sys: &Sys) {
     let cart_ram = sys.mem.cart.ram();
 
-    let file_name = sys.mem.cart.header().title();
-    let path = format!("{}\\{}.sav", SAVE_FOLDER_PATH, file_name);
+    let path = save_path(sys);
     println!("{}", path);
     let path = Path::new(&path);
 
@@ -26,8 +32,7 @@ pub fn save_state(sys: &Sys) {
 
 /// Loads the contents of cartridge RAM from a file named after the currently running game.
 pub fn load_state(sys: &mut Sys) -> bool {
-    let file_name = sys.mem.cart.header().title();
-    let path = format!("{}\\{}.sav", SAVE_FOLDER_PATH, file_name);
+    let path = save_path(sys);
 
     let Ok(buffer) = fs::read(&path) else {
         return false;
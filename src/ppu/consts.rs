@@ -31,6 +31,9 @@ pub const JOYPAD_ORG: IVec2 = i2(
     VIEWPORT_ORG.x,
     VIEWPORT_ORG.y + (VIEWPORT_P8_SIZE.y + 1) * P8.y,
 );
+pub const CODE_PANEL_ORG: IVec2 = i2(JOYPAD_ORG.x, JOYPAD_ORG.y + 6 * P8.y);
+pub const CODE_PANEL_LINES_BEFORE: usize = 2;
+pub const CODE_PANEL_LINES_AFTER: usize = 4;
 
 pub const WINDOW_P8_SIZE_NORMAL: IVec2 = i2(VIEWPORT_P8_SIZE.x + 2, VIEWPORT_P8_SIZE.y + 10);
 pub const WINDOW_SIZE_NORMAL: IVec2 = IVec2::mul(WINDOW_P8_SIZE_NORMAL, P8);
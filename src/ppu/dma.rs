@@ -1,11 +1,17 @@
 use crate::{mem::io_regs::IoReg, sys::Sys};
 
 const DMA_DURATION_M_CYCLES: u16 = 160;
+/// M-cycles between writing DMA and the transfer taking over the bus.
+const DMA_START_DELAY_M_CYCLES: u8 = 1;
 
 /// Represents the OAM DMA Transfer state.
 pub struct Dma {
     is_active: bool,
     next_idx: u16,
+    src_page: u8,
+    /// A transfer that was requested, with its source page and the M-cycles
+    /// left before it starts. An active transfer keeps going until then.
+    pending: Option<(u8, u8)>,
 }
 
 impl Dma {
@@ -13,44 +19,131 @@ impl Dma {
         Self {
             is_active: false,
             next_idx: 0,
+            src_page: 0,
+            pending: None,
         }
     }
+
+    /// Whether a transfer is using the bus, which leaves the CPU with only
+    /// HRAM and the IO registers.
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// Whether no transfer is running or about to start.
+    pub fn is_idle(&self) -> bool {
+        !self.is_active && self.pending.is_none()
+    }
 }
 
 /// Advances the DMA state by one M-Cycle.
 pub fn update_dma(sys: &mut Sys) {
+    if sys.mem.io_regs.dma_requested {
+        sys.mem.io_regs.dma_requested = false;
+        let src_page = sys.mem.io_regs.get(IoReg::Dma);
+        sys.ppu.dma_mut().pending = Some((src_page, DMA_START_DELAY_M_CYCLES));
+    }
+
+    if sys.ppu.dma_mut().is_active {
+        transfer_one_byte(sys);
+    }
+
     let dma = sys.ppu.dma_mut();
-    if !dma.is_active {
-        if sys.mem.io_regs.dma_requested {
-            sys.mem.io_regs.dma_requested = false;
-            start_dma(sys);
-        } else {
-            return;
+    match dma.pending {
+        Some((src_page, 0)) => {
+            dma.pending = None;
+            start_dma(sys, src_page);
         }
+        Some((src_page, delay)) => dma.pending = Some((src_page, delay - 1)),
+        None => {}
     }
-
-    transfer_one_byte(sys);
 }
 
-fn start_dma(sys: &mut Sys) {
+fn start_dma(sys: &mut Sys, src_page: u8) {
     let dma = sys.ppu.dma_mut();
     dma.is_active = true;
     dma.next_idx = 0;
+    dma.src_page = src_page;
 }
 
 fn transfer_one_byte(sys: &mut Sys) {
     let dma = sys.ppu.dma_mut();
 
     let idx = dma.next_idx;
-    let dma_val = sys.mem.io_regs.get(IoReg::Dma) as u16;
-    let src_addr = (dma_val * 0x100) + idx;
+    let src_addr = (dma.src_page as u16 * 0x100) + idx;
     let dst_addr = 0xFE00 + idx;
 
     let data = sys.mem.read(src_addr);
     sys.mem.write(dst_addr, data);
 
+    let dma = sys.ppu.dma_mut();
     dma.next_idx += 1;
     if dma.next_idx >= DMA_DURATION_M_CYCLES {
         dma.is_active = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{ppu::ppu::update_ppu, test::program::idle_sys};
+
+    use super::*;
+
+    /// Returns a system with the LCD off, and pages $C0 and $C1 of WRAM
+    /// filled with different values.
+    fn dma_sys() -> Sys {
+        let mut sys = idle_sys();
+        for i in 0..0x100 {
+            sys.mem.write(0xC000 + i, i as u8);
+            sys.mem.write(0xC100 + i, !(i as u8));
+        }
+        sys
+    }
+
+    fn run_m_cycles(sys: &mut Sys, m_cycles: u32) {
+        for _ in 0..m_cycles {
+            update_ppu(sys);
+        }
+    }
+
+    #[test]
+    fn test_dma_leaves_cpu_with_hram() {
+        let mut sys = dma_sys();
+        sys.write(IoReg::Dma.as_addr(), 0xC0);
+
+        // The transfer starts after a delay.
+        run_m_cycles(&mut sys, 1);
+        assert_eq!(sys.read(0xC005), 0x05);
+
+        run_m_cycles(&mut sys, 1);
+        assert_eq!(sys.read(0xC005), 0xFF);
+        assert_eq!(sys.read(0x0100), 0xFF);
+        sys.write(0xFF80, 0x12);
+        assert_eq!(sys.read(0xFF80), 0x12);
+
+        run_m_cycles(&mut sys, DMA_DURATION_M_CYCLES as u32);
+        assert_eq!(sys.read(0xC005), 0x05);
+        assert_eq!(sys.read(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn test_dma_restart() {
+        let mut sys = dma_sys();
+        sys.write(IoReg::Dma.as_addr(), 0xC0);
+        run_m_cycles(&mut sys, 20);
+
+        // The first transfer keeps going until the new one starts over.
+        sys.write(IoReg::Dma.as_addr(), 0xC1);
+        run_m_cycles(&mut sys, 2);
+        assert_eq!(sys.mem.read(0xFE13), 0x13);
+        assert_eq!(sys.mem.read(0xFE00), 0x00);
+        assert_eq!(sys.read(0xC000), 0xFF);
+
+        run_m_cycles(&mut sys, 1);
+        assert_eq!(sys.mem.read(0xFE00), 0xFF);
+
+        run_m_cycles(&mut sys, DMA_DURATION_M_CYCLES as u32 - 1);
+        assert_eq!(sys.read(0xFE12), !0x12);
+        assert_eq!(sys.read(0xFE9F), !0x9F);
+    }
+}
@@ -0,0 +1,749 @@
+use std::collections::VecDeque;
+
+use crate::{
+    mem::{io_regs::IoReg, Addr},
+    sys::Sys,
+    util::math::bit8,
+};
+
+use super::{
+    consts::{TILE_DATA_ADDR_8000, TILE_DATA_TILE_SIZE},
+    lcdc::LcdcState,
+    oam_scan::Obj,
+    palette::Palette,
+    render_util::{get_tile_map_addr, tile_data_idx_to_addr},
+};
+
+pub const LINE_WIDTH: usize = 160;
+
+/// Dots spent on the fetch at the start of each line, whose tile is thrown
+/// away.
+const FIRST_FETCH_DOTS: u8 = 6;
+/// Dots spent fetching an object's tile data.
+const OBJ_FETCH_DOTS: u8 = 6;
+
+/// The steps of the background fetcher. The first three take 2 dots each.
+/// `Push` is retried every dot until the background FIFO is empty.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+/// Fetches 8 pixels of background or window at a time.
+struct BgFetcher {
+    step: FetchStep,
+    /// Whether the first dot of the current step has passed.
+    is_mid_step: bool,
+    /// The next tile column to fetch, relative to the left of the line (or
+    /// of the window).
+    tile_x: u8,
+    tile_idx: u8,
+    lo: u8,
+    hi: u8,
+}
+
+impl BgFetcher {
+    fn new() -> Self {
+        Self {
+            step: FetchStep::Tile,
+            is_mid_step: false,
+            tile_x: 0,
+            tile_idx: 0,
+            lo: 0,
+            hi: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color_id: u8,
+    palette_reg_is_obp1: bool,
+    /// Whether background and window colors 1-3 are drawn over it.
+    is_behind_bg: bool,
+}
+
+/// The background and object pixel FIFOs, and the fetchers that fill them.
+///
+/// During mode 3, `step_pixel_fifo` runs once per dot and outputs at most
+/// one pixel, so registers are sampled when each pixel is actually fetched
+/// or output: SCX/SCY and the LCDC tile areas when a tile is fetched, the
+/// palettes and layer enables when a pixel is output, and WX as the line
+/// is drawn.
+///
+/// `test::acid2` checks the output against dmg-acid2's reference image.
+/// The ROM isn't part of the repository, see `assets/tests/acid2`.
+pub struct PixelFifo {
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+    fetcher: BgFetcher,
+    /// Dots left before the fetcher starts.
+    stall_dots: u8,
+    /// Pixels left to throw away at the start of the line, for SCX.
+    discard: u8,
+    /// The next pixel to output.
+    x: u8,
+    ly: u8,
+    is_in_window: bool,
+    /// Whether LY matched WY at the start of a line during this frame, which
+    /// lets the window show from then on.
+    is_wy_reached: bool,
+    /// The next window row to draw. It only moves on after lines that drew
+    /// the window.
+    window_line: u8,
+    /// Objects on the line that haven't been fetched yet, ordered by X.
+    objs: VecDeque<Obj>,
+    /// Dots left fetching the first of `objs`.
+    obj_fetch_dots: Option<u8>,
+    /// The background tile that an object fetch last waited on.
+    obj_wait_tile: Option<u8>,
+    /// The color values of the pixels output on the current line.
+    pub line: [u8; LINE_WIDTH],
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetcher: BgFetcher::new(),
+            stall_dots: 0,
+            discard: 0,
+            x: 0,
+            ly: 0,
+            is_in_window: false,
+            is_wy_reached: false,
+            window_line: 0,
+            objs: VecDeque::new(),
+            obj_fetch_dots: None,
+            obj_wait_tile: None,
+            line: [0; LINE_WIDTH],
+        }
+    }
+
+    pub fn is_line_done(&self) -> bool {
+        self.x as usize >= LINE_WIDTH
+    }
+}
+
+/// Prepares the pixel FIFO for drawing line `ly`, at the start of mode 3.
+pub fn start_pixel_fifo(sys: &mut Sys, ly: u8) {
+    let scx = sys.mem.io_regs.get(IoReg::Scx);
+
+    // Objects are fetched from left to right. On ties, the one first in
+    // OAM is fetched first, and so is drawn on top.
+    let mut objs = sys.ppu.oam_scan.objs.clone();
+    objs.sort_by_key(|obj| obj.x);
+
+    let fifo = &mut sys.ppu.fifo;
+    fifo.bg.clear();
+    fifo.obj.clear();
+    fifo.fetcher = BgFetcher::new();
+    fifo.stall_dots = FIRST_FETCH_DOTS;
+    fifo.discard = scx % 8;
+    fifo.x = 0;
+    fifo.ly = ly;
+    fifo.is_in_window = false;
+    fifo.objs = objs.into();
+    fifo.obj_fetch_dots = None;
+    fifo.obj_wait_tile = None;
+}
+
+/// Latches whether the window can show on this frame, at the start of mode 2.
+pub fn check_window_y(sys: &mut Sys, ly: u8) {
+    if sys.mem.io_regs.get(IoReg::Wy) == ly {
+        sys.ppu.fifo.is_wy_reached = true;
+    }
+}
+
+/// Resets the window for the next frame.
+pub fn reset_window(sys: &mut Sys) {
+    sys.ppu.fifo.is_wy_reached = false;
+    sys.ppu.fifo.window_line = 0;
+}
+
+/// Advances the pixel FIFO by one dot of mode 3.
+pub fn step_pixel_fifo(sys: &mut Sys) {
+    if sys.ppu.fifo.is_line_done() {
+        return;
+    }
+    if sys.ppu.fifo.stall_dots > 0 {
+        sys.ppu.fifo.stall_dots -= 1;
+        return;
+    }
+
+    let lcdc = LcdcState::from(sys);
+
+    try_start_window(sys, &lcdc);
+
+    // An object starting at this pixel pauses everything else while its
+    // tile is fetched.
+    let fifo = &sys.ppu.fifo;
+    let is_obj_pending =
+        lcdc.obj_enable && fifo.objs.front().is_some_and(|obj| obj.x <= fifo.x + 8);
+    if is_obj_pending {
+        fetch_obj(sys);
+        return;
+    }
+
+    step_bg_fetcher(sys, &lcdc);
+    output_pixel(sys, &lcdc);
+}
+
+fn try_start_window(sys: &mut Sys, lcdc: &LcdcState) {
+    let fifo = &sys.ppu.fifo;
+    if fifo.is_in_window || !fifo.is_wy_reached || !lcdc.bg_window_enable || !lcdc.window_enable {
+        return;
+    }
+
+    // With WX below 7, the window starts at the left edge, with its first
+    // pixels cut off.
+    let wx = sys.mem.io_regs.get(IoReg::Wx);
+    let window_x = fifo.x + 7;
+    if window_x != wx && !(fifo.x == 0 && wx < 7) {
+        return;
+    }
+
+    let fifo = &mut sys.ppu.fifo;
+    fifo.is_in_window = true;
+    fifo.bg.clear();
+    fifo.fetcher = BgFetcher::new();
+    fifo.discard = window_x - wx;
+}
+
+fn step_bg_fetcher(sys: &mut Sys, lcdc: &LcdcState) {
+    let fetcher = &mut sys.ppu.fifo.fetcher;
+
+    if fetcher.step == FetchStep::Push {
+        let fifo = &mut sys.ppu.fifo;
+        if fifo.bg.is_empty() {
+            let (lo, hi) = (fifo.fetcher.lo, fifo.fetcher.hi);
+            fifo.bg.extend(
+                (0..8)
+                    .rev()
+                    .map(|bit| (bit8(&hi, bit) << 1) | bit8(&lo, bit)),
+            );
+            fifo.fetcher.tile_x = fifo.fetcher.tile_x.wrapping_add(1);
+            fifo.fetcher.step = FetchStep::Tile;
+        }
+        return;
+    }
+
+    if !fetcher.is_mid_step {
+        fetcher.is_mid_step = true;
+        return;
+    }
+    fetcher.is_mid_step = false;
+
+    match fetcher.step {
+        FetchStep::Tile => {
+            let map_addr = bg_tile_map_addr(sys, lcdc);
+            let fetcher = &mut sys.ppu.fifo.fetcher;
+            fetcher.tile_idx = sys.mem.vram.read(map_addr);
+            fetcher.step = FetchStep::DataLow;
+        }
+        FetchStep::DataLow => {
+            let row_addr = bg_tile_row_addr(sys, lcdc);
+            let fetcher = &mut sys.ppu.fifo.fetcher;
+            fetcher.lo = sys.mem.vram.read(row_addr);
+            fetcher.step = FetchStep::DataHigh;
+        }
+        FetchStep::DataHigh => {
+            let row_addr = bg_tile_row_addr(sys, lcdc);
+            let fetcher = &mut sys.ppu.fifo.fetcher;
+            fetcher.hi = sys.mem.vram.read(row_addr + 1);
+            fetcher.step = FetchStep::Push;
+        }
+        FetchStep::Push => unreachable!(),
+    }
+}
+
+/// Returns the position, in tiles, of the background or window tile that
+/// is being fetched, and the pixel row within it.
+fn bg_fetch_pos(sys: &Sys) -> (u8, u8, u8) {
+    let fifo = &sys.ppu.fifo;
+
+    if fifo.is_in_window {
+        let y = fifo.window_line;
+        (fifo.fetcher.tile_x, y / 8, y % 8)
+    } else {
+        let scx = sys.mem.io_regs.get(IoReg::Scx);
+        let scy = sys.mem.io_regs.get(IoReg::Scy);
+        let y = fifo.ly.wrapping_add(scy);
+        let tile_x = (scx / 8).wrapping_add(fifo.fetcher.tile_x) % 32;
+        (tile_x, y / 8, y % 8)
+    }
+}
+
+fn bg_tile_map_addr(sys: &Sys, lcdc: &LcdcState) -> Addr {
+    let is_map_mode_9c00 = if sys.ppu.fifo.is_in_window {
+        lcdc.window_tile_map_area_is_9c00
+    } else {
+        lcdc.bg_tile_map_area_is_9c00
+    };
+
+    let (tile_x, tile_y, _) = bg_fetch_pos(sys);
+    get_tile_map_addr(is_map_mode_9c00) + (tile_y as Addr * 32) + tile_x as Addr
+}
+
+fn bg_tile_row_addr(sys: &Sys, lcdc: &LcdcState) -> Addr {
+    let (_, _, pixel_y) = bg_fetch_pos(sys);
+    let data_addr = tile_data_idx_to_addr(
+        sys.ppu.fifo.fetcher.tile_idx as u16,
+        lcdc.bg_window_tile_data_area_is_8000,
+    );
+
+    data_addr + (pixel_y as Addr * 2)
+}
+
+/// Returns how many dots fetching an object at the current pixel takes.
+///
+/// On top of its own fetch, the first object on each background tile
+/// waits for the background fetcher to be done with that tile, which takes
+/// longer the further left in the tile the object starts.
+fn obj_fetch_duration(sys: &mut Sys) -> u8 {
+    let scx = sys.mem.io_regs.get(IoReg::Scx);
+    let wx = sys.mem.io_regs.get(IoReg::Wx);
+
+    let fifo = &mut sys.ppu.fifo;
+    let bg_x = if fifo.is_in_window {
+        (fifo.x + 7).wrapping_sub(wx)
+    } else {
+        fifo.x.wrapping_add(scx)
+    };
+    if fifo.obj_wait_tile == Some(bg_x / 8) {
+        return OBJ_FETCH_DOTS;
+    }
+    fifo.obj_wait_tile = Some(bg_x / 8);
+
+    OBJ_FETCH_DOTS + 5 - u8::min(5, bg_x % 8)
+}
+
+/// Spends a dot fetching the next object, and mixes its pixels into the
+/// object FIFO once done.
+fn fetch_obj(sys: &mut Sys) {
+    let dots = match sys.ppu.fifo.obj_fetch_dots {
+        Some(dots) => dots,
+        None => obj_fetch_duration(sys),
+    };
+    let fifo = &mut sys.ppu.fifo;
+    if dots > 1 {
+        fifo.obj_fetch_dots = Some(dots - 1);
+        return;
+    }
+    fifo.obj_fetch_dots = None;
+    let obj = fifo.objs.pop_front().unwrap();
+    let (ly, x) = (fifo.ly, fifo.x);
+
+    let lcdc = LcdcState::from(sys);
+    let obj_h = if lcdc.obj_size_is_8x16 { 16 } else { 8 };
+    let y_flip = bit8(&obj.attrs, 6) == 1;
+    let x_flip = bit8(&obj.attrs, 5) == 1;
+    let palette_reg_is_obp1 = bit8(&obj.attrs, 4) == 1;
+    let is_behind_bg = bit8(&obj.attrs, 7) == 1;
+
+    let mut pixel_y = (ly + 16).wrapping_sub(obj.y) % obj_h;
+    if y_flip {
+        pixel_y = obj_h - 1 - pixel_y;
+    }
+    // Tall objects ignore bit 0 of the tile index.
+    let mut tile_idx = obj.tile_idx as u16;
+    if lcdc.obj_size_is_8x16 {
+        tile_idx = (tile_idx & 0xFE) + (pixel_y / 8) as u16;
+        pixel_y %= 8;
+    }
+
+    let row_addr = tile_idx * TILE_DATA_TILE_SIZE + TILE_DATA_ADDR_8000 + (pixel_y as Addr * 2);
+    let lo = sys.mem.vram.read(row_addr);
+    let hi = sys.mem.vram.read(row_addr + 1);
+
+    // Objects partly off the left edge lose their leftmost pixels.
+    let skip = x + 8 - obj.x;
+    let fifo = &mut sys.ppu.fifo;
+    for (i, obj_x) in (skip..8).enumerate() {
+        let bit = if x_flip { obj_x } else { 7 - obj_x };
+        let pixel = ObjPixel {
+            color_id: (bit8(&hi, bit) << 1) | bit8(&lo, bit),
+            palette_reg_is_obp1,
+            is_behind_bg,
+        };
+
+        // Pixels of objects fetched earlier stay on top.
+        match fifo.obj.get_mut(i) {
+            Some(prev) if prev.color_id == 0 => *prev = pixel,
+            Some(_) => {}
+            None => fifo.obj.push_back(pixel),
+        }
+    }
+}
+
+fn output_pixel(sys: &mut Sys, lcdc: &LcdcState) {
+    let fifo = &mut sys.ppu.fifo;
+    let Some(bg_color_id) = fifo.bg.pop_front() else {
+        return;
+    };
+    if fifo.discard > 0 {
+        fifo.discard -= 1;
+        return;
+    }
+    let obj_pixel = fifo.obj.pop_front().unwrap_or_default();
+
+    let is_bg_over_obj = obj_pixel.is_behind_bg && lcdc.bg_window_enable && bg_color_id != 0;
+    let is_obj_visible = lcdc.obj_enable && obj_pixel.color_id != 0 && !is_bg_over_obj;
+    let color = if is_obj_visible {
+        let palette_reg = if obj_pixel.palette_reg_is_obp1 {
+            IoReg::Obp1
+        } else {
+            IoReg::Obp0
+        };
+        Palette::from_reg(sys, palette_reg).map(obj_pixel.color_id)
+    } else if lcdc.bg_window_enable {
+        Palette::from_reg(sys, IoReg::Bgp).map(bg_color_id)
+    } else {
+        // The background and window are blank (white).
+        0
+    };
+
+    let fifo = &mut sys.ppu.fifo;
+    fifo.line[fifo.x as usize] = color;
+    fifo.x += 1;
+    if fifo.is_line_done() && fifo.is_in_window {
+        fifo.window_line += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ppu::{
+            consts::{OAM_ADDR_FE00, OAM_OBJ_SIZE},
+            oam_scan::{start_oam_scan, step_oam_scan},
+            ppu::update_ppu,
+        },
+        test::program::idle_sys,
+    };
+
+    use super::*;
+
+    /// Returns a system with the background on, using tiles from $8000.
+    /// Tile N (for N = 1-3) is solid color N, and the top-left tile of the
+    /// map is tile 3; everything else is color 0.
+    fn bg_sys() -> Sys {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x91);
+        sys.mem.io_regs.set(IoReg::Bgp, 0xE4);
+        sys.mem.io_regs.set(IoReg::Obp0, 0xE4);
+        for color_id in 1..=3u8 {
+            let tile_addr = TILE_DATA_ADDR_8000 + color_id as Addr * TILE_DATA_TILE_SIZE;
+            for row in 0..8 {
+                let row_addr = tile_addr + row * 2;
+                sys.mem.vram.write(row_addr, 0xFF * (color_id & 1));
+                sys.mem.vram.write(row_addr + 1, 0xFF * (color_id >> 1));
+            }
+        }
+        sys.mem.vram.write(0x9800 as Addr, 3);
+        sys
+    }
+
+    fn step_dots(sys: &mut Sys, dots: u32) {
+        for _ in 0..dots {
+            step_pixel_fifo(sys);
+        }
+    }
+
+    /// Runs the pixel FIFO until the line is complete, and returns how many
+    /// dots that took (which is the length of mode 3).
+    fn finish_line(sys: &mut Sys) -> u32 {
+        let mut dots = 0;
+        while !sys.ppu.fifo.is_line_done() {
+            step_pixel_fifo(sys);
+            dots += 1;
+        }
+        dots
+    }
+
+    /// Runs the OAM scan and then the pixel FIFO for line `ly`.
+    fn draw_line(sys: &mut Sys, ly: u8) -> u32 {
+        check_window_y(sys, ly);
+        start_oam_scan(sys);
+        for _ in 0..80 {
+            step_oam_scan(sys, ly);
+        }
+        start_pixel_fifo(sys, ly);
+        finish_line(sys)
+    }
+
+    /// Places an object with its top row on line 0.
+    fn set_obj(sys: &mut Sys, obj_idx: u16, x: u8, tile_idx: u8, attrs: u8) {
+        let obj_addr = OAM_ADDR_FE00 + obj_idx * OAM_OBJ_SIZE;
+        sys.mem.oam.write(obj_addr, 16);
+        sys.mem.oam.write(obj_addr + 1, x);
+        sys.mem.oam.write(obj_addr + 2, tile_idx);
+        sys.mem.oam.write(obj_addr + 3, attrs);
+    }
+
+    #[test]
+    fn test_scx_fine_scroll() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Scx, 4);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[0..4], [3; 4]);
+        assert_eq!(line[4..12], [0; 8]);
+    }
+
+    #[test]
+    fn test_bgp_write_mid_line() {
+        let mut sys = bg_sys();
+
+        // The first pixel comes out after the fetch at the start of the
+        // line and the first tile's fetch, then one pixel per dot.
+        start_pixel_fifo(&mut sys, 0);
+        step_dots(&mut sys, 12 + 80);
+        sys.mem.io_regs.set(IoReg::Bgp, 0x1B);
+        finish_line(&mut sys);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[0..8], [3; 8]);
+        assert_eq!(line[8..80], [0; 72]);
+        assert_eq!(line[80..160], [3; 80]);
+    }
+
+    #[test]
+    fn test_window_starts_at_wx() {
+        let mut sys = bg_sys();
+        // The window uses the $9C00 map, which is all tile 3.
+        sys.mem.io_regs.set(IoReg::Lcdc, 0xF1);
+        for addr in 0x9C00..0x9C20 {
+            sys.mem.vram.write(addr as Addr, 3);
+        }
+        sys.mem.io_regs.set(IoReg::Wy, 0);
+        sys.mem.io_regs.set(IoReg::Wx, 7 + 100);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[8..100], [0; 92]);
+        assert_eq!(line[100..160], [3; 60]);
+    }
+
+    #[test]
+    fn test_window_y_checked_on_first_line_after_lcd_on() {
+        let mut sys = idle_sys();
+        update_ppu(&mut sys);
+        sys.mem.io_regs.set(IoReg::Wy, 0);
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+
+        update_ppu(&mut sys);
+        assert!(sys.ppu.fifo.is_wy_reached);
+    }
+
+    #[test]
+    fn test_mode_3_length() {
+        let mut sys = bg_sys();
+        assert_eq!(draw_line(&mut sys, 0), 172);
+
+        // Fine scrolling throws away pixels at the start of the line.
+        sys.mem.io_regs.set(IoReg::Scx, 3);
+        assert_eq!(draw_line(&mut sys, 0), 175);
+        sys.mem.io_regs.set(IoReg::Scx, 0);
+
+        // Starting the window restarts the background fetch.
+        sys.mem.io_regs.set(IoReg::Lcdc, 0xB1);
+        sys.mem.io_regs.set(IoReg::Wy, 0);
+        sys.mem.io_regs.set(IoReg::Wx, 7 + 80);
+        assert_eq!(draw_line(&mut sys, 0), 178);
+    }
+
+    #[test]
+    fn test_obj_fetches_lengthen_mode_3() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x93);
+
+        // At the left of a tile, the object waits for the whole background
+        // fetch.
+        set_obj(&mut sys, 0, 8, 0, 0);
+        assert_eq!(draw_line(&mut sys, 0), 172 + 11);
+
+        // A second object on the same tile only pays for its own fetch.
+        set_obj(&mut sys, 1, 8 + 2, 0, 0);
+        assert_eq!(draw_line(&mut sys, 0), 172 + 11 + 6);
+
+        // An object hidden past the left edge still gets fetched. Further
+        // right in a tile, the wait is shorter.
+        set_obj(&mut sys, 0, 0, 0, 0);
+        set_obj(&mut sys, 1, 8 + 20, 0, 0);
+        assert_eq!(draw_line(&mut sys, 0), 172 + 11 + 6 + 1);
+    }
+
+    #[test]
+    fn test_only_10_objs_per_line() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x93);
+        for obj_idx in 0..11 {
+            set_obj(&mut sys, obj_idx, 16 + obj_idx as u8 * 8, 1, 0);
+        }
+        // Objects off the side of the screen count too.
+        set_obj(&mut sys, 0, 0, 1, 0);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[8..16], [0; 8]);
+        assert_eq!(line[16..88], [1; 72]);
+        assert_eq!(line[88..96], [0; 8]);
+    }
+
+    #[test]
+    fn test_obj_priority() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x93);
+
+        // The leftmost object is drawn on top, even if it comes later in
+        // OAM.
+        set_obj(&mut sys, 0, 8 + 12, 2, 0);
+        set_obj(&mut sys, 1, 8 + 8, 1, 0);
+        // On ties, the first one in OAM is drawn on top.
+        set_obj(&mut sys, 2, 8 + 40, 1, 0);
+        set_obj(&mut sys, 3, 8 + 40, 2, 0);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[8..16], [1; 8]);
+        assert_eq!(line[16..20], [2; 4]);
+        assert_eq!(line[40..48], [1; 8]);
+    }
+
+    #[test]
+    fn test_obj_behind_bg() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x93);
+
+        // Only background color 0 is drawn under the object.
+        set_obj(&mut sys, 0, 8 + 4, 1, 0x80);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[0..8], [3; 8]);
+        assert_eq!(line[8..12], [1; 4]);
+
+        // Unless the background is off.
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x92);
+        draw_line(&mut sys, 0);
+        assert_eq!(sys.ppu.fifo.line[4..12], [1; 8]);
+    }
+
+    #[test]
+    fn test_8x16_obj_ignores_tile_idx_bit_0() {
+        let mut sys = bg_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x97);
+        set_obj(&mut sys, 0, 8 + 16, 3, 0);
+
+        draw_line(&mut sys, 0);
+        assert_eq!(sys.ppu.fifo.line[16..24], [2; 8]);
+
+        draw_line(&mut sys, 8);
+        assert_eq!(sys.ppu.fifo.line[16..24], [3; 8]);
+    }
+
+    /// Turns the window on, using the $9C00 map, which is all `tile_idx`.
+    fn set_window(sys: &mut Sys, tile_idx: u8, wx: u8, wy: u8) {
+        sys.mem.io_regs.set(IoReg::Lcdc, 0xF1);
+        sys.mem.io_regs.set(IoReg::Wx, wx);
+        sys.mem.io_regs.set(IoReg::Wy, wy);
+        for addr in 0x9C00..0x9C00 + 32 * 32 {
+            sys.mem.vram.write(addr as Addr, tile_idx);
+        }
+    }
+
+    /// Makes tile 4 have color 3 on row 0, color 1 on row 1, and color 0
+    /// elsewhere.
+    fn set_striped_tile(sys: &mut Sys) {
+        let tile_addr = TILE_DATA_ADDR_8000 + 4 * TILE_DATA_TILE_SIZE;
+        sys.mem.vram.write(tile_addr, 0xFF);
+        sys.mem.vram.write(tile_addr + 1, 0xFF);
+        sys.mem.vram.write(tile_addr + 2, 0xFF);
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        let mut sys = bg_sys();
+        set_striped_tile(&mut sys);
+        set_window(&mut sys, 4, 7, 0);
+
+        draw_line(&mut sys, 0);
+        assert_eq!(sys.ppu.fifo.line[0..160], [3; 160]);
+
+        // Lines without the window don't move it on.
+        sys.mem.io_regs.set(IoReg::Lcdc, 0xD1);
+        draw_line(&mut sys, 1);
+        draw_line(&mut sys, 2);
+        sys.mem.io_regs.set(IoReg::Lcdc, 0xF1);
+        draw_line(&mut sys, 3);
+        assert_eq!(sys.ppu.fifo.line[0..160], [1; 160]);
+
+        // Until the next frame.
+        reset_window(&mut sys);
+        draw_line(&mut sys, 0);
+        assert_eq!(sys.ppu.fifo.line[0..160], [3; 160]);
+    }
+
+    #[test]
+    fn test_wy_is_latched() {
+        let mut sys = bg_sys();
+        set_window(&mut sys, 3, 7, 1);
+
+        draw_line(&mut sys, 0);
+        assert_eq!(sys.ppu.fifo.line[8..160], [0; 152]);
+
+        draw_line(&mut sys, 1);
+        assert_eq!(sys.ppu.fifo.line[0..160], [3; 160]);
+
+        // Moving WY afterwards doesn't hide the window.
+        sys.mem.io_regs.set(IoReg::Wy, 100);
+        draw_line(&mut sys, 2);
+        assert_eq!(sys.ppu.fifo.line[0..160], [3; 160]);
+
+        // It has to match LY at the start of a line.
+        reset_window(&mut sys);
+        sys.mem.io_regs.set(IoReg::Wy, 1);
+        draw_line(&mut sys, 2);
+        assert_eq!(sys.ppu.fifo.line[8..160], [0; 152]);
+    }
+
+    #[test]
+    fn test_wx_below_7_cuts_off_window() {
+        let mut sys = bg_sys();
+        set_window(&mut sys, 0, 3, 0);
+        // Tile 5's row 0 is color 1 on its right half.
+        let tile_addr = TILE_DATA_ADDR_8000 + 5 * TILE_DATA_TILE_SIZE;
+        sys.mem.vram.write(tile_addr, 0x0F);
+        sys.mem.vram.write(0x9C00 as Addr, 5);
+        sys.mem.io_regs.set(IoReg::Scx, 5);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[0..4], [1; 4]);
+        assert_eq!(line[4..12], [0; 8]);
+    }
+
+    #[test]
+    fn test_wx_166_shows_last_column() {
+        let mut sys = bg_sys();
+        set_window(&mut sys, 3, 166, 0);
+
+        draw_line(&mut sys, 0);
+
+        let line = &sys.ppu.fifo.line;
+        assert_eq!(line[8..159], [0; 151]);
+        assert_eq!(line[159], 3);
+    }
+}
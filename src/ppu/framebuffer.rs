@@ -0,0 +1,34 @@
+use super::fifo::LINE_WIDTH;
+
+pub const FRAMEBUFFER_WIDTH: usize = LINE_WIDTH;
+pub const FRAMEBUFFER_HEIGHT: usize = 144;
+
+/// The picture output by the PPU, one shade (0 is white, 3 is black) per
+/// pixel. Lines are written as they are drawn, so the frame is complete when
+/// VBlank starts.
+pub struct Framebuffer {
+    shades: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            shades: vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT],
+        }
+    }
+
+    /// The shades of all the pixels, line by line.
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    pub fn set_line(&mut self, y: usize, line: &[u8; LINE_WIDTH]) {
+        let start = y * FRAMEBUFFER_WIDTH;
+        self.shades[start..start + FRAMEBUFFER_WIDTH].copy_from_slice(line);
+    }
+
+    /// Fills the picture with white, as shown while the LCD is off.
+    pub fn clear(&mut self) {
+        self.shades.fill(0);
+    }
+}
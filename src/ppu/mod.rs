@@ -1,10 +1,14 @@
 pub mod consts;
 mod dma;
+mod fifo;
+pub mod framebuffer;
 mod lcdc;
+pub mod oam_bug;
+mod oam_scan;
 mod palette;
 pub mod ppu;
-mod render;
 mod render_mem;
 mod render_util;
-mod text;
+pub mod screen;
+pub mod text;
 pub mod ui;
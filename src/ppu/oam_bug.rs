@@ -0,0 +1,228 @@
+use crate::{
+    mem::{array::Array, Addr},
+    sys::Sys,
+};
+
+use super::consts::OAM_ADDR_FE00;
+
+/// OAM is corrupted in rows of 8 bytes (4 words, or 2 objects).
+const OAM_ROW_SIZE: Addr = 8;
+const OAM_ROW_COUNT: usize = 20;
+
+/// How the CPU used an address in $FE00-$FEFF during an M-cycle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OamBugAccess {
+    Read,
+    /// A write, or the address going through the 16-bit increment/decrement
+    /// unit (as in `INC HL` or `PUSH`).
+    Write,
+    /// A read while the address is incremented or decremented in the same
+    /// M-cycle (as in `LD A, [HL+]` or `POP`).
+    ReadIncDec,
+}
+
+/// Emulates the DMG's OAM corruption bug. When the CPU puts an address in
+/// $FE00-$FEFF on the bus during the OAM scan, the row of OAM that the PPU
+/// is reading gets mixed with the row before it. The first row is never
+/// corrupted.
+pub fn trigger_oam_bug(sys: &mut Sys, addr: Addr, access: OamBugAccess) {
+    if !(0xFE00..=0xFEFF).contains(&addr) {
+        return;
+    }
+    let Some(row) = sys.ppu.oam_scan_row() else {
+        return;
+    };
+    if row == 0 || row >= OAM_ROW_COUNT {
+        return;
+    }
+
+    let oam = &mut sys.mem.oam;
+    match access {
+        OamBugAccess::Read => corrupt_read(oam, row),
+        OamBugAccess::Write => corrupt_write(oam, row),
+        OamBugAccess::ReadIncDec => {
+            // Away from the edges of OAM, the two rows before get mixed too.
+            if (4..OAM_ROW_COUNT - 1).contains(&row) {
+                let a = get_word(oam, row - 2, 0);
+                let b = get_word(oam, row - 1, 0);
+                let c = get_word(oam, row, 0);
+                let d = get_word(oam, row - 1, 2);
+                set_word(oam, row - 1, 0, (b & (a | c | d)) | (a & c & d));
+                copy_row(oam, row - 1, row);
+                copy_row(oam, row - 1, row - 2);
+            }
+            corrupt_read(oam, row);
+        }
+    }
+}
+
+fn corrupt_read(oam: &mut Array, row: usize) {
+    let a = get_word(oam, row, 0);
+    let b = get_word(oam, row - 1, 0);
+    let c = get_word(oam, row - 1, 2);
+    set_word(oam, row, 0, b | (a & c));
+    copy_row_tail(oam, row - 1, row);
+}
+
+fn corrupt_write(oam: &mut Array, row: usize) {
+    let a = get_word(oam, row, 0);
+    let b = get_word(oam, row - 1, 0);
+    let c = get_word(oam, row - 1, 2);
+    set_word(oam, row, 0, ((a ^ c) & (b ^ c)) ^ c);
+    copy_row_tail(oam, row - 1, row);
+}
+
+fn word_addr(row: usize, word: usize) -> Addr {
+    OAM_ADDR_FE00 + (row as Addr * OAM_ROW_SIZE) + (word as Addr * 2)
+}
+
+fn get_word(oam: &Array, row: usize, word: usize) -> u16 {
+    let addr = word_addr(row, word);
+    u16::from_le_bytes([oam.read(addr), oam.read(addr + 1)])
+}
+
+fn set_word(oam: &mut Array, row: usize, word: usize, data: u16) {
+    let addr = word_addr(row, word);
+    let [lo, hi] = data.to_le_bytes();
+    oam.write(addr, lo);
+    oam.write(addr + 1, hi);
+}
+
+/// Copies the last 3 words of row `src` to row `dst`.
+fn copy_row_tail(oam: &mut Array, src: usize, dst: usize) {
+    for word in 1..4 {
+        set_word(oam, dst, word, get_word(oam, src, word));
+    }
+}
+
+fn copy_row(oam: &mut Array, src: usize, dst: usize) {
+    for word in 0..4 {
+        set_word(oam, dst, word, get_word(oam, src, word));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{exec::execute_next_instr, regs::CpuReg16},
+        mem::io_regs::IoReg,
+        ppu::ppu::update_ppu,
+        test::program::idle_sys,
+    };
+
+    use super::*;
+
+    /// Returns a system with OAM filled with a known pattern, scanning `row`
+    /// of OAM in mode 2.
+    fn sys_scanning_row(row: usize) -> Sys {
+        let mut sys = idle_sys();
+        for row in 0..OAM_ROW_COUNT {
+            let base = row as u16 * 0x0100;
+            for word in 0..4 {
+                set_word(&mut sys.mem.oam, row, word, base + word as u16);
+            }
+        }
+
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        while sys.ppu.oam_scan_row() != Some(row) {
+            update_ppu(&mut sys);
+        }
+        sys
+    }
+
+    fn words(oam: &Array) -> Vec<u16> {
+        (0..OAM_ROW_COUNT)
+            .flat_map(|row| (0..4).map(move |word| get_word(oam, row, word)))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_corruption() {
+        let mut sys = sys_scanning_row(5);
+        set_word(&mut sys.mem.oam, 5, 0, 0x00FF);
+        set_word(&mut sys.mem.oam, 4, 0, 0x0F0F);
+        set_word(&mut sys.mem.oam, 4, 2, 0x3333);
+
+        trigger_oam_bug(&mut sys, 0xFE10, OamBugAccess::Write);
+
+        let oam = &sys.mem.oam;
+        assert_eq!(get_word(oam, 5, 0), 0x033F);
+        assert_eq!(get_word(oam, 5, 1), 0x0401);
+        assert_eq!(get_word(oam, 5, 2), 0x3333);
+        assert_eq!(get_word(oam, 5, 3), 0x0403);
+        // The other rows are left alone.
+        assert_eq!(get_word(oam, 6, 0), 0x0600);
+    }
+
+    #[test]
+    fn test_read_corruption() {
+        let mut sys = sys_scanning_row(5);
+        set_word(&mut sys.mem.oam, 5, 0, 0x00FF);
+        set_word(&mut sys.mem.oam, 4, 0, 0x0F0F);
+        set_word(&mut sys.mem.oam, 4, 2, 0x3333);
+
+        trigger_oam_bug(&mut sys, 0xFE10, OamBugAccess::Read);
+
+        let oam = &sys.mem.oam;
+        assert_eq!(get_word(oam, 5, 0), 0x0F3F);
+        assert_eq!(get_word(oam, 5, 1), 0x0401);
+    }
+
+    #[test]
+    fn test_read_inc_dec_corruption() {
+        let mut sys = sys_scanning_row(5);
+        set_word(&mut sys.mem.oam, 3, 0, 0x00F0);
+        set_word(&mut sys.mem.oam, 4, 0, 0x0F00);
+        set_word(&mut sys.mem.oam, 5, 0, 0x000F);
+        set_word(&mut sys.mem.oam, 4, 2, 0x0FF0);
+
+        trigger_oam_bug(&mut sys, 0xFE10, OamBugAccess::ReadIncDec);
+
+        // Row 4 is mixed with its neighbours and copied over both of them,
+        // then row 5 goes through the read corruption.
+        let oam = &sys.mem.oam;
+        assert_eq!(get_word(oam, 4, 0), 0x0F00);
+        assert_eq!(get_word(oam, 3, 0), 0x0F00);
+        assert_eq!(get_word(oam, 3, 2), 0x0FF0);
+        assert_eq!(get_word(oam, 5, 0), 0x0F00);
+        assert_eq!(get_word(oam, 5, 3), 0x0403);
+    }
+
+    #[test]
+    fn test_no_corruption_outside_oam_scan() {
+        let mut sys = sys_scanning_row(5);
+        let oam = words(&sys.mem.oam);
+
+        // Not an OAM address.
+        trigger_oam_bug(&mut sys, 0xC010, OamBugAccess::Write);
+        assert_eq!(words(&sys.mem.oam), oam);
+
+        // Not in mode 2.
+        while sys.ppu.oam_scan_row().is_some() {
+            update_ppu(&mut sys);
+        }
+        trigger_oam_bug(&mut sys, 0xFE10, OamBugAccess::Write);
+        assert_eq!(words(&sys.mem.oam), oam);
+
+        // The first row is never corrupted.
+        let mut sys = sys_scanning_row(0);
+        let oam = words(&sys.mem.oam);
+        trigger_oam_bug(&mut sys, 0xFE10, OamBugAccess::Write);
+        assert_eq!(words(&sys.mem.oam), oam);
+    }
+
+    #[test]
+    fn test_inc_hl_corrupts_oam() {
+        let mut sys = sys_scanning_row(5);
+        sys.regs.set_16(CpuReg16::HL, 0xFE10);
+        let row_4 = [0, 1, 2, 3].map(|word| get_word(&sys.mem.oam, 4, word));
+
+        // inc hl
+        sys.mem.write(0xC000, 0x23);
+        sys.regs.set_16(CpuReg16::PC, 0xC000);
+        execute_next_instr(&mut sys);
+
+        assert_eq!(sys.regs.get_16(CpuReg16::HL), 0xFE11);
+        assert_eq!(get_word(&sys.mem.oam, 5, 1), row_4[1]);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{mem::Addr, sys::Sys};
+
+use super::{
+    consts::{OAM_ADDR_FE00, OAM_OBJ_SIZE},
+    lcdc::LcdcState,
+};
+
+pub const OAM_OBJ_COUNT: u8 = 40;
+/// How many objects can be drawn on a single line.
+pub const MAX_OBJS_PER_LINE: usize = 10;
+
+/// An object found on the current line.
+#[derive(Clone, Copy, Debug)]
+pub struct Obj {
+    pub x: u8,
+    pub y: u8,
+    pub tile_idx: u8,
+    pub attrs: u8,
+}
+
+/// The OAM scan done during mode 2, which checks one object every 2 dots and
+/// keeps the first 10 that overlap the line.
+pub struct OamScan {
+    next_idx: u8,
+    is_mid_obj: bool,
+    /// The objects selected so far, in OAM order.
+    pub objs: Vec<Obj>,
+}
+
+impl OamScan {
+    pub fn new() -> Self {
+        Self {
+            next_idx: 0,
+            is_mid_obj: false,
+            objs: Vec::with_capacity(MAX_OBJS_PER_LINE),
+        }
+    }
+
+    /// The row of OAM (8 bytes, or 2 objects) being read.
+    pub fn row(&self) -> usize {
+        self.next_idx as usize / 2
+    }
+}
+
+/// Starts scanning OAM for the next line, at the start of mode 2.
+pub fn start_oam_scan(sys: &mut Sys) {
+    let scan = &mut sys.ppu.oam_scan;
+    scan.next_idx = 0;
+    scan.is_mid_obj = false;
+    scan.objs.clear();
+}
+
+/// Advances the OAM scan by one dot of mode 2.
+pub fn step_oam_scan(sys: &mut Sys, ly: u8) {
+    let scan = &mut sys.ppu.oam_scan;
+    if scan.next_idx >= OAM_OBJ_COUNT {
+        return;
+    }
+    if !scan.is_mid_obj {
+        scan.is_mid_obj = true;
+        return;
+    }
+    scan.is_mid_obj = false;
+    let obj_idx = scan.next_idx;
+    scan.next_idx += 1;
+    if scan.objs.len() >= MAX_OBJS_PER_LINE {
+        return;
+    }
+
+    let obj_addr = OAM_ADDR_FE00 + (OAM_OBJ_SIZE * obj_idx as Addr);
+    let obj = Obj {
+        y: sys.mem.oam.read(obj_addr),
+        x: sys.mem.oam.read(obj_addr + 1),
+        tile_idx: sys.mem.oam.read(obj_addr + 2),
+        attrs: sys.mem.oam.read(obj_addr + 3),
+    };
+
+    // Only Y matters here: objects off the sides of the screen still count
+    // towards the limit.
+    let obj_h = if LcdcState::from(sys).obj_size_is_8x16 {
+        16
+    } else {
+        8
+    };
+    let line = ly as u16 + 16;
+    if (obj.y as u16..obj.y as u16 + obj_h).contains(&line) {
+        sys.ppu.oam_scan.objs.push(obj);
+    }
+}
@@ -1,14 +1,17 @@
 use crate::{
     cpu::interrupt::{request_interrupt, InterruptType},
-    mem::io_regs::IoReg,
+    mem::{io_regs::IoReg, sections::MemSection, Addr},
     sys::Sys,
     util::math::{bit8, set_bit8},
 };
 
 use super::{
-    consts::VIEWPORT_ORG,
     dma::{update_dma, Dma},
-    render::render_scanline,
+    fifo::{
+        check_window_y, reset_window, start_pixel_fifo, step_pixel_fifo, PixelFifo, LINE_WIDTH,
+    },
+    lcdc::LcdcState,
+    oam_scan::{start_oam_scan, step_oam_scan, OamScan},
 };
 
 pub const DOTS_PER_SCANLINE: u32 = 456;
@@ -24,17 +27,45 @@ enum PpuMode {
 
 /// Represents the PPU state.
 pub struct Ppu {
+    is_lcd_on: bool,
+    /// Whether the LCD was just turned on, and the frame being drawn is not
+    /// shown.
+    is_first_frame: bool,
+    /// Whether the current line has no OAM scan, as happens on the first line
+    /// after the LCD is turned on.
+    is_oam_scan_skipped: bool,
+    mode: PpuMode,
+    /// The line being drawn. LY reads the same, except near the end of line
+    /// 153, where it already reads 0.
+    line: u8,
+    /// What LYC is compared to, if anything.
+    ly_compare: Option<u8>,
+    /// The STAT interrupt line: all the enabled STAT interrupt sources, ORed
+    /// together. The interrupt is only requested when it goes high.
+    stat_line: bool,
+    /// While the LCD is off, the dots of the whole frame are counted here.
     curr_scanline_dot: u32,
     total_frames_drawn: u64,
     dma: Dma,
+    pub oam_scan: OamScan,
+    pub fifo: PixelFifo,
 }
 
 impl Ppu {
     pub fn new() -> Self {
         Self {
+            is_lcd_on: true,
+            is_first_frame: false,
+            is_oam_scan_skipped: false,
+            mode: PpuMode::OamScan,
+            line: 0,
+            ly_compare: Some(0),
+            stat_line: false,
             curr_scanline_dot: 0,
             total_frames_drawn: 0,
             dma: Dma::new(),
+            oam_scan: OamScan::new(),
+            fifo: PixelFifo::new(),
         }
     }
 
@@ -45,71 +76,243 @@ impl Ppu {
     pub fn dma_mut(&mut self) -> &mut Dma {
         &mut self.dma
     }
+
+    /// The row of OAM being scanned, during mode 2.
+    pub fn oam_scan_row(&self) -> Option<usize> {
+        (self.is_lcd_on && self.mode == PpuMode::OamScan).then(|| self.oam_scan.row())
+    }
+
+    /// Whether the CPU can access `addr` right now. VRAM is in use by the
+    /// PPU during mode 3 and OAM during modes 2 and 3. An OAM DMA transfer
+    /// takes over the rest of the bus, leaving the CPU with HRAM and the IO
+    /// registers (so that it can restart the transfer).
+    pub fn is_cpu_accessible(&self, addr: Addr) -> bool {
+        let section = MemSection::from_abs_addr(addr);
+        if self.dma.is_active() {
+            return matches!(
+                section,
+                MemSection::IoRegs | MemSection::Hram | MemSection::IeReg
+            );
+        }
+
+        match section {
+            MemSection::Vram => self.mode != PpuMode::Draw,
+            MemSection::Oam => !matches!(self.mode, PpuMode::OamScan | PpuMode::Draw),
+            _ => true,
+        }
+    }
 }
 
 /// Advances the PPU state by 1 M-Cycle.
 pub fn update_ppu(sys: &mut Sys) {
+    let is_lcd_on = LcdcState::from(sys).ppu_enable;
+    if is_lcd_on != sys.ppu.is_lcd_on {
+        if is_lcd_on {
+            turn_lcd_on(sys);
+        } else {
+            turn_lcd_off(sys);
+        }
+    }
+
     // Advance by 1 M-Cycle (4 dots).
     for _ in 0..4 {
-        update(sys);
+        if is_lcd_on {
+            update(sys);
+        } else {
+            update_lcd_off(sys);
+        }
     }
     update_dma(sys);
+
+    // On DMG, writing STAT acts as if all its interrupt sources were enabled
+    // for a moment.
+    if std::mem::take(&mut sys.mem.io_regs.stat_written) && is_lcd_on {
+        update_stat_line(sys, true);
+    }
 }
 
-fn update(sys: &mut Sys) {
-    let mut ly = sys.mem.io_regs.get(IoReg::Ly);
+/// Returns how many of the coming M-cycles the PPU would spend only counting
+/// dots, with no change to LY, STAT, IF or the framebuffer. That is the
+/// rest of an HBlank or VBlank line (past its first M-cycles, where LY and
+/// the LYC compare change), or the rest of a frame with the LCD off.
+pub fn ppu_idle_m_cycles(sys: &Sys) -> u32 {
+    let is_lcd_on = LcdcState::from(sys).ppu_enable;
+    let io_regs = &sys.mem.io_regs;
+    if is_lcd_on != sys.ppu.is_lcd_on
+        || !sys.ppu.dma.is_idle()
+        || io_regs.dma_requested
+        || io_regs.stat_written
+    {
+        return 0;
+    }
+
+    let dot = sys.ppu.curr_scanline_dot;
+    let idle_dots = if !is_lcd_on {
+        DOTS_PER_SCANLINE * SCANLINES_PER_FRAME as u32 - 1 - dot
+    } else if matches!(sys.ppu.mode, PpuMode::HBlank | PpuMode::VBlank)
+        && dot >= 8
+        && !sys.ppu.is_oam_scan_skipped
+    {
+        DOTS_PER_SCANLINE - 1 - dot
+    } else {
+        0
+    };
+
+    idle_dots / 4
+}
+
+/// Advances the PPU by `m_cycles`, which must not be more than
+/// `ppu_idle_m_cycles`.
+pub fn skip_ppu_m_cycles(sys: &mut Sys, m_cycles: u32) {
+    sys.ppu.curr_scanline_dot += 4 * m_cycles;
+}
+
+/// Stops the PPU, with LY held at 0 and STAT in mode 0.
+fn turn_lcd_off(sys: &mut Sys) {
+    sys.ppu.is_lcd_on = false;
+    sys.ppu.mode = PpuMode::HBlank;
+    sys.ppu.line = 0;
+    sys.ppu.stat_line = false;
+    sys.ppu.curr_scanline_dot = 0;
+    sys.mem.io_regs.set(IoReg::Ly, 0);
+    sys.mem
+        .io_regs
+        .mut_(IoReg::Stat, |stat| *stat &= 0b1111_1100);
+    sys.framebuffer.clear();
+}
+
+/// Starts the PPU from the top of the screen. The first line is 4 dots
+/// shorter and has no OAM scan, and the first frame stays blank.
+fn turn_lcd_on(sys: &mut Sys) {
+    sys.ppu.is_lcd_on = true;
+    sys.ppu.is_first_frame = true;
+    sys.ppu.is_oam_scan_skipped = true;
+    sys.ppu.curr_scanline_dot = 4;
+    // WY is still compared on the first line, even without its OAM scan.
+    reset_window(sys);
+    check_window_y(sys, 0);
+    start_oam_scan(sys);
+    enter_scanline(sys);
+    update_stat_line(sys, false);
+}
 
-    let prev_mode = get_mode(sys.ppu.curr_scanline_dot, ly);
+/// Keeps time while the LCD is off, so the frontend still gets a (blank)
+/// frame at the usual rate.
+fn update_lcd_off(sys: &mut Sys) {
+    sys.ppu.curr_scanline_dot += 1;
+    if sys.ppu.curr_scanline_dot >= DOTS_PER_SCANLINE * SCANLINES_PER_FRAME as u32 {
+        sys.ppu.curr_scanline_dot = 0;
+        sys.ppu.total_frames_drawn += 1;
+        sys.is_render_pending = true;
+    }
+}
 
+fn update(sys: &mut Sys) {
     sys.ppu.curr_scanline_dot += 1;
     if sys.ppu.curr_scanline_dot >= DOTS_PER_SCANLINE {
         sys.ppu.curr_scanline_dot = 0;
-        ly += 1;
-        if ly >= SCANLINES_PER_FRAME {
-            ly = 0;
+        sys.ppu.line += 1;
+        if sys.ppu.line >= SCANLINES_PER_FRAME {
+            sys.ppu.line = 0;
             sys.ppu.total_frames_drawn += 1;
+            sys.ppu.is_first_frame = false;
         }
+        sys.ppu.is_oam_scan_skipped = false;
 
-        enter_scanline(sys, ly);
+        enter_scanline(sys);
     }
+    update_ly(sys);
 
-    let next_mode = get_mode(sys.ppu.curr_scanline_dot, ly);
-
-    if prev_mode != next_mode {
+    let next_mode = get_next_mode(sys);
+    if sys.ppu.mode != next_mode {
+        sys.ppu.mode = next_mode;
         enter_mode(sys, next_mode);
     }
+
+    match next_mode {
+        PpuMode::OamScan => step_oam_scan(sys, sys.ppu.line),
+        PpuMode::Draw => step_pixel_fifo(sys),
+        _ => {}
+    }
+
+    update_stat_line(sys, false);
 }
 
-fn get_mode(dot: u32, scanline: u8) -> PpuMode {
-    if scanline >= 144 {
+/// Returns the mode for the current dot. Mode 3 lasts until the pixel FIFO
+/// has output the whole line, which takes longer with fine scrolling, the
+/// window and objects.
+fn get_next_mode(sys: &Sys) -> PpuMode {
+    if sys.ppu.line >= 144 {
         PpuMode::VBlank
-    } else if dot < 80 {
-        return PpuMode::OamScan;
-    } else if dot - 80 < 172 {
-        return PpuMode::Draw;
+    } else if sys.ppu.curr_scanline_dot < 80 {
+        if sys.ppu.is_oam_scan_skipped {
+            PpuMode::HBlank
+        } else {
+            PpuMode::OamScan
+        }
+    } else if sys.ppu.curr_scanline_dot == 80 {
+        PpuMode::Draw
+    } else if sys.ppu.mode == PpuMode::Draw && sys.ppu.fifo.is_line_done() {
+        PpuMode::HBlank
     } else {
-        return PpuMode::HBlank;
+        sys.ppu.mode
     }
 }
 
-fn enter_scanline(sys: &mut Sys, scanline: u8) {
-    // Update LY
-    let ly = scanline;
-    sys.mem.io_regs.set(IoReg::Ly, ly);
+fn enter_scanline(sys: &mut Sys) {
+    let line = sys.ppu.line;
+    sys.mem.io_regs.set(IoReg::Ly, line);
+
+    // LYC isn't compared to anything during the first M-cycle of a line,
+    // except on line 0, where LY was already 0.
+    sys.ppu.ly_compare = if line == 0 { Some(0) } else { None };
+}
+
+/// Updates LY and what LYC is compared to, for the current dot.
+fn update_ly(sys: &mut Sys) {
+    let line = sys.ppu.line;
 
-    // Update STAT.LYC==LY flag.
+    match sys.ppu.curr_scanline_dot {
+        // LY reads 0 for most of line 153, but LYC is still compared to 153
+        // for another M-cycle.
+        4 if line == 153 => {
+            sys.mem.io_regs.set(IoReg::Ly, 0);
+            sys.ppu.ly_compare = Some(153);
+        }
+        4 => sys.ppu.ly_compare = Some(line),
+        8 if line == 153 => sys.ppu.ly_compare = Some(0),
+        _ => {}
+    }
+}
+
+/// Updates the STAT LYC==LY flag and the STAT interrupt line, and requests
+/// the interrupt if the line went high. With `is_stat_write`, the HBlank,
+/// VBlank and LYC sources are treated as enabled, but not the OAM scan one.
+fn update_stat_line(sys: &mut Sys, is_stat_write: bool) {
     let lyc = sys.mem.io_regs.get(IoReg::Lyc);
+    let is_lyc_match = sys.ppu.ly_compare == Some(lyc);
     let stat = sys.mem.io_regs.mut_(IoReg::Stat, |stat| {
-        let lyc_ly: u8 = (lyc == ly).into();
-        set_bit8(stat, 2, lyc_ly);
+        set_bit8(stat, 2, is_lyc_match.into());
     });
+    let stat = if is_stat_write { 0xDF } else { stat };
 
-    let lyc_ly_sel = bit8(&stat, 6) == 1;
-    let lyc_ly = bit8(&stat, 2) == 1;
+    let mode_flag_idx = match sys.ppu.mode {
+        PpuMode::HBlank => Some(3),
+        PpuMode::VBlank => Some(4),
+        PpuMode::OamScan => Some(5),
+        PpuMode::Draw => None,
+    };
+    let is_mode_source_high = mode_flag_idx.is_some_and(|idx| bit8(&stat, idx) == 1);
+    let is_lyc_source_high = is_lyc_match && bit8(&stat, 6) == 1;
+    let stat_line = is_mode_source_high || is_lyc_source_high;
 
-    if lyc_ly_sel && lyc_ly {
+    if stat_line && !sys.ppu.stat_line {
         request_interrupt(sys, InterruptType::Stat);
     }
+    // The write only affects the line for a moment.
+    if !is_stat_write {
+        sys.ppu.stat_line = stat_line;
+    }
 }
 
 fn enter_mode(sys: &mut Sys, mode: PpuMode) {
@@ -118,44 +321,241 @@ fn enter_mode(sys: &mut Sys, mode: PpuMode) {
         PpuMode::VBlank => {
             //render_screen(sys);
             sys.is_render_pending = true;
+            reset_window(sys);
             request_interrupt(sys, InterruptType::VBlank);
         }
-        PpuMode::Draw => {
-            let ly = sys.mem.io_regs.get(IoReg::Ly);
-            render_scanline(sys, ly, VIEWPORT_ORG);
+        PpuMode::Draw => start_pixel_fifo(sys, sys.ppu.line),
+        PpuMode::OamScan => {
+            check_window_y(sys, sys.ppu.line);
+            start_oam_scan(sys);
         }
-        _ => {}
+        PpuMode::HBlank => output_line(sys),
     }
 
     // Update the PPU mode indicator bits (1:0)
-    let stat = sys.mem.io_regs.mut_(IoReg::Stat, |stat| {
+    sys.mem.io_regs.mut_(IoReg::Stat, |stat| {
         *stat &= 0b1111_1100;
         *stat |= mode as u8;
     });
+}
 
-    // Request an interrupt if mode request condition is met.
-    let stat_mode_flag_idx = match mode {
-        PpuMode::HBlank => 3,
-        PpuMode::VBlank => 4,
-        PpuMode::OamScan => 5,
-        _ => {
-            return;
-        }
-    };
-    let is_stat_mode_flag_set = bit8(&stat, stat_mode_flag_idx) == 1;
-    if is_stat_mode_flag_set {
-        request_interrupt(sys, InterruptType::Stat);
+/// Copies the line that the pixel FIFO output to the framebuffer.
+fn output_line(sys: &mut Sys) {
+    let y = sys.ppu.line as usize;
+    if sys.ppu.is_first_frame {
+        sys.framebuffer.set_line(y, &[0; LINE_WIDTH]);
+    } else {
+        sys.framebuffer.set_line(y, &sys.ppu.fifo.line);
     }
 }
 
 pub fn print_ppu(sys: &Sys) {
     let dot = sys.ppu.curr_scanline_dot;
     let ly = sys.mem.io_regs.get(IoReg::Ly);
-    let mode = get_mode(dot, ly);
 
     println!("PPU:");
-    println!("  curr mode = {:?}", mode);
+    println!("  curr mode = {:?}", sys.ppu.mode);
     println!("  scanline dots = {}", dot);
     println!("  LY = {}", ly);
     println!("  frames drawn = {}", sys.ppu.total_frames_drawn);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::program::idle_sys;
+
+    use super::*;
+
+    fn stat_mode(sys: &Sys) -> u8 {
+        sys.mem.io_regs.get(IoReg::Stat) & 0b11
+    }
+
+    #[test]
+    fn test_lcd_off_holds_ly() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::If, 0);
+
+        // A bit more than a frame.
+        for _ in 0..20_000 {
+            update_ppu(&mut sys);
+        }
+
+        assert_eq!(sys.mem.io_regs.get(IoReg::Ly), 0);
+        assert_eq!(stat_mode(&sys), 0);
+        assert_eq!(sys.mem.io_regs.get(IoReg::If) & 0b11, 0);
+        // The frontend still gets a frame.
+        assert!(sys.is_render_pending);
+        assert_eq!(sys.ppu.total_frames_drawn(), 1);
+    }
+
+    /// Runs the PPU until the next frame is in the framebuffer.
+    fn run_frame(sys: &mut Sys) {
+        while !sys.is_render_pending {
+            update_ppu(sys);
+        }
+        sys.is_render_pending = false;
+    }
+
+    #[test]
+    fn test_frames_output_to_framebuffer() {
+        let mut sys = idle_sys();
+        // Every tile in the map is tile 0, which is made solid black.
+        for addr in 0x8000..0x8010 {
+            sys.mem.vram.write(addr as u16, 0xFF);
+        }
+        sys.mem.io_regs.set(IoReg::Bgp, 0b1110_0100);
+        update_ppu(&mut sys);
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x91);
+
+        // The first frame after the LCD is turned on is blank.
+        run_frame(&mut sys);
+        assert!(sys.framebuffer.shades().iter().all(|&shade| shade == 0));
+        run_frame(&mut sys);
+        assert!(sys.framebuffer.shades().iter().all(|&shade| shade == 3));
+
+        // Turning the LCD off shows white.
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x11);
+        update_ppu(&mut sys);
+        assert!(sys.framebuffer.shades().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_lcd_on_skips_first_oam_scan() {
+        let mut sys = idle_sys();
+        update_ppu(&mut sys);
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+
+        // Mode 3 starts 76 dots in, with no mode 2 before it.
+        for _ in 0..18 {
+            update_ppu(&mut sys);
+            assert_eq!(stat_mode(&sys), 0);
+        }
+        update_ppu(&mut sys);
+        assert_eq!(stat_mode(&sys), 3);
+
+        // The next line is as usual.
+        while sys.mem.io_regs.get(IoReg::Ly) == 0 {
+            update_ppu(&mut sys);
+        }
+        assert_eq!(stat_mode(&sys), 2);
+    }
+
+    /// Runs the PPU with the LCD on until `line` and `dot`.
+    fn run_until(sys: &mut Sys, line: u8, dot: u32) {
+        while sys.ppu.line != line || sys.ppu.curr_scanline_dot != dot {
+            update_ppu(sys);
+        }
+    }
+
+    /// Runs the PPU for a frame, and counts the STAT interrupts requested.
+    fn count_stat_interrupts(sys: &mut Sys) -> u32 {
+        let mut count = 0;
+        for _ in 0..DOTS_PER_SCANLINE * SCANLINES_PER_FRAME as u32 / 4 {
+            sys.mem.io_regs.set(IoReg::If, 0);
+            update_ppu(sys);
+            count += bit8(&sys.mem.io_regs.get(IoReg::If), 1) as u32;
+        }
+        count
+    }
+
+    #[test]
+    fn test_stat_interrupt_blocking() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        run_until(&mut sys, 0, 0);
+
+        // Going from HBlank straight to VBlank keeps the line high, so
+        // VBlank doesn't add an interrupt.
+        sys.mem.io_regs.set(IoReg::Stat, 0x18);
+        assert_eq!(count_stat_interrupts(&mut sys), 144);
+
+        // LY=LYC fires on line 10, but then holds the line high through
+        // that line's HBlank.
+        sys.mem.io_regs.set(IoReg::Lyc, 10);
+        sys.mem.io_regs.set(IoReg::Stat, 0x48);
+        assert_eq!(count_stat_interrupts(&mut sys), 144);
+    }
+
+    #[test]
+    fn test_ly_is_0_early_on_line_153() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        sys.mem.io_regs.set(IoReg::Lyc, 0);
+        run_until(&mut sys, 153, 0);
+        let lyc_flag = |sys: &Sys| bit8(&sys.mem.io_regs.get(IoReg::Stat), 2);
+
+        assert_eq!(sys.mem.io_regs.get(IoReg::Ly), 153);
+        assert_eq!(lyc_flag(&sys), 0);
+        update_ppu(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Ly), 0);
+        assert_eq!(lyc_flag(&sys), 0);
+        update_ppu(&mut sys);
+        assert_eq!(lyc_flag(&sys), 1);
+    }
+
+    #[test]
+    fn test_lyc_compare_starts_after_first_m_cycle() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        sys.mem.io_regs.set(IoReg::Lyc, 5);
+        run_until(&mut sys, 5, 0);
+        let lyc_flag = |sys: &Sys| bit8(&sys.mem.io_regs.get(IoReg::Stat), 2);
+
+        assert_eq!(lyc_flag(&sys), 0);
+        update_ppu(&mut sys);
+        assert_eq!(lyc_flag(&sys), 1);
+    }
+
+    #[test]
+    fn test_stat_write_during_vblank_fires() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        run_until(&mut sys, 150, 0);
+        sys.mem.io_regs.set(IoReg::If, 0);
+
+        sys.mem.io_regs.user_write(IoReg::Stat.as_addr(), 0x00);
+        update_ppu(&mut sys);
+
+        assert_eq!(bit8(&sys.mem.io_regs.get(IoReg::If), 1), 1);
+    }
+
+    #[test]
+    fn test_stat_write_during_oam_scan_doesnt_fire() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        sys.mem.io_regs.set(IoReg::Lyc, 100);
+        run_until(&mut sys, 1, 40);
+        assert_eq!(stat_mode(&sys), 2);
+        sys.mem.io_regs.set(IoReg::If, 0);
+
+        sys.mem.io_regs.user_write(IoReg::Stat.as_addr(), 0x00);
+        update_ppu(&mut sys);
+
+        assert_eq!(bit8(&sys.mem.io_regs.get(IoReg::If), 1), 0);
+    }
+
+    #[test]
+    fn test_vram_and_oam_blocked_by_mode() {
+        let mut sys = idle_sys();
+        sys.mem.io_regs.set(IoReg::Lcdc, 0x80);
+        sys.mem.write(0x8000, 0x12);
+        sys.mem.write(0xFE00, 0x34);
+
+        run_until(&mut sys, 1, 40);
+        assert_eq!(stat_mode(&sys), 2);
+        assert_eq!(sys.read(0x8000), 0x12);
+        assert_eq!(sys.read(0xFE00), 0xFF);
+
+        run_until(&mut sys, 1, 84);
+        assert_eq!(stat_mode(&sys), 3);
+        assert_eq!(sys.read(0x8000), 0xFF);
+        assert_eq!(sys.read(0xFE00), 0xFF);
+        sys.write(0x8000, 0x56);
+        assert_eq!(sys.mem.read(0x8000), 0x12);
+
+        run_until(&mut sys, 1, 300);
+        assert_eq!(stat_mode(&sys), 0);
+        assert_eq!(sys.read(0x8000), 0x12);
+        assert_eq!(sys.read(0xFE00), 0x34);
+    }
+}
@@ -47,12 +47,17 @@ pub fn draw_pixel<const TRANSPARENT: bool>(pos: IVec2, palette: &Palette, color_
     if TRANSPARENT && (color_id == 0) {
         return;
     }
-    let color = get_color(palette.map(color_id));
-    draw_rect(ir(pos, i2(1, 1)), color);
+    draw_shade(pos, palette.map(color_id));
 }
 
+/// Draws a pixel of the given color value (0 is white, 3 is black).
 #[inline]
-fn get_color(color_value: u8) -> Color {
+pub fn draw_shade(pos: IVec2, color_value: u8) {
+    draw_rect(ir(pos, i2(1, 1)), get_color(color_value));
+}
+
+#[inline]
+pub fn get_color(color_value: u8) -> Color {
     match color_value {
         0b00 => WHITE,
         0b01 => LIGHTGRAY,
@@ -0,0 +1,49 @@
+use macroquad::{
+    color::WHITE,
+    texture::{draw_texture, FilterMode, Texture2D},
+};
+use xf::num::ivec2::IVec2;
+
+use super::{
+    framebuffer::{Framebuffer, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH},
+    render_util::get_color,
+};
+
+/// Shows a system's framebuffer in the window, by uploading it to a texture
+/// once per frame.
+pub struct Screen {
+    texture: Texture2D,
+    rgba: Vec<u8>,
+}
+
+impl Screen {
+    /// Needs a window, so it can only be created by the frontend.
+    pub fn new() -> Self {
+        let rgba = vec![0xFF; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT * 4];
+        let texture =
+            Texture2D::from_rgba8(FRAMEBUFFER_WIDTH as u16, FRAMEBUFFER_HEIGHT as u16, &rgba);
+        texture.set_filter(FilterMode::Nearest);
+
+        Self { texture, rgba }
+    }
+
+    /// Draws the framebuffer with its top left corner at `org`.
+    pub fn draw(&mut self, framebuffer: &Framebuffer, org: IVec2) {
+        for (pixel, &shade) in self.rgba.chunks_exact_mut(4).zip(framebuffer.shades()) {
+            let color = get_color(shade);
+            pixel.copy_from_slice(&[
+                (color.r * 255.0) as u8,
+                (color.g * 255.0) as u8,
+                (color.b * 255.0) as u8,
+                0xFF,
+            ]);
+        }
+        self.texture.update_from_bytes(
+            FRAMEBUFFER_WIDTH as u32,
+            FRAMEBUFFER_HEIGHT as u32,
+            &self.rgba,
+        );
+
+        draw_texture(&self.texture, org.x as f32, org.y as f32, WHITE);
+    }
+}
@@ -40,6 +40,18 @@ fn lookup(c: char) -> IVec2 {
         _ if c.is_uppercase() => i2(8, 0) + alpha(c as i32 - 'A' as i32),
         _ if c.is_lowercase() => i2(8, 4) + alpha(c as i32 - 'a' as i32),
         _ if c.is_numeric() => i2(8, 8) + alpha(c as i32 - '0' as i32),
+        ',' => i2(8, 10),
+        '.' => i2(9, 10),
+        '!' => i2(10, 10),
+        ':' => i2(11, 10),
+        ';' => i2(12, 10),
+        '(' => i2(13, 10),
+        ')' => i2(14, 10),
+        '\'' => i2(15, 10),
+        '?' => i2(8, 11),
+        '$' => i2(9, 11),
+        '[' => i2(10, 11),
+        ']' => i2(11, 11),
 
         _ => i2(1, 11),
     };
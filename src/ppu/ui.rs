@@ -1,4 +1,4 @@
-use macroquad::color::BLACK;
+use macroquad::color::{BLACK, DARKBLUE};
 use xf::{
     mq::draw::draw_rect,
     num::{
@@ -7,12 +7,15 @@ use xf::{
     },
 };
 
-use crate::{consts::P8, other::joypad::draw_joypad_state, sys::Sys};
+use crate::{
+    consts::P8, cpu::disasm::disassemble_around, other::joypad::draw_joypad_state, sys::Sys,
+};
 
 use super::{
     consts::{
-        JOYPAD_ORG, TILE_DATA_BLOCK_DRAW_P8_SIZE, TILE_DATA_BLOCK_DRAW_SIZE, TILE_DATA_ORG,
-        TILE_MAP_ORG, VIEWPORT_P8_SIZE,
+        CODE_PANEL_LINES_AFTER, CODE_PANEL_LINES_BEFORE, CODE_PANEL_ORG, JOYPAD_ORG,
+        TILE_DATA_BLOCK_DRAW_P8_SIZE, TILE_DATA_BLOCK_DRAW_SIZE, TILE_DATA_ORG, TILE_MAP_ORG,
+        VIEWPORT_P8_SIZE,
     },
     lcdc::LcdcState,
     render_mem::{render_scroll_view_area, render_tile_data_block, render_tile_map},
@@ -22,36 +25,44 @@ use super::{
 
 const SHOW_SCROLL_AREA_OUTLINE: bool = false;
 
-pub fn render_ui(sys: &mut Sys) {
+/// Draws the UI around the viewport, with the window's top left corner at
+/// `org`.
+pub fn render_ui(sys: &mut Sys, org: IVec2) {
     let lcdc = LcdcState::from(sys);
 
     // Viewport.
-    draw_rect(ir(IVec2::ZERO, i2(VIEWPORT_P8_SIZE.x + 1, 1) * P8), BLACK);
-    draw_rect(ir(IVec2::ZERO, i2(1, VIEWPORT_P8_SIZE.y + 1) * P8), BLACK);
+    draw_rect(ir(org, i2(VIEWPORT_P8_SIZE.x + 1, 1) * P8), BLACK);
+    draw_rect(ir(org, i2(1, VIEWPORT_P8_SIZE.y + 1) * P8), BLACK);
     draw_rect(
         ir(
-            i2(VIEWPORT_P8_SIZE.x + 1, 0) * P8,
+            org + i2(VIEWPORT_P8_SIZE.x + 1, 0) * P8,
             i2(1, VIEWPORT_P8_SIZE.y + 1) * P8,
         ),
         BLACK,
     );
     draw_rect(
         ir(
-            i2(0, VIEWPORT_P8_SIZE.y + 1) * P8,
+            org + i2(0, VIEWPORT_P8_SIZE.y + 1) * P8,
             i2(VIEWPORT_P8_SIZE.x + 1, 1) * P8,
         ),
         BLACK,
     );
     let game_title = sys.mem.cart.header().title();
-    draw_text(game_title, i2(1, 0) * P8);
+    draw_text(game_title, org + i2(1, 0) * P8);
 
     // Joypad.
-    draw_joypad_state(JOYPAD_ORG);
+    draw_joypad_state(&sys.joypad, org + JOYPAD_ORG);
+    if sys.input_macros.is_recording() {
+        draw_text("REC", org + JOYPAD_ORG + i2(16, 1) * P8);
+    }
 
     if !sys.options.show_vram_views {
         return;
     }
 
+    // Code around PC.
+    render_code_panel(sys, org + CODE_PANEL_ORG);
+
     // Background tilemap view.
     let is_showing_win = sys.emu.show_win_map;
     let tile_map_area_is_9c00 = if is_showing_win {
@@ -63,31 +74,54 @@ pub fn render_ui(sys: &mut Sys) {
     let label = if is_showing_win { "WIN" } else { "BG" };
     draw_text(
         format!("0x{:0>4X} {} MAP", bg_tile_map_area, label),
-        TILE_MAP_ORG - i2(0, 8),
+        org + TILE_MAP_ORG - i2(0, 8),
     );
     let tile_map_addr = get_tile_map_addr(tile_map_area_is_9c00);
-    render_tile_map(sys, tile_map_addr, TILE_MAP_ORG);
+    render_tile_map(sys, tile_map_addr, org + TILE_MAP_ORG);
     let is_even_frame = sys.ppu.total_frames_drawn() % 2 == 0;
     if SHOW_SCROLL_AREA_OUTLINE && !is_showing_win && is_even_frame {
-        render_scroll_view_area(sys, TILE_MAP_ORG);
+        render_scroll_view_area(sys, org + TILE_MAP_ORG);
     }
 
     // Tile data blocks view.
-    draw_text("0x8000 TILES", TILE_DATA_ORG - i2(0, 8));
-    render_tile_data_block(sys, 0x8000, TILE_DATA_ORG);
-    draw_text("0x8800", TILE_DATA_ORG + i2(0, TILE_DATA_BLOCK_DRAW_SIZE.y));
+    let tile_data_org = org + TILE_DATA_ORG;
+    draw_text("0x8000 TILES", tile_data_org - i2(0, 8));
+    render_tile_data_block(sys, 0x8000, tile_data_org);
+    draw_text("0x8800", tile_data_org + i2(0, TILE_DATA_BLOCK_DRAW_SIZE.y));
     render_tile_data_block(
         sys,
         0x8800,
-        TILE_DATA_ORG + i2(0, TILE_DATA_BLOCK_DRAW_P8_SIZE.y + 1) * P8,
+        tile_data_org + i2(0, TILE_DATA_BLOCK_DRAW_P8_SIZE.y + 1) * P8,
     );
     draw_text(
         "0x9000",
-        TILE_DATA_ORG + i2(0, 2 * TILE_DATA_BLOCK_DRAW_SIZE.y) + i2(0, 8),
+        tile_data_org + i2(0, 2 * TILE_DATA_BLOCK_DRAW_SIZE.y) + i2(0, 8),
     );
     render_tile_data_block(
         sys,
         0x9000,
-        TILE_DATA_ORG + i2(0, 2 * TILE_DATA_BLOCK_DRAW_P8_SIZE.y + 2) * P8,
+        tile_data_org + i2(0, 2 * TILE_DATA_BLOCK_DRAW_P8_SIZE.y + 2) * P8,
+    );
+}
+
+/// Draws the disassembled instructions around PC, with PC's line highlighted.
+fn render_code_panel(sys: &Sys, org: IVec2) {
+    draw_text("CODE", org);
+
+    let pc = sys.regs.pc();
+    let lines = disassemble_around(
+        |addr| sys.mem.peek(addr),
+        pc,
+        CODE_PANEL_LINES_BEFORE,
+        CODE_PANEL_LINES_AFTER,
     );
+
+    for (i, (addr, text)) in lines.into_iter().enumerate() {
+        let pos = org + i2(0, i as i32 + 1) * P8;
+        if addr == pc {
+            draw_rect(ir(pos, i2(VIEWPORT_P8_SIZE.x, 1) * P8), DARKBLUE);
+        }
+
+        draw_text(format!("{:0>4X} {}", addr, text), pos);
+    }
 }
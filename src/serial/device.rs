@@ -0,0 +1,29 @@
+/// A peripheral connected to the other end of the link cable.
+pub trait SerialDevice {
+    /// Called when the Game Boy starts a transfer using its internal clock,
+    /// with `data` in SB.
+    fn start_transfer(&mut self, _data: u8) {}
+
+    /// Called when the Game Boy has shifted out the last bit of an internal
+    /// clock transfer of `data`. Returns the byte that was shifted into SB.
+    fn exchange(&mut self, data: u8) -> u8;
+
+    /// Called every M-cycle while the Game Boy waits for an external clock,
+    /// with `data` in SB. Returns the byte that was shifted in if the device
+    /// clocked a transfer.
+    fn poll_external_clock(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+
+    /// Called every M-cycle, before any of the other functions.
+    fn tick(&mut self) {}
+
+    /// Whether the device needs the Game Boy to run one M-cycle at a time,
+    /// so idle loops can't be jumped over.
+    fn needs_lock_step(&self) -> bool {
+        false
+    }
+
+    /// Called when the emulator stops, to write out anything buffered.
+    fn flush(&mut self) {}
+}
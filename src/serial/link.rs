@@ -0,0 +1,303 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use super::device::SerialDevice;
+
+/// How often (in M-cycles) both ends of the cable wait for each other.
+///
+/// A transfer started by one side is seen by the other at its next sync,
+/// and the reply arrives at the sync after that. Both have to happen
+/// within the 1024 M-cycles that an internal clock transfer lasts.
+const SYNC_PERIOD_MCYCLES: u32 = 256;
+
+/// A message sent over the cable. Each one is 2 bytes: a tag and a value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LinkMsg {
+    /// The sender has reached the end of a sync period.
+    Sync,
+    /// The sender started an internal clock transfer of this byte.
+    Transfer(u8),
+    /// The sender was clocked by a `Transfer`, and had this byte in SB.
+    Reply(u8),
+}
+
+impl LinkMsg {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            LinkMsg::Sync => [0, 0],
+            LinkMsg::Transfer(data) => [1, data],
+            LinkMsg::Reply(data) => [2, data],
+        }
+    }
+
+    fn from_bytes(bytes: [u8; 2]) -> Result<Self, String> {
+        match bytes {
+            [0, _] => Ok(LinkMsg::Sync),
+            [1, data] => Ok(LinkMsg::Transfer(data)),
+            [2, data] => Ok(LinkMsg::Reply(data)),
+            [tag, _] => Err(format!("Unknown link message: {}", tag)),
+        }
+    }
+}
+
+/// A link cable to another emulator instance over TCP.
+///
+/// Either side can drive the clock, as decided by SC on each transfer.
+/// To keep transfers deterministic, both emulators run in lock-step: every
+/// `SYNC_PERIOD_MCYCLES` each side sends a sync message and waits for the
+/// other's. Messages are only acted upon at these syncs, so what each side
+/// sees only depends on emulated time, never on how fast the hosts run.
+///
+/// If the other side isn't waiting for an external clock when a transfer
+/// reaches it, the byte is lost and the sender receives 0xFF, as with an
+/// unplugged cable. While the other instance is paused, this one waits for
+/// it at the next sync. If the connection is closed or fails, the cable acts
+/// unplugged.
+pub struct LinkCable {
+    stream: Option<TcpStream>,
+    m_cycles: u32,
+    /// Whether the Game Boy was waiting for an external clock last M-cycle.
+    waiting_external: bool,
+    /// A byte from the other side's transfer, which clocks ours.
+    received: Option<u8>,
+    /// The other side's reply to our transfer.
+    reply: Option<u8>,
+}
+
+impl LinkCable {
+    /// Waits for the other instance to connect on `addr` (e.g.
+    /// "127.0.0.1:8765").
+    pub fn host(addr: &str) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("Unable to listen on {}: {}", addr, e))?;
+        println!("Waiting for the link cable on {}...", addr);
+        let (stream, _) = listener
+            .accept()
+            .map_err(|e| format!("Unable to accept link: {}", e))?;
+
+        Self::from_stream(stream)
+    }
+
+    /// Connects to another instance that is hosting on `addr`.
+    pub fn connect(addr: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| format!("Unable to connect to {}: {}", addr, e))?;
+
+        Self::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Result<Self, String> {
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        println!("Link cable connected to {:?}.", stream.peer_addr());
+
+        Ok(Self {
+            stream: Some(stream),
+            m_cycles: 0,
+            waiting_external: false,
+            received: None,
+            reply: None,
+        })
+    }
+
+    fn send(&mut self, msg: LinkMsg) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        if let Err(e) = stream.write_all(&msg.to_bytes()) {
+            self.unplug(&e.to_string());
+        }
+    }
+
+    fn recv(&mut self) -> Option<LinkMsg> {
+        let stream = self.stream.as_mut()?;
+
+        let mut bytes = [0; 2];
+        let result = stream
+            .read_exact(&mut bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|_| LinkMsg::from_bytes(bytes));
+        match result {
+            Ok(msg) => Some(msg),
+            Err(msg) => {
+                self.unplug(&msg);
+                None
+            }
+        }
+    }
+
+    fn unplug(&mut self, reason: &str) {
+        println!("Link cable disconnected: {}", reason);
+        self.stream = None;
+    }
+
+    /// Sends our sync, then handles everything the other side sent up to
+    /// its own sync.
+    fn sync(&mut self, was_waiting_external: bool) {
+        self.send(LinkMsg::Sync);
+
+        while let Some(msg) = self.recv() {
+            match msg {
+                LinkMsg::Sync => break,
+                LinkMsg::Transfer(data) => {
+                    if was_waiting_external {
+                        self.received = Some(data);
+                    }
+                }
+                LinkMsg::Reply(data) => self.reply = Some(data),
+            }
+        }
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn start_transfer(&mut self, data: u8) {
+        self.reply = None;
+        self.send(LinkMsg::Transfer(data));
+    }
+
+    fn exchange(&mut self, _data: u8) -> u8 {
+        self.reply.take().unwrap_or(0xFF)
+    }
+
+    fn poll_external_clock(&mut self, data: u8) -> Option<u8> {
+        self.waiting_external = true;
+
+        let received = self.received.take()?;
+        self.send(LinkMsg::Reply(data));
+
+        Some(received)
+    }
+
+    fn tick(&mut self) {
+        let was_waiting_external = std::mem::take(&mut self.waiting_external);
+
+        self.m_cycles += 1;
+        if self.m_cycles >= SYNC_PERIOD_MCYCLES {
+            self.m_cycles = 0;
+            self.sync(was_waiting_external);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{
+        asm::assemble,
+        cart::cart::Cart,
+        cpu::regs::CpuReg8,
+        sys::{Options, Sys},
+        test::program::{run_until_label, sys_from_asm},
+    };
+
+    use super::*;
+
+    /// Sends $55 using the internal clock, after giving the other side time
+    /// to get ready. Also used by the local link tests.
+    pub const MASTER_SRC: &str = "
+        SECTION \"Entry\", ROM0[$100]
+            nop
+            jp Main
+
+        SECTION \"Main\", ROM0[$150]
+        Main:
+            ld b, 0
+        .delay:
+            dec b
+            jr nz, .delay
+            ld a, $55
+            ldh [$01], a
+            ld a, $81
+            ldh [$02], a
+        .wait:
+            ldh a, [$02]
+            bit 7, a
+            jr nz, .wait
+            ldh a, [$01]
+        Done:
+            jr Done
+    ";
+
+    /// Waits for an external clock with $AA in SB.
+    pub const SLAVE_SRC: &str = "
+        SECTION \"Entry\", ROM0[$100]
+            nop
+            jp Main
+
+        SECTION \"Main\", ROM0[$150]
+        Main:
+            ld a, $AA
+            ldh [$01], a
+            ld a, $80
+            ldh [$02], a
+        .wait:
+            ldh a, [$02]
+            bit 7, a
+            jr nz, .wait
+            ldh a, [$01]
+        Done:
+            jr Done
+    ";
+
+    #[test]
+    fn test_link_cable_exchanges_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let (mut master, master_program) = sys_from_asm(MASTER_SRC);
+
+        // The other instance runs on its own thread. Sys can't be sent
+        // across threads, so it is created there.
+        let slave_thread = std::thread::spawn(move || {
+            let program = assemble(SLAVE_SRC).unwrap();
+            let options = Options {
+                kill_on_dead_loop: false,
+                skip_idle_loops: true,
+                show_vram_views: false,
+            };
+            let mut slave = Sys::new(options, Cart::from_rom(&program.rom, false).unwrap());
+            slave
+                .mem
+                .io_regs
+                .set(crate::mem::io_regs::IoReg::Lcdc, 0x00);
+            slave
+                .serial
+                .connect(Box::new(LinkCable::connect(&addr).unwrap()));
+
+            let done = run_until_label(&mut slave, &program, "Done", 20_000);
+            (done, slave.regs.get_8(CpuReg8::A))
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        master
+            .serial
+            .connect(Box::new(LinkCable::from_stream(stream).unwrap()));
+        let master_done = run_until_label(&mut master, &master_program, "Done", 20_000);
+        let master_a = master.regs.get_8(CpuReg8::A);
+        drop(master);
+
+        let (slave_done, slave_a) = slave_thread.join().unwrap();
+        assert!(master_done && slave_done);
+        assert_eq!(master_a, 0xAA);
+        assert_eq!(slave_a, 0x55);
+    }
+
+    #[test]
+    fn test_link_cable_unplugs_when_peer_drops() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        drop(peer);
+
+        let (mut master, master_program) = sys_from_asm(MASTER_SRC);
+        master
+            .serial
+            .connect(Box::new(LinkCable::from_stream(stream).unwrap()));
+        let master_done = run_until_label(&mut master, &master_program, "Done", 20_000);
+
+        assert!(master_done);
+        assert_eq!(master.regs.get_8(CpuReg8::A), 0xFF);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::device::SerialDevice;
+
+/// What one end of a local link cable is doing.
+#[derive(Default)]
+struct LinkEnd {
+    /// SB, if the Game Boy waited for an external clock this M-cycle.
+    waiting_external: Option<u8>,
+    /// A byte clocked in by the other end, for the next poll.
+    received: Option<u8>,
+}
+
+/// A link cable between two systems in the same process.
+///
+/// Both systems have to be stepped in lock-step, one M-cycle each in turn,
+/// on the same thread. When a transfer with the internal clock completes
+/// and the other end is waiting for an external clock, the bytes are
+/// swapped. The other end sees its transfer complete on its next M-cycle.
+pub struct LocalLink {
+    ends: Rc<RefCell<[LinkEnd; 2]>>,
+    side: usize,
+}
+
+impl LocalLink {
+    /// Returns both ends of a new cable.
+    pub fn pair() -> (Self, Self) {
+        let ends = Rc::new(RefCell::new(Default::default()));
+
+        (
+            Self {
+                ends: ends.clone(),
+                side: 0,
+            },
+            Self { ends, side: 1 },
+        )
+    }
+}
+
+impl SerialDevice for LocalLink {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut ends = self.ends.borrow_mut();
+        let other = &mut ends[1 - self.side];
+
+        match other.waiting_external.take() {
+            Some(other_data) => {
+                other.received = Some(data);
+                other_data
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll_external_clock(&mut self, data: u8) -> Option<u8> {
+        let mut ends = self.ends.borrow_mut();
+        let end = &mut ends[self.side];
+
+        match end.received.take() {
+            Some(received) => Some(received),
+            None => {
+                end.waiting_external = Some(data);
+                None
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.ends.borrow_mut()[self.side].waiting_external = None;
+    }
+
+    /// The other end only stays in step if both run the same M-cycles.
+    fn needs_lock_step(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::regs::CpuReg8,
+        serial::link::tests::{MASTER_SRC, SLAVE_SRC},
+        test::program::{run_until_label, sys_from_asm},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_local_link_exchanges_bytes() {
+        let (mut master, master_program) = sys_from_asm(MASTER_SRC);
+        let (mut slave, slave_program) = sys_from_asm(SLAVE_SRC);
+        let (master_end, slave_end) = LocalLink::pair();
+        master.serial.connect(Box::new(master_end));
+        slave.serial.connect(Box::new(slave_end));
+
+        for _ in 0..20_000 {
+            master.run_one_m_cycle();
+            slave.run_one_m_cycle();
+        }
+
+        assert!(run_until_label(&mut master, &master_program, "Done", 10));
+        assert!(run_until_label(&mut slave, &slave_program, "Done", 10));
+        assert_eq!(master.regs.get_8(CpuReg8::A), 0xAA);
+        assert_eq!(slave.regs.get_8(CpuReg8::A), 0x55);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod device;
+pub mod link;
+pub mod local_link;
+pub mod printer;
+pub mod serial;
+pub mod text_capture;
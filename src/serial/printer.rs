@@ -0,0 +1,400 @@
+use std::path::PathBuf;
+
+use macroquad::{color::Color, texture::Image};
+
+use super::device::SerialDevice;
+
+/// Width of the printed image in pixels (20 tiles).
+const PRINT_WIDTH: usize = 160;
+/// Most image data the printer can buffer (9 DATA packets of 2 tile rows).
+const BUFFER_CAPACITY: usize = 0x1680;
+/// Pixel rows of blank paper fed per margin line.
+const MARGIN_FEED_ROWS: usize = 8;
+/// STATUS packets that report the printer as busy after a PRINT.
+const PRINT_BUSY_STATUS_POLLS: u8 = 3;
+
+/// Reply to the first byte after a packet's checksum.
+const DEVICE_ID: u8 = 0x81;
+
+// Status byte bits.
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_BUSY: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED_DATA: u8 = 1 << 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PacketPos {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LenLo,
+    LenHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    DeviceId,
+    Status,
+}
+
+/// A packet as received, before it is checked and handled.
+#[derive(Default)]
+struct Packet {
+    command: u8,
+    compressed: bool,
+    len: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    /// Sum of every byte from the command to the end of the data.
+    sum: u16,
+}
+
+/// The Game Boy Printer.
+///
+/// Games talk to it with packets: the magic bytes 0x88 0x33, a command, a
+/// compression flag, a 16-bit length, the data, and a 16-bit checksum,
+/// followed by 2 bytes during which the printer replies with its ID and
+/// its status. The commands are INIT (clears the buffer), DATA (buffers
+/// image tiles, optionally RLE compressed), PRINT (prints the buffer with
+/// the given margins, palette and exposure) and STATUS.
+///
+/// Each PRINT is written to `output_dir` as a PNG image.
+pub struct Printer {
+    output_dir: PathBuf,
+    pos: PacketPos,
+    packet: Packet,
+    /// Image data in 2bpp tile format, 20 tiles per row.
+    buffer: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+    /// Paths of every printout written so far.
+    pub printouts: Vec<PathBuf>,
+}
+
+impl Printer {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            pos: PacketPos::Magic1,
+            packet: Packet::default(),
+            buffer: Vec::new(),
+            status: 0,
+            busy_polls: 0,
+            printouts: Vec::new(),
+        }
+    }
+
+    /// Handles one byte of a packet. Returns the reply the printer had
+    /// ready for it.
+    fn receive(&mut self, data: u8) -> u8 {
+        let mut reply = 0x00;
+
+        self.pos = match self.pos {
+            PacketPos::Magic1 if data == 0x88 => PacketPos::Magic2,
+            PacketPos::Magic1 => PacketPos::Magic1,
+            PacketPos::Magic2 if data == 0x33 => {
+                self.packet = Packet::default();
+                PacketPos::Command
+            }
+            PacketPos::Magic2 => PacketPos::Magic1,
+            PacketPos::Command => {
+                self.packet.command = data;
+                self.packet.sum = data as u16;
+                PacketPos::Compression
+            }
+            PacketPos::Compression => {
+                self.packet.compressed = (data & 0x01) != 0;
+                self.packet.sum += data as u16;
+                PacketPos::LenLo
+            }
+            PacketPos::LenLo => {
+                self.packet.len = data as u16;
+                self.packet.sum += data as u16;
+                PacketPos::LenHi
+            }
+            PacketPos::LenHi => {
+                self.packet.len |= (data as u16) << 8;
+                self.packet.sum += data as u16;
+                if self.packet.len == 0 {
+                    PacketPos::ChecksumLo
+                } else {
+                    PacketPos::Data
+                }
+            }
+            PacketPos::Data => {
+                self.packet.data.push(data);
+                self.packet.sum = self.packet.sum.wrapping_add(data as u16);
+                if self.packet.data.len() >= self.packet.len as usize {
+                    PacketPos::ChecksumLo
+                } else {
+                    PacketPos::Data
+                }
+            }
+            PacketPos::ChecksumLo => {
+                self.packet.checksum = data as u16;
+                PacketPos::ChecksumHi
+            }
+            PacketPos::ChecksumHi => {
+                self.packet.checksum |= (data as u16) << 8;
+                self.handle_packet();
+                PacketPos::DeviceId
+            }
+            PacketPos::DeviceId => {
+                reply = DEVICE_ID;
+                PacketPos::Status
+            }
+            PacketPos::Status => {
+                reply = self.status;
+                PacketPos::Magic1
+            }
+        };
+
+        reply
+    }
+
+    fn handle_packet(&mut self) {
+        let packet = std::mem::take(&mut self.packet);
+        if packet.sum != packet.checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match packet.command {
+            // INIT
+            0x01 => {
+                self.buffer.clear();
+                self.busy_polls = 0;
+            }
+            // PRINT
+            0x02 => {
+                if let [sheets, margins, palette, exposure] = packet.data[..] {
+                    if sheets > 0 {
+                        self.print(margins, palette, exposure);
+                    }
+                    self.buffer.clear();
+                    self.busy_polls = PRINT_BUSY_STATUS_POLLS;
+                }
+            }
+            // DATA
+            0x04 => {
+                let data = if packet.compressed {
+                    decompress(&packet.data)
+                } else {
+                    packet.data
+                };
+                let space = BUFFER_CAPACITY - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(space));
+            }
+            // STATUS
+            0x0F => {
+                self.busy_polls = self.busy_polls.saturating_sub(1);
+            }
+            _ => {}
+        }
+
+        self.status &= STATUS_CHECKSUM_ERROR;
+        if self.busy_polls > 0 {
+            self.status |= STATUS_BUSY;
+        }
+        if self.buffer.len() >= BUFFER_CAPACITY {
+            self.status |= STATUS_IMAGE_FULL;
+        }
+        if !self.buffer.is_empty() {
+            self.status |= STATUS_UNPROCESSED_DATA;
+        }
+    }
+
+    /// Renders the buffer and writes it to the output folder.
+    fn print(&mut self, margins: u8, palette: u8, exposure: u8) {
+        let image = render_printout(&self.buffer, margins, palette, exposure);
+
+        if let Err(msg) = std::fs::create_dir_all(&self.output_dir) {
+            println!("Unable to create {:?}: {}", self.output_dir, msg);
+            return;
+        }
+        let path = self
+            .output_dir
+            .join(format!("printout_{:03}.png", self.printouts.len() + 1));
+        image.export_png(&path.to_string_lossy());
+        println!("Printed to: {:?}", path);
+
+        self.printouts.push(path);
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+}
+
+/// Expands the printer's RLE compression: a control byte with bit 7 set
+/// repeats the next byte (n & 0x7F) + 2 times, otherwise the next n + 1
+/// bytes are copied as is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let ctrl = data[i];
+        i += 1;
+        if (ctrl & 0x80) != 0 {
+            let count = (ctrl & 0x7F) as usize + 2;
+            if let Some(&value) = data.get(i) {
+                out.extend(std::iter::repeat_n(value, count));
+            }
+            i += 1;
+        } else {
+            let count = ctrl as usize + 1;
+            let end = usize::min(i + count, data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+/// Renders 2bpp tile data (20 tiles per row) with blank margins above and
+/// below, as set by the PRINT command.
+fn render_printout(tiles: &[u8], margins: u8, palette: u8, exposure: u8) -> Image {
+    let tile_rows = tiles.len() / (16 * 20);
+    let margin_before = (margins >> 4) as usize * MARGIN_FEED_ROWS;
+    let margin_after = (margins & 0x0F) as usize * MARGIN_FEED_ROWS;
+    let height = margin_before + tile_rows * 8 + margin_after;
+
+    let white = shade_color(0, exposure);
+    let mut image = Image::gen_image_color(PRINT_WIDTH as u16, height as u16, white);
+
+    for tile_row in 0..tile_rows {
+        for tile_col in 0..20 {
+            let tile = &tiles[((tile_row * 20 + tile_col) * 16)..][..16];
+            for y in 0..8 {
+                let lo = tile[y * 2];
+                let hi = tile[y * 2 + 1];
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    let color_id = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                    let shade = (palette >> (color_id * 2)) & 0b11;
+
+                    let px = tile_col * 8 + x;
+                    let py = margin_before + tile_row * 8 + y;
+                    // export_png() flips the image vertically.
+                    let flipped_py = height - 1 - py;
+                    image.set_pixel(px as u32, flipped_py as u32, shade_color(shade, exposure));
+                }
+            }
+        }
+    }
+
+    image
+}
+
+/// Returns the ink color for a shade (0 = white, 3 = black). Exposure 0x40
+/// is neutral, and lower or higher values print up to 25% lighter or
+/// darker.
+fn shade_color(shade: u8, exposure: u8) -> Color {
+    let darkness = shade as f32 / 3.0;
+    let exposure = (exposure & 0x7F) as f32 / 0x40 as f32 - 1.0;
+    let darkness = (darkness * (1.0 + 0.25 * exposure)).clamp(0.0, 1.0);
+    let level = 1.0 - darkness;
+
+    Color::new(level, level, level, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a packet with a correct checksum, followed by the 2 bytes that the
+    /// printer replies to.
+    fn make_packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut body = vec![command, compressed as u8, len as u8, (len >> 8) as u8];
+        body.extend_from_slice(data);
+        let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+
+        let mut packet = vec![0x88, 0x33];
+        packet.extend(body);
+        packet.extend([checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+        packet
+    }
+
+    fn send(printer: &mut Printer, packet: &[u8]) -> (u8, u8) {
+        let replies: Vec<u8> = packet.iter().map(|&b| printer.exchange(b)).collect();
+        (replies[replies.len() - 2], replies[replies.len() - 1])
+    }
+
+    fn temp_output_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust_gb_printer_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = [0x81, 0xAA, 0x02, 0x01, 0x02, 0x03];
+        assert_eq!(decompress(&data), vec![0xAA, 0xAA, 0xAA, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn test_status_replies() {
+        let mut printer = Printer::new(temp_output_dir("status"));
+
+        assert_eq!(
+            send(&mut printer, &make_packet(0x01, false, &[])),
+            (DEVICE_ID, 0x00)
+        );
+        assert_eq!(
+            send(&mut printer, &make_packet(0x04, false, &[0; 0x280])),
+            (DEVICE_ID, STATUS_UNPROCESSED_DATA)
+        );
+
+        let mut bad_packet = make_packet(0x0F, false, &[]);
+        bad_packet[6] ^= 0xFF;
+        assert_eq!(
+            send(&mut printer, &bad_packet).1 & STATUS_CHECKSUM_ERROR,
+            STATUS_CHECKSUM_ERROR
+        );
+    }
+
+    #[test]
+    fn test_print_writes_png() {
+        let dir = temp_output_dir("print");
+        let mut printer = Printer::new(&dir);
+
+        // Row 0 is made of black tiles (color 3), row 1 is compressed white.
+        let black_row = [0xFF; 16 * 20];
+        let compressed_white = [0xFF, 0x00, 0xFF, 0x00, 0x80 | (62 - 2), 0x00];
+
+        send(&mut printer, &make_packet(0x01, false, &[]));
+        send(&mut printer, &make_packet(0x04, false, &black_row));
+        send(&mut printer, &make_packet(0x04, true, &compressed_white));
+        send(&mut printer, &make_packet(0x04, false, &[]));
+        let (_, status) = send(
+            &mut printer,
+            &make_packet(0x02, false, &[1, 0x01, 0xE4, 0x40]),
+        );
+        assert_eq!(status & STATUS_BUSY, STATUS_BUSY);
+
+        assert_eq!(printer.printouts.len(), 1);
+        let bytes = std::fs::read(&printer.printouts[0]).unwrap();
+        let image = Image::from_file_with_format(&bytes, None).unwrap();
+        assert_eq!(
+            (image.width(), image.height()),
+            (160, 16 + MARGIN_FEED_ROWS)
+        );
+        assert_eq!(image.get_pixel(0, 0), Color::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(image.get_pixel(159, 8), Color::new(1.0, 1.0, 1.0, 1.0));
+        assert_eq!(image.get_pixel(0, 16), Color::new(1.0, 1.0, 1.0, 1.0));
+
+        // It stops being busy after a few status checks.
+        for _ in 0..PRINT_BUSY_STATUS_POLLS {
+            send(&mut printer, &make_packet(0x0F, false, &[]));
+        }
+        assert_eq!(printer.status & STATUS_BUSY, 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    cpu::interrupt::{request_interrupt, InterruptType},
+    mem::io_regs::IoReg,
+    sys::Sys,
+    util::math::bit8,
+};
+
+use super::{device::SerialDevice, text_capture::SerialTextCapture};
+
+/// The system counter bit whose falling edge shifts one bit with the
+/// internal clock (8192 Hz).
+const INTERNAL_CLOCK_BIT: u16 = 1 << 8;
+
+/// The serial port controller.
+///
+/// Writing SC with bit 7 set starts a transfer. With the internal clock
+/// (SC bit 0 set), SB is shifted out MSB first, one bit per 8192 Hz clock,
+/// and the byte from the connected device ends up in SB once the last bit
+/// is shifted (1s are shifted in until then). With the external clock, the
+/// transfer only completes when the device clocks it. Either way, SC bit 7
+/// is cleared and the Serial interrupt is requested once all 8 bits are
+/// shifted.
+pub struct Serial {
+    device: Option<Box<dyn SerialDevice>>,
+    /// Bits left to shift in the current internal clock transfer.
+    bits_left: u8,
+    /// The byte being sent.
+    outgoing: u8,
+    prev_clock: bool,
+}
+
+impl Serial {
+    /// Creates a serial port with a `SerialTextCapture` connected.
+    pub fn new() -> Self {
+        Self {
+            device: Some(Box::<SerialTextCapture>::default()),
+            bits_left: 0,
+            outgoing: 0xFF,
+            prev_clock: false,
+        }
+    }
+
+    /// Connects a device to the serial port, replacing the current one.
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    /// Writes out anything the connected device has buffered.
+    pub fn flush(&mut self) {
+        if let Some(device) = &mut self.device {
+            device.flush();
+        }
+    }
+
+    /// Unplugs the connected device. Bytes shifted in are then 0xFF.
+    #[cfg(test)]
+    pub fn disconnect(&mut self) {
+        self.device = None;
+    }
+}
+
+pub fn update_serial(sys: &mut Sys) {
+    // SB: data to send, and data received once the transfer is done.
+    // SC: .7: transfer enable/in progress; .0: clock select (1 = internal);
+
+    if let Some(device) = &mut sys.serial.device {
+        device.tick();
+    }
+
+    if sys.mem.io_regs.serial_transfer_requested {
+        sys.mem.io_regs.serial_transfer_requested = false;
+        start_transfer(sys);
+    }
+
+    let clock = (sys.timer.sys_counter & INTERNAL_CLOCK_BIT) != 0;
+    let clock_fell = sys.serial.prev_clock && !clock;
+    sys.serial.prev_clock = clock;
+
+    let sc = sys.mem.io_regs.get(IoReg::Sc);
+    if bit8(&sc, 7) == 0 {
+        sys.serial.bits_left = 0;
+        return;
+    }
+
+    if bit8(&sc, 0) == 1 {
+        if clock_fell && sys.serial.bits_left > 0 {
+            shift_bit(sys);
+        }
+    } else {
+        let sb = sys.mem.io_regs.get(IoReg::Sb);
+        let received = match &mut sys.serial.device {
+            Some(device) => device.poll_external_clock(sb),
+            None => None,
+        };
+        if let Some(data) = received {
+            sys.mem.io_regs.set(IoReg::Sb, data);
+            complete_transfer(sys);
+        }
+    }
+}
+
+/// Returns how many of the coming M-cycles the serial port would spend
+/// doing nothing: all of them, unless a transfer is running or starting, or
+/// the device needs every M-cycle.
+pub fn serial_idle_m_cycles(sys: &Sys) -> u32 {
+    let sc = sys.mem.io_regs.get(IoReg::Sc);
+    let needs_lock_step = sys
+        .serial
+        .device
+        .as_ref()
+        .is_some_and(|device| device.needs_lock_step());
+    if needs_lock_step || sys.mem.io_regs.serial_transfer_requested || bit8(&sc, 7) == 1 {
+        0
+    } else {
+        u32::MAX
+    }
+}
+
+/// Advances the serial port by `m_cycles`, which must not be more than
+/// `serial_idle_m_cycles`. The device is still ticked on every one of them.
+pub fn skip_serial_m_cycles(sys: &mut Sys, m_cycles: u32) {
+    if let Some(device) = &mut sys.serial.device {
+        for _ in 0..m_cycles {
+            device.tick();
+        }
+    }
+
+    sys.serial.bits_left = 0;
+    sys.serial.prev_clock = (sys.timer.sys_counter & INTERNAL_CLOCK_BIT) != 0;
+}
+
+fn start_transfer(sys: &mut Sys) {
+    let sc = sys.mem.io_regs.get(IoReg::Sc);
+    if bit8(&sc, 0) == 0 {
+        // Wait for the external clock.
+        sys.serial.bits_left = 0;
+        return;
+    }
+
+    let sb = sys.mem.io_regs.get(IoReg::Sb);
+    if let Some(device) = &mut sys.serial.device {
+        device.start_transfer(sb);
+    }
+    sys.serial.outgoing = sb;
+    sys.serial.bits_left = 8;
+}
+
+fn shift_bit(sys: &mut Sys) {
+    sys.mem.io_regs.mut_(IoReg::Sb, |sb| {
+        *sb = (*sb << 1) | 1;
+    });
+
+    sys.serial.bits_left -= 1;
+    if sys.serial.bits_left == 0 {
+        let outgoing = sys.serial.outgoing;
+        let received = match &mut sys.serial.device {
+            Some(device) => device.exchange(outgoing),
+            None => 0xFF,
+        };
+        sys.mem.io_regs.set(IoReg::Sb, received);
+        complete_transfer(sys);
+    }
+}
+
+fn complete_transfer(sys: &mut Sys) {
+    sys.mem.io_regs.mut_(IoReg::Sc, |sc| {
+        *sc &= 0x7F;
+    });
+    request_interrupt(sys, InterruptType::Serial);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::program::idle_sys;
+
+    use super::*;
+
+    /// M-cycles for a whole transfer with the internal clock.
+    const TRANSFER_M_CYCLES: u32 = 8 * 128;
+
+    /// Replies to every byte with its complement.
+    struct Inverter;
+
+    impl SerialDevice for Inverter {
+        fn exchange(&mut self, data: u8) -> u8 {
+            !data
+        }
+    }
+
+    fn start(sys: &mut Sys, sb: u8, sc: u8) {
+        sys.mem.io_regs.set(IoReg::If, 0x00);
+        sys.write(IoReg::Sb.as_addr(), sb);
+        sys.write(IoReg::Sc.as_addr(), sc);
+    }
+
+    fn serial_irq_requested(sys: &Sys) -> bool {
+        bit8(&sys.mem.io_regs.get(IoReg::If), 3) == 1
+    }
+
+    #[test]
+    fn test_internal_clock_transfer_without_device() {
+        let mut sys = idle_sys();
+        sys.serial.disconnect();
+        start(&mut sys, 0x12, 0x81);
+
+        for _ in 0..(TRANSFER_M_CYCLES - 128) {
+            sys.run_one_m_cycle();
+        }
+        assert_eq!(bit8(&sys.mem.io_regs.get(IoReg::Sc), 7), 1);
+        assert!(!serial_irq_requested(&sys));
+
+        for _ in 0..256 {
+            sys.run_one_m_cycle();
+        }
+        assert_eq!(sys.mem.io_regs.get(IoReg::Sb), 0xFF);
+        assert_eq!(bit8(&sys.mem.io_regs.get(IoReg::Sc), 7), 0);
+        assert!(serial_irq_requested(&sys));
+    }
+
+    #[test]
+    fn test_transfer_with_device() {
+        let mut sys = idle_sys();
+        sys.serial.connect(Box::new(Inverter));
+        start(&mut sys, 0x5A, 0x81);
+
+        for _ in 0..(TRANSFER_M_CYCLES + 128) {
+            sys.run_one_m_cycle();
+        }
+        assert_eq!(sys.mem.io_regs.get(IoReg::Sb), 0xA5);
+        assert!(serial_irq_requested(&sys));
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_device() {
+        let mut sys = idle_sys();
+        sys.serial.disconnect();
+        start(&mut sys, 0x12, 0x80);
+
+        for _ in 0..(TRANSFER_M_CYCLES * 4) {
+            sys.run_one_m_cycle();
+        }
+        assert_eq!(sys.mem.io_regs.get(IoReg::Sb), 0x12);
+        assert_eq!(bit8(&sys.mem.io_regs.get(IoReg::Sc), 7), 1);
+        assert!(!serial_irq_requested(&sys));
+    }
+}
@@ -0,0 +1,21 @@
+use super::device::SerialDevice;
+
+/// Logs every byte sent as a character, for test ROMs that print their
+/// results over serial (e.g. blargg's). Behaves like an unplugged cable
+/// otherwise.
+#[derive(Default)]
+pub struct SerialTextCapture {
+    log: String,
+}
+
+impl SerialDevice for SerialTextCapture {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.log.push(data as char);
+        0xFF
+    }
+
+    fn flush(&mut self) {
+        let log = std::mem::take(&mut self.log);
+        println!("{}", log);
+    }
+}
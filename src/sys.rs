@@ -1,24 +1,36 @@
 use crate::{
     cart::cart::Cart,
     cpu::{
-        exec::execute_next_instr,
+        idle::{execute_next_instr_or_replay, IdleLoop},
         interrupt::try_handle_interrupts,
         regs::{CpuReg16, CpuReg8, CpuRegs},
     },
-    debug::{self, debug_state},
-    mem::{io_regs::IoReg, mem::Mem},
-    other::{emu::Emu, joypad::handle_joypad_inputs},
-    ppu::ppu::{print_ppu, update_ppu, Ppu},
+    debug::{DebugConfig, DebugState},
+    mem::{io_regs::IoReg, mem::Mem, Addr},
+    other::{
+        emu::Emu,
+        input_macro::InputMacros,
+        joypad::{handle_joypad_inputs, InputSource, InputState, JoypadLines, JoypadState},
+    },
+    ppu::{
+        framebuffer::Framebuffer,
+        ppu::{print_ppu, update_ppu, Ppu},
+    },
+    serial::serial::{update_serial, Serial},
     time::{
         clock::Clock,
-        timers::{
-            update_timer_regs, CPU_PERIOD_MCYCLES, DIV_PERIOD_MCYCLES, TAC_CLK_0_PERIOD_MCYCLES,
-        },
+        timers::{update_timer_regs, Timer, CPU_PERIOD_MCYCLES},
     },
 };
 
+#[cfg(test)]
+use crate::mem::bus::FlatBus;
+
 pub struct Options {
-    pub kill_on_infinite_loop: bool,
+    /// Stop when the CPU is stuck in a loop that nothing can break.
+    pub kill_on_dead_loop: bool,
+    /// Fast-forward through loops that only wait on memory or interrupts.
+    pub skip_idle_loops: bool,
     pub show_vram_views: bool,
 }
 
@@ -26,16 +38,30 @@ pub struct Options {
 pub struct Sys {
     pub options: Options,
     pub emu: Emu,
+    pub debug: DebugState,
 
     pub mem: Mem,
+    /// When set, CPU memory accesses go to this bus instead of `mem`.
+    #[cfg(test)]
+    pub test_bus: Option<FlatBus>,
     pub ppu: Ppu,
+    /// The picture the PPU draws, for the frontend to show.
+    pub framebuffer: Framebuffer,
     pub regs: CpuRegs,
+    /// What the user is doing with the controls, as set by the frontend
+    /// each frame.
+    pub input: InputState,
+    pub input_macros: InputMacros,
+    /// The buttons the game sees held down, after turbo and macros.
+    pub joypad: JoypadState,
+    pub joypad_lines: JoypadLines,
 
     pub cpu_clock: Clock,
-    pub div_timer_clock: Clock,
-    pub tima_timer_clock: Clock,
+    pub timer: Timer,
+    pub serial: Serial,
 
     pub cpu_delay_ticks: u32,
+    pub idle_loop: IdleLoop,
 
     pub cpu_enable: bool,
     //pub lcd_enable: bool,
@@ -50,16 +76,25 @@ impl Sys {
         let mut sys = Self {
             options,
             emu: Emu::default(),
+            debug: DebugState::new(DebugConfig::default()),
 
             mem: Mem::new(cart),
+            #[cfg(test)]
+            test_bus: None,
             ppu: Ppu::new(),
+            framebuffer: Framebuffer::new(),
             regs: CpuRegs::new(),
+            input: InputState::default(),
+            input_macros: InputMacros::default(),
+            joypad: JoypadState::default(),
+            joypad_lines: JoypadLines::default(),
 
             cpu_clock: Clock::new("CPU", CPU_PERIOD_MCYCLES),
-            div_timer_clock: Clock::new("DIV", DIV_PERIOD_MCYCLES),
-            tima_timer_clock: Clock::new("TIMA", TAC_CLK_0_PERIOD_MCYCLES),
+            timer: Timer::new(),
+            serial: Serial::new(),
 
             cpu_delay_ticks: 0,
+            idle_loop: IdleLoop::default(),
 
             cpu_enable: true,
             //lcd_enable: true,
@@ -94,7 +129,7 @@ impl Sys {
         sys.mem.io_regs.set(P1, 0xCF);
         sys.mem.io_regs.set(Sb, 0x00);
         sys.mem.io_regs.set(Sc, 0x7E);
-        sys.mem.io_regs.set(Div, 0xAB);
+        sys.mem.io_regs.set(Div, (sys.timer.sys_counter >> 8) as u8);
         sys.mem.io_regs.set(Tima, 0x00);
         sys.mem.io_regs.set(Tma, 0x00);
         sys.mem.io_regs.set(Tac, 0xF8);
@@ -117,35 +152,91 @@ impl Sys {
         sys.mem.io_regs.set(Ie, 0x00);
     }
 
+    /// Reads a byte from the bus the CPU is connected to. Memory that is in
+    /// use by the PPU or DMA reads 0xFF.
+    pub fn read(&self, addr: Addr) -> u8 {
+        #[cfg(test)]
+        if let Some(bus) = &self.test_bus {
+            return bus.read(addr);
+        }
+
+        let data = if self.ppu.is_cpu_accessible(addr) {
+            self.mem.read(addr)
+        } else {
+            0xFF
+        };
+        self.debug.record_io_reg_access(addr, false, data);
+
+        data
+    }
+
+    /// Writes a byte to the bus the CPU is connected to. Writes to memory
+    /// that is in use by the PPU or DMA are ignored.
+    pub fn write(&mut self, addr: Addr, data: u8) {
+        #[cfg(test)]
+        if let Some(bus) = &mut self.test_bus {
+            return bus.write(addr, data);
+        }
+
+        if self.ppu.is_cpu_accessible(addr) {
+            self.mem.write(addr, data);
+        }
+        self.debug.record_io_reg_access(addr, true, data);
+    }
+
+    /// Sets the input state for the next frame from `source`.
+    pub fn update_input(&mut self, source: &mut dyn InputSource) {
+        self.input = source.input_state(self.ppu.total_frames_drawn());
+    }
+
+    /// Runs `count` frames with the current input, e.g. several per frame of
+    /// the frontend when sped up. The hotkeys are handled once, on the first
+    /// frame.
+    pub fn run_frames(&mut self, count: u32) {
+        for _ in 0..count {
+            while !self.is_render_pending && !self.hard_lock {
+                self.run_one_m_cycle();
+            }
+            self.is_render_pending = false;
+        }
+    }
+
     pub fn run_one_m_cycle(&mut self) {
         if self.cpu_clock.update_and_check() {
             self.cpu_delay_ticks = u32::saturating_sub(self.cpu_delay_ticks, 1);
             if self.cpu_delay_ticks == 0 {
                 try_handle_interrupts(self);
                 if self.cpu_enable {
-                    self.cpu_delay_ticks = execute_next_instr(self);
+                    self.cpu_delay_ticks = execute_next_instr_or_replay(self);
                 }
             }
         }
 
         update_ppu(self);
         update_timer_regs(self);
+        update_serial(self);
         handle_joypad_inputs(self);
 
         ///////// DEBUG //////////////////////////////////////////////
-        if let Some(kill_after_nop_count) = debug_state().config.kill_after_nop_count {
-            if debug_state().nop_count >= kill_after_nop_count {
-                debug::fail("Debug max NOP count exceeded.");
+        if cfg!(feature = "debug_hooks") {
+            if let Some(kill_after_nop_count) = self.debug.config.kill_after_nop_count {
+                if self.debug.nop_count >= kill_after_nop_count {
+                    self.debug.fail("Debug max NOP count exceeded.");
+                }
             }
-        }
 
-        if let Some(kill_after_ticks) = debug_state().config.kill_after_cpu_ticks {
-            if self.cpu_clock.debug_total_ticks >= kill_after_ticks {
-                debug::fail("Debug kill time elapsed.");
+            if let Some(kill_after_ticks) = self.debug.config.kill_after_cpu_ticks {
+                if self.cpu_clock.debug_total_ticks >= kill_after_ticks {
+                    self.debug.fail("Debug kill time elapsed.");
+                }
             }
         }
 
-        if let Some(failure) = debug::get_failure() {
+        if let Some(msg) = self.mem.take_bad_access() {
+            self.debug.fail(msg);
+        }
+
+        if let Some(failure) = self.debug.get_failure() {
             println!("FAILURE: {}", failure);
             //debug::print_system_state(&self);
             self.hard_lock = true;
@@ -155,13 +246,13 @@ impl Sys {
     pub fn print(&self) {
         self.regs.print();
         println!("IME={}", self.interrupt_master_enable);
+        println!("In idle loop={}", self.idle_loop.is_replaying());
         println!("IE={:0>8b}", self.mem.io_regs.get(IoReg::Ie));
         println!("IF={:0>8b}", self.mem.io_regs.get(IoReg::If));
 
         print_ppu(self);
 
         self.cpu_clock.print();
-        self.div_timer_clock.print();
-        self.tima_timer_clock.print();
+        self.timer.print();
     }
 }
@@ -0,0 +1,179 @@
+//! Runs the acid2 PPU test ROMs (https://github.com/mattcurrie/dmg-acid2 and
+//! https://github.com/mattcurrie/cgb-acid2) and compares the picture they
+//! leave in the framebuffer with their reference images.
+//!
+//! The ROMs and reference images go in `assets/tests/acid2`, as
+//! `dmg-acid2.gb` and `dmg-acid2.png` (and `cgb-acid2.gbc` and
+//! `cgb-acid2.png`). A test fails if its files are missing. When the
+//! picture doesn't match, a diff image is written to `target/acid2`, with the
+//! wrong pixels in red over a faded copy of the reference.
+
+use std::{fs::File, path::Path};
+
+use crate::{
+    cart::cart::Cart,
+    ppu::framebuffer::{FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH},
+    sys::{Options, Sys},
+};
+
+const TEST_DIR: &str = "assets/tests/acid2";
+const DIFF_DIR: &str = "target/acid2";
+
+/// The tests are done well before this, and then wait in a loop.
+const FRAME_COUNT: u32 = 30;
+
+/// The grey the reference images use for each shade, from white to black.
+const SHADE_GREYS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// Runs the ROM for a fixed number of frames and returns the shades in the
+/// framebuffer.
+fn run_rom(rom_path: &Path) -> Result<Vec<u8>, String> {
+    let cart = Cart::load_from(&rom_path.to_string_lossy(), false)?;
+    let options = Options {
+        kill_on_dead_loop: false,
+        skip_idle_loops: true,
+        show_vram_views: false,
+    };
+    let mut sys = Sys::new(options, cart);
+
+    for _ in 0..FRAME_COUNT {
+        while !sys.is_render_pending && !sys.hard_lock {
+            sys.run_one_m_cycle();
+        }
+        sys.is_render_pending = false;
+    }
+
+    Ok(sys.framebuffer.shades().to_vec())
+}
+
+/// Loads a reference image as RGB pixels, line by line.
+fn load_reference(path: &Path) -> Result<Vec<[u8; 3]>, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| format!("Unable to decode {:?}: {}", path, e))?;
+
+    if (info.width as usize, info.height as usize) != (FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT) {
+        return Err(format!(
+            "{:?} is {}x{}, expected {}x{}",
+            path, info.width, info.height, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT
+        ));
+    }
+
+    let channels = info.color_type.samples();
+    Ok(buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| match channels {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        })
+        .collect())
+}
+
+fn shade_to_rgb(shade: u8) -> [u8; 3] {
+    [SHADE_GREYS[shade as usize]; 3]
+}
+
+/// Writes the reference image faded out, with the pixels that differ in red.
+fn write_diff_image(path: &Path, shades: &[u8], reference: &[[u8; 3]]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Unable to create {:?}: {}", dir, e))?;
+    }
+
+    let mut rgb = Vec::with_capacity(reference.len() * 3);
+    for (&shade, &expected) in shades.iter().zip(reference) {
+        if shade_to_rgb(shade) == expected {
+            rgb.extend(expected.map(|c| c / 4 + 0xC0));
+        } else {
+            rgb.extend([0xFF, 0x00, 0x00]);
+        }
+    }
+
+    let file = File::create(path).map_err(|e| format!("Unable to create {:?}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(file, FRAMEBUFFER_WIDTH as u32, FRAMEBUFFER_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb))
+        .map_err(|e| format!("Unable to write {:?}: {}", path, e))
+}
+
+/// Runs `<name>.<rom_ext>` and compares its output with `<name>.png`.
+fn run_acid2_test(name: &str, rom_ext: &str) -> Result<(), String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = root.join(TEST_DIR);
+    let rom_path = dir.join(format!("{}.{}", name, rom_ext));
+    let reference_path = dir.join(format!("{}.png", name));
+    for path in [&rom_path, &reference_path] {
+        if !path.exists() {
+            return Err(format!(
+                "{:?} is missing, see {:?}",
+                path,
+                dir.join("README.md")
+            ));
+        }
+    }
+
+    let reference = load_reference(&reference_path)?;
+    let shades = run_rom(&rom_path)?;
+
+    let wrong_pixels = shades
+        .iter()
+        .zip(&reference)
+        .filter(|(&shade, &expected)| shade_to_rgb(shade) != expected)
+        .count();
+    if wrong_pixels == 0 {
+        return Ok(());
+    }
+
+    let diff_path = root.join(DIFF_DIR).join(format!("{}-diff.png", name));
+    write_diff_image(&diff_path, &shades, &reference)?;
+    Err(format!(
+        "{} pixels differ from the reference, see {:?}",
+        wrong_pixels, diff_path
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dmg_acid2() {
+        if let Err(msg) = run_acid2_test("dmg-acid2", "gb") {
+            panic!("{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_diff_image_marks_wrong_pixels() {
+        let mut shades = vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT];
+        let reference = vec![shade_to_rgb(0); shades.len()];
+        shades[1] = 3;
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(DIFF_DIR)
+            .join("diff-test.png");
+        write_diff_image(&path, &shades, &reference).unwrap();
+        let diff = load_reference(&path).unwrap();
+
+        assert_eq!(diff[0], [0xFF; 3]);
+        assert_eq!(diff[1], [0xFF, 0x00, 0x00]);
+    }
+
+    // The emulator only implements DMG hardware: there are no CGB palettes,
+    // VRAM banks or tile attributes, so this can't pass yet.
+    #[test]
+    #[ignore = "only DMG hardware is emulated"]
+    fn test_cgb_acid2() {
+        if let Err(msg) = run_acid2_test("cgb-acid2", "gbc") {
+            panic!("{}", msg);
+        }
+    }
+}
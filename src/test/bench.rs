@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    cart::cart::Cart,
+    cpu::{
+        exec::execute_next_instr,
+        instr::{decode, lookup, Instr},
+    },
+    debug::{DebugConfig, DebugState},
+    mem::io_regs::IoReg,
+    sys::{Options, Sys},
+};
+
+/// A tight loop of loads, ALU ops and a memory write, placed at 0x0100.
+const BENCH_LOOP: &[u8] = &[
+    0x21, 0x00, 0xC0, // ld hl, $C000
+    0x3C, // inc a
+    0x80, // add a, b
+    0x47, // ld b, a
+    0xA9, // xor c
+    0x77, // ld [hl], a
+    0xCB, 0x37, // swap a
+    0x18, 0xF6, // jr -10
+];
+
+/// A loop waiting for LY to reach 144, placed at 0x0100.
+const IDLE_LOOP: &[u8] = &[
+    0xF0, 0x44, // ldh a, [$44]
+    0xFE, 0x90, // cp 144
+    0x20, 0xFA, // jr nz, -6
+    0x18, 0xF8, // jr -8
+];
+
+/// Creates a ROM-only cartridge that runs `code` forever.
+fn make_bench_cart(code: &[u8]) -> Cart {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..(0x0100 + code.len())].copy_from_slice(code);
+
+    Cart::from_rom(&rom, false).unwrap()
+}
+
+/// Times decoding every opcode `rounds` times with the bit pattern decoder
+/// and with the precomputed table.
+pub fn bench_instr_decode(rounds: u32) -> (Duration, Duration) {
+    let start = Instant::now();
+    let mut decoded = 0u32;
+    for _ in 0..rounds {
+        for op in 0x00..=0xFF {
+            if let Ok(instr) = decode(std::hint::black_box(op), false) {
+                decoded += (instr != Instr::Nop) as u32;
+            }
+        }
+    }
+    let decode_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut looked_up = 0u32;
+    for _ in 0..rounds {
+        for op in 0x00..=0xFF {
+            let instr = lookup(std::hint::black_box(op), false);
+            looked_up += (instr != Instr::Nop) as u32;
+        }
+    }
+    let lookup_time = start.elapsed();
+
+    std::hint::black_box((decoded, looked_up));
+
+    (decode_time, lookup_time)
+}
+
+/// Times executing `instr_count` instructions of `BENCH_LOOP`, with
+/// instruction recording enabled or disabled.
+pub fn bench_execute(instr_count: u32, record_instrs: bool) -> Duration {
+    let options = Options {
+        kill_on_dead_loop: false,
+        skip_idle_loops: true,
+        show_vram_views: false,
+    };
+    let mut sys = Sys::new(options, make_bench_cart(BENCH_LOOP));
+    sys.debug = DebugState::new(DebugConfig {
+        record_instrs,
+        ..Default::default()
+    });
+
+    let start = Instant::now();
+    for _ in 0..instr_count {
+        execute_next_instr(&mut sys);
+    }
+    let elapsed = start.elapsed();
+
+    if record_instrs {
+        assert_eq!(sys.debug.total_instrs_executed, instr_count as u64);
+    }
+
+    elapsed
+}
+
+/// Times running `m_cycles` M-cycles of `IDLE_LOOP`, with idle loop
+/// skipping enabled or disabled, and the LCD on or off.
+pub fn bench_idle_loop(m_cycles: u32, skip_idle_loops: bool, is_lcd_on: bool) -> Duration {
+    let options = Options {
+        kill_on_dead_loop: false,
+        skip_idle_loops,
+        show_vram_views: false,
+    };
+    let mut sys = Sys::new(options, make_bench_cart(IDLE_LOOP));
+    let lcdc = if is_lcd_on { 0x91 } else { 0x00 };
+    sys.mem.io_regs.set(IoReg::Lcdc, lcdc);
+
+    // Idle loops can be jumped over, so go by emulated time.
+    let start = Instant::now();
+    while sys.cpu_clock.debug_total_ticks < m_cycles as u64 {
+        sys.run_one_m_cycle();
+    }
+
+    start.elapsed()
+}
+
+/// Prints the decode and execution benchmark results to the console.
+pub fn run_benchmarks() {
+    const DECODE_ROUNDS: u32 = 20_000;
+    const INSTR_COUNT: u32 = 5_000_000;
+
+    let (decode_time, lookup_time) = bench_instr_decode(DECODE_ROUNDS);
+    println!("decode {} opcodes:", DECODE_ROUNDS * 256);
+    println!("  bit patterns: {:?}", decode_time);
+    println!("  table lookup: {:?}", lookup_time);
+
+    let recording_time = bench_execute(INSTR_COUNT, true);
+    let plain_time = bench_execute(INSTR_COUNT, false);
+    println!("execute {} instrs:", INSTR_COUNT);
+    println!("  recording on:  {:?}", recording_time);
+    println!("  recording off: {:?}", plain_time);
+
+    for is_lcd_on in [false, true] {
+        let skipping_time = bench_idle_loop(INSTR_COUNT, true, is_lcd_on);
+        let looping_time = bench_idle_loop(INSTR_COUNT, false, is_lcd_on);
+        let lcd = if is_lcd_on { "on" } else { "off" };
+        println!("run {} M-cycles of an idle loop, LCD {}:", INSTR_COUNT, lcd);
+        println!("  skipping on:  {:?}", skipping_time);
+        println!("  skipping off: {:?}", looping_time);
+
+        // With the LCD on, most of the time goes into drawing, which can't
+        // be skipped.
+        if !is_lcd_on {
+            assert!(
+                skipping_time * 10 < looping_time,
+                "Skipping idle loops should be much faster"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench`.
+    #[test]
+    #[ignore]
+    fn bench_cpu_hot_path() {
+        run_benchmarks();
+    }
+}
@@ -2,12 +2,15 @@ use macroquad::{
     input::{is_key_pressed, KeyCode},
     window::next_frame,
 };
-use xf::mq::window::{Window, WindowParams};
+use xf::{
+    mq::window::{Window, WindowParams},
+    num::ivec2::IVec2,
+};
 
 use crate::{
     cart::cart::Cart,
     consts::{PIXEL_SCALE, SCREEN_SIZE},
-    debug::{self, initialize_debug, DebugConfig},
+    debug::{DebugConfig, DebugState},
     ppu::ui::render_ui,
     sys::{Options, Sys},
 };
@@ -16,12 +19,14 @@ use crate::{
 /// to the console.
 #[allow(dead_code)]
 async fn run_blarggs_test_suite() {
-    initialize_debug(DebugConfig {
+    let debug_config = DebugConfig {
         enable_debug_print: false,
+        record_instrs: true,
+        record_io_reg_usage: true,
         kill_after_cpu_ticks: None, //Some(1__000),
         kill_after_nop_count: None, // Some(16),
         last_instr_count: 5,
-    });
+    };
 
     let window = Window::new(WindowParams {
         resolution: SCREEN_SIZE,
@@ -47,11 +52,13 @@ async fn run_blarggs_test_suite() {
 
     for path in rom_paths {
         let options = Options {
-            kill_on_infinite_loop: true,
+            kill_on_dead_loop: true,
+            skip_idle_loops: true,
             show_vram_views: true,
         };
         let cart = Cart::load_from(path, false).unwrap();
         let mut sys = Sys::new(options, cart);
+        sys.debug = DebugState::new(debug_config.clone());
 
         let rom_name = std::path::Path::new(path)
             .file_name()
@@ -68,13 +75,13 @@ async fn run_blarggs_test_suite() {
 
             if sys.is_render_pending {
                 window.render_pass(|| {
-                    render_ui(&mut sys);
+                    render_ui(&mut sys, IVec2::ZERO);
                 });
                 next_frame().await;
                 sys.is_render_pending = false;
             }
         }
 
-        debug::flush_serial_char();
+        sys.serial.flush();
     }
 }
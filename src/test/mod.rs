@@ -1,2 +1,9 @@
+#[cfg(test)]
+pub mod acid2;
+#[cfg(test)]
+pub mod bench;
 pub mod blargg;
 pub mod instr;
+pub mod program;
+#[cfg(test)]
+pub mod sm83;
@@ -0,0 +1,102 @@
+use crate::{
+    asm::{assemble, Program},
+    cart::cart::Cart,
+    mem::io_regs::IoReg,
+    sys::{Options, Sys},
+};
+
+/// Assembles `src` and creates a `Sys` that runs it from $0100.
+///
+/// The LCD starts off, so tests that don't care about the PPU don't spend
+/// time drawing. Buttons can be pressed by setting `sys.input`.
+#[allow(dead_code)]
+pub fn sys_from_asm(src: &str) -> (Sys, Program) {
+    let program = match assemble(src) {
+        Ok(program) => program,
+        Err(msg) => panic!("{}", msg),
+    };
+    let cart = Cart::from_rom(&program.rom, false).unwrap();
+    let options = Options {
+        kill_on_dead_loop: false,
+        skip_idle_loops: true,
+        show_vram_views: false,
+    };
+
+    let mut sys = Sys::new(options, cart);
+    sys.mem.io_regs.set(IoReg::Lcdc, 0x00);
+
+    (sys, program)
+}
+
+const IDLE_SRC: &str = "
+    SECTION \"Entry\", ROM0[$100]
+    Done:
+        jr Done
+";
+
+/// Creates a `Sys` whose program just loops, for tests of the hardware
+/// around the CPU. The LCD starts off, as with `sys_from_asm`.
+#[allow(dead_code)]
+pub fn idle_sys() -> Sys {
+    sys_from_asm(IDLE_SRC).0
+}
+
+/// Runs `sys` until PC reaches `label`. Returns false if it isn't reached
+/// within `max_m_cycles`. The ROM bank mapped in isn't checked.
+#[allow(dead_code)]
+pub fn run_until_label(sys: &mut Sys, program: &Program, label: &str, max_m_cycles: u64) -> bool {
+    let Some(loc) = program.label_addr(label) else {
+        panic!("Unknown label: {}", label);
+    };
+
+    for _ in 0..max_m_cycles {
+        sys.run_one_m_cycle();
+        if sys.regs.pc() == loc.addr {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::regs::CpuReg8;
+
+    use super::*;
+
+    #[test]
+    fn test_run_inline_program() {
+        let src = r#"
+SECTION "Vars", WRAM0
+wResult: ds 1
+
+SECTION "Entry", ROM0[$100]
+    nop
+    jp Main
+
+SECTION "Main", ROM0[$150]
+Main:
+    ld sp, $DFFF
+    xor a
+    ld b, 10
+.loop:
+    call AddB
+    dec b
+    jr nz, .loop
+    ld [wResult], a
+Done:
+    jr Done
+
+; Adds B to A.
+AddB:
+    add a, b
+    ret
+"#;
+        let (mut sys, program) = sys_from_asm(src);
+
+        assert!(run_until_label(&mut sys, &program, "Done", 10_000));
+        assert_eq!(sys.regs.get_8(CpuReg8::A), 55);
+        assert_eq!(sys.read(program.label_addr("wResult").unwrap().addr), 55);
+    }
+}
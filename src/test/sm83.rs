@@ -0,0 +1,297 @@
+//! Runs the CPU against the SM83 single-step test vectors
+//! (https://github.com/SingleStepTests/sm83).
+//!
+//! The JSON files of the `v1` folder of that repository go in
+//! `assets/tests/sm83`, one per opcode (e.g. `3c.json` or `cb 37.json`). The
+//! test fails if there are none. A case gives the initial and final register
+//! and RAM state, plus the bus activity of every M-cycle. The instruction is
+//! executed once on a flat 64KB bus, and the final state, the M-cycle count
+//! and the reads and writes, in order, are compared against the expected
+//! values. The CPU doesn't time the accesses within an instruction, so the
+//! M-cycles without one are only counted. The opcode is expected at the
+//! initial PC.
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    cart::cart::Cart,
+    cpu::{
+        exec::execute_next_instr,
+        regs::{CpuReg16, CpuReg8},
+    },
+    mem::{
+        bus::{BusAccess, FlatBus},
+        io_regs::IoReg,
+    },
+    sys::{Options, Sys},
+};
+
+const TEST_DIR: &str = "assets/tests/sm83";
+
+/// Only the first few failing cases of each opcode are printed.
+const MAX_REPORTED_CASES: usize = 3;
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: CpuState,
+    #[serde(rename = "final")]
+    final_: CpuState,
+    cycles: Vec<(Option<u16>, Option<u8>, String)>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CpuState {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    #[serde(default)]
+    ime: u8,
+    ie: Option<u8>,
+    ram: Vec<(u16, u8)>,
+}
+
+/// The failed cases for a single opcode file.
+struct OpcodeFailures {
+    opcode: String,
+    total_cases: usize,
+    failures: Vec<String>,
+}
+
+fn make_test_sys() -> Sys {
+    let rom = vec![0; 0x8000];
+    let cart = Cart::from_rom(&rom, false).unwrap();
+    let options = Options {
+        kill_on_dead_loop: false,
+        skip_idle_loops: true,
+        show_vram_views: false,
+    };
+
+    let mut sys = Sys::new(options, cart);
+    sys.test_bus = Some(FlatBus::default());
+
+    sys
+}
+
+fn load_state(sys: &mut Sys, state: &CpuState) {
+    use CpuReg8::*;
+    sys.regs.set_8(A, state.a);
+    sys.regs.set_8(F, state.f);
+    sys.regs.set_8(B, state.b);
+    sys.regs.set_8(C, state.c);
+    sys.regs.set_8(D, state.d);
+    sys.regs.set_8(E, state.e);
+    sys.regs.set_8(H, state.h);
+    sys.regs.set_8(L, state.l);
+    sys.regs.set_16(CpuReg16::SP, state.sp);
+    sys.regs.set_16(CpuReg16::PC, state.pc);
+    sys.interrupt_master_enable = state.ime != 0;
+
+    // IE is both in RAM, for the CPU to read, and in its register, for the
+    // interrupt logic.
+    if let Some(ie) = state.ie {
+        sys.write(0xFFFF, ie);
+        sys.mem.io_regs.set(IoReg::Ie, ie);
+    }
+    for &(addr, data) in &state.ram {
+        sys.write(addr, data);
+    }
+}
+
+/// Returns the reads and writes of the bus activity of a case, in order.
+fn expected_accesses(case: &TestCase) -> Vec<BusAccess> {
+    case.cycles
+        .iter()
+        .filter_map(|(addr, data, activity)| {
+            let is_read = activity.starts_with('r');
+            let is_write = activity.get(1..2) == Some("w");
+            (is_read || is_write).then(|| BusAccess {
+                addr: addr.unwrap_or_default(),
+                data: data.unwrap_or_default(),
+                is_write,
+            })
+        })
+        .collect()
+}
+
+fn format_accesses(accesses: &[BusAccess]) -> String {
+    let accesses: Vec<_> = accesses
+        .iter()
+        .map(|access| {
+            let kind = if access.is_write { 'w' } else { 'r' };
+            format!("{} {:0>4X}={:0>2X}", kind, access.addr, access.data)
+        })
+        .collect();
+    format!("[{}]", accesses.join(", "))
+}
+
+/// Returns a description of every difference between the state of `sys`
+/// and the expected state.
+fn compare_state(sys: &Sys, expected: &CpuState, cycles: u32, expected_cycles: u32) -> Vec<String> {
+    let mut diffs = vec![];
+    let mut check = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            diffs.push(format!(
+                "{}: expected {:0>4X}, got {:0>4X}",
+                name, expected, actual
+            ));
+        }
+    };
+
+    use CpuReg8::*;
+    check("A", sys.regs.get_8(A) as u16, expected.a as u16);
+    check("F", sys.regs.get_8(F) as u16, expected.f as u16);
+    check("B", sys.regs.get_8(B) as u16, expected.b as u16);
+    check("C", sys.regs.get_8(C) as u16, expected.c as u16);
+    check("D", sys.regs.get_8(D) as u16, expected.d as u16);
+    check("E", sys.regs.get_8(E) as u16, expected.e as u16);
+    check("H", sys.regs.get_8(H) as u16, expected.h as u16);
+    check("L", sys.regs.get_8(L) as u16, expected.l as u16);
+    check("SP", sys.regs.sp(), expected.sp);
+    check("PC", sys.regs.pc(), expected.pc);
+    check(
+        "IME",
+        sys.interrupt_master_enable as u16,
+        (expected.ime != 0) as u16,
+    );
+    if let Some(ie) = expected.ie {
+        check("IE", sys.read(0xFFFF) as u16, ie as u16);
+    }
+    check("M-cycles", cycles as u16, expected_cycles as u16);
+
+    for &(addr, data) in &expected.ram {
+        check(
+            &format!("[{:0>4X}]", addr),
+            sys.read(addr) as u16,
+            data as u16,
+        );
+    }
+
+    diffs
+}
+
+/// Runs a single test case. Returns a description of the mismatches on failure.
+fn run_case(case: &TestCase) -> Result<(), String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut sys = make_test_sys();
+        load_state(&mut sys, &case.initial);
+        let bus = sys.test_bus.as_mut().unwrap();
+        bus.take_accesses();
+
+        let cycles = execute_next_instr(&mut sys);
+        let accesses = sys.test_bus.as_mut().unwrap().take_accesses();
+
+        let mut diffs = compare_state(&sys, &case.final_, cycles, case.cycles.len() as u32);
+        let expected = expected_accesses(case);
+        if accesses != expected {
+            diffs.push(format!(
+                "bus: expected {}, got {}",
+                format_accesses(&expected),
+                format_accesses(&accesses)
+            ));
+        }
+        diffs
+    }));
+
+    match result {
+        Ok(diffs) if diffs.is_empty() => Ok(()),
+        Ok(diffs) => Err(format!("{}: {}", case.name, diffs.join(", "))),
+        Err(_) => Err(format!("{}: panicked", case.name)),
+    }
+}
+
+fn run_opcode_file(path: &Path) -> Result<OpcodeFailures, String> {
+    let opcode = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let cases: Vec<TestCase> =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", opcode, e))?;
+
+    let failures = cases
+        .iter()
+        .filter_map(|case| run_case(case).err())
+        .collect();
+
+    Ok(OpcodeFailures {
+        opcode,
+        total_cases: cases.len(),
+        failures,
+    })
+}
+
+/// Runs every opcode file in the test directory and prints a summary of
+/// the failures for each opcode. Returns the opcodes that failed, or an
+/// error if there are no opcode files.
+pub fn run_sm83_tests(dir: &Path) -> Result<Vec<String>, String> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Unable to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(format!(
+            "No SM83 test vectors found in {}, see README.md there",
+            dir.display()
+        ));
+    }
+
+    let mut failed_opcodes = vec![];
+    for path in paths {
+        let result = match run_opcode_file(&path) {
+            Ok(result) => result,
+            Err(msg) => {
+                println!("Failed to load {}: {}", path.display(), msg);
+                failed_opcodes.push(path.display().to_string());
+                continue;
+            }
+        };
+
+        if result.failures.is_empty() {
+            continue;
+        }
+
+        println!(
+            "${}: {}/{} cases failed",
+            result.opcode,
+            result.failures.len(),
+            result.total_cases
+        );
+        for failure in result.failures.iter().take(MAX_REPORTED_CASES) {
+            println!("  {}", failure);
+        }
+        failed_opcodes.push(result.opcode);
+    }
+
+    Ok(failed_opcodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sm83_single_step() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEST_DIR);
+        let failed_opcodes = run_sm83_tests(&dir).unwrap_or_else(|msg| panic!("{}", msg));
+
+        assert!(
+            failed_opcodes.is_empty(),
+            "Failing opcodes: {}",
+            failed_opcodes.join(", ")
+        );
+    }
+}
@@ -18,10 +18,6 @@ impl Clock {
         }
     }
 
-    pub fn set_period_dots(&mut self, period_dots: u32) {
-        self.period_dots = period_dots;
-    }
-
     pub fn update_and_check(&mut self) -> bool {
         self.count_dots += 1;
 
@@ -34,6 +30,13 @@ impl Clock {
         }
     }
 
+    /// Same as calling `update_and_check` `updates` times.
+    pub fn skip(&mut self, updates: u32) {
+        let count_dots = self.count_dots + updates;
+        self.debug_total_ticks += (count_dots / self.period_dots) as u64;
+        self.count_dots = count_dots % self.period_dots;
+    }
+
     pub fn print(&self) {
         println!("Simple clock {}", self.name);
         println!("  period: {} dots", self.period_dots);
@@ -6,57 +6,334 @@ use crate::{
 };
 
 pub const CPU_PERIOD_MCYCLES: u32 = 1;
-pub const DIV_PERIOD_MCYCLES: u32 = 64;
-pub const TAC_CLK_0_PERIOD_MCYCLES: u32 = 256;
-pub const TAC_CLK_1_PERIOD_MCYCLES: u32 = 4;
-pub const TAC_CLK_2_PERIOD_MCYCLES: u32 = 16;
-pub const TAC_CLK_3_PERIOD_MCYCLES: u32 = 64;
+
+/// The system counter value after the DMG boot ROM.
+pub const SYS_COUNTER_AFTER_BOOT: u16 = 0xABCC;
+
+/// The system counter bit whose falling edge clocks the APU frame sequencer
+/// (DIV bit 4).
+const FRAME_SEQUENCER_BIT: u16 = 1 << 12;
+
+/// Where the timer is in the TIMA overflow sequence.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimaReload {
+    None,
+    /// TIMA overflowed and reads 0x00 for this M-cycle. Writing TIMA now
+    /// cancels the reload and the interrupt.
+    Pending,
+    /// TIMA was just reloaded from TMA. Writing TIMA now is ignored, and
+    /// writing TMA loads the new value into TIMA too.
+    Reloaded,
+}
+
+/// The internal timer state.
+///
+/// DIV and TIMA are both driven by a single 16-bit counter that counts
+/// T-cycles. DIV is its upper byte, and TIMA is incremented on the falling
+/// edge of (TAC enable AND the counter bit selected by TAC). Writing DIV
+/// or TAC can cause such an edge too, so both also increment TIMA at
+/// times.
+pub struct Timer {
+    pub sys_counter: u16,
+    /// The last value of the signal that clocks TIMA.
+    tima_signal: bool,
+    pub tima_reload: TimaReload,
+    /// Step (0-7) of the APU frame sequencer.
+    pub frame_sequencer_step: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            sys_counter: SYS_COUNTER_AFTER_BOOT,
+            tima_signal: false,
+            tima_reload: TimaReload::None,
+            frame_sequencer_step: 0,
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Timer");
+        println!("  sys counter: {:#06x}", self.sys_counter);
+        println!("  TIMA reload: {:?}", self.tima_reload);
+        println!("  frame sequencer step: {}", self.frame_sequencer_step);
+    }
+}
+
+/// Returns the system counter bit selected by the TAC clock select bits.
+fn tac_counter_bit(tac: u8) -> u16 {
+    match bits8(&tac, 1, 0) {
+        0 => 1 << 9, // 4096 Hz
+        1 => 1 << 3, // 262144 Hz
+        2 => 1 << 5, // 65536 Hz
+        3 => 1 << 7, // 16384 Hz
+        _ => unreachable!(),
+    }
+}
 
 pub fn update_timer_regs(sys: &mut Sys) {
-    // DIV: incs every 16384 Hz; Writing any sets to 0x00; reset on STOP; doesnt tick until stop mode ends)
-    // TIMA: incs at freq specified in TAC; when overflows, it is reset to value in TMA and an interrupt is reqd
+    // DIV: upper byte of the system counter; writing any value resets the whole counter.
+    // TIMA: incs on the falling edge of the TAC selected counter bit; when it overflows, it reads 0x00 for 1 M-cycle, then it is reset to TMA and an interrupt is reqd.
     // TMA: determines TIMA reset value after overflow
     // TAC: .2: enable; .1-0: clock select;
 
-    let div_ticked = sys.div_timer_clock.update_and_check();
+    update_tima_reload(sys);
 
-    if div_ticked {
-        sys.mem.io_regs.mut_(IoReg::Div, |div| {
-            let div_ = u8::wrapping_add(*div, 1);
-            if div_ == 0 {
-                // DIV overflow
-            }
-            *div = div_;
-        });
+    if sys.mem.io_regs.div_reset_requested {
+        sys.mem.io_regs.div_reset_requested = false;
+        set_sys_counter(sys, 0);
+    }
+
+    // Advance by 1 M-Cycle (4 T-cycles).
+    let counter = sys.timer.sys_counter.wrapping_add(4);
+    set_sys_counter(sys, counter);
+}
+
+/// Returns how many of the coming M-cycles the timer would spend only
+/// counting, up to (not including) the one where TIMA overflows. DIV and
+/// TIMA still change meanwhile, so a loop that reads them can't be skipped.
+pub fn timer_idle_m_cycles(sys: &Sys) -> u32 {
+    let io_regs = &sys.mem.io_regs;
+    if sys.timer.tima_reload != TimaReload::None
+        || io_regs.div_reset_requested
+        || io_regs.tima_written
+    {
+        return 0;
+    }
+
+    let tac = io_regs.get(IoReg::Tac);
+    let counter = sys.timer.sys_counter as u32;
+    if bit8(&tac, 2) == 0 {
+        return if sys.timer.tima_signal { 0 } else { u32::MAX };
+    }
+
+    // A TAC write since the last update can cause an edge right away.
+    let bit = tac_counter_bit(tac) as u32;
+    if sys.timer.tima_signal != ((counter & bit) != 0) {
+        return 0;
     }
 
-    // Update TIMA
+    // TIMA is clocked each time the counter goes past a multiple of twice
+    // the selected bit.
+    let period = 2 * bit;
+    let increments_left = 0x100 - io_regs.get(IoReg::Tima) as u32;
+    let overflow_counter = (counter / period + increments_left) * period;
+    let overflow_m_cycle = (overflow_counter - counter).div_ceil(4);
+
+    overflow_m_cycle - 1
+}
+
+/// Advances the timer by `m_cycles`, which must not be more than
+/// `timer_idle_m_cycles`.
+pub fn skip_timer_m_cycles(sys: &mut Sys, m_cycles: u32) {
+    let counter = sys.timer.sys_counter as u32;
+    let counter_ = counter + 4 * m_cycles;
+    let falling_edges = |bit: u32| counter_ / (2 * bit) - counter / (2 * bit);
+
+    let steps = (falling_edges(FRAME_SEQUENCER_BIT as u32) % 8) as u8;
+    sys.timer.frame_sequencer_step = (sys.timer.frame_sequencer_step + steps) % 8;
+
     let tac = sys.mem.io_regs.get(IoReg::Tac);
-    let enable = bit8(&tac, 2) == 1; // unused
-    let clock_sel = bits8(&tac, 1, 0);
-    let tima_clk_period = match clock_sel {
-        0 => TAC_CLK_0_PERIOD_MCYCLES,
-        1 => TAC_CLK_1_PERIOD_MCYCLES,
-        2 => TAC_CLK_2_PERIOD_MCYCLES,
-        3 => TAC_CLK_3_PERIOD_MCYCLES,
-        _ => unreachable!(),
-    };
+    if bit8(&tac, 2) == 1 {
+        let increments = falling_edges(tac_counter_bit(tac) as u32) as u8;
+        sys.mem
+            .io_regs
+            .mut_(IoReg::Tima, |tima| *tima = tima.wrapping_add(increments));
+    }
+
+    // No edges are left to see.
+    let counter_ = counter_ as u16;
+    let enable = bit8(&tac, 2) == 1;
+    sys.timer.tima_signal = enable && (counter_ & tac_counter_bit(tac)) != 0;
+    sys.timer.sys_counter = counter_;
+    sys.mem.io_regs.set(IoReg::Div, (counter_ >> 8) as u8);
+}
+
+/// Sets the system counter, and clocks whatever sees a falling edge.
+fn set_sys_counter(sys: &mut Sys, counter: u16) {
+    let prev_counter = sys.timer.sys_counter;
+    sys.timer.sys_counter = counter;
+    sys.mem.io_regs.set(IoReg::Div, (counter >> 8) as u8);
 
-    sys.tima_timer_clock.set_period_dots(tima_clk_period);
+    if (prev_counter & FRAME_SEQUENCER_BIT) != 0 && (counter & FRAME_SEQUENCER_BIT) == 0 {
+        sys.timer.frame_sequencer_step = (sys.timer.frame_sequencer_step + 1) % 8;
+    }
+
+    // Also catches edges caused by TAC writes since the last update.
+    let tac = sys.mem.io_regs.get(IoReg::Tac);
+    let enable = bit8(&tac, 2) == 1;
+    let tima_signal = enable && (counter & tac_counter_bit(tac)) != 0;
+    if sys.timer.tima_signal && !tima_signal {
+        increment_tima(sys);
+    }
+    sys.timer.tima_signal = tima_signal;
+}
 
-    if enable {
-        let tima_ticked = sys.tima_timer_clock.update_and_check();
+/// Moves the TIMA overflow sequence along by 1 M-cycle, taking into account
+/// the TIMA and TMA writes made by the CPU during this M-cycle.
+fn update_tima_reload(sys: &mut Sys) {
+    let tima_written = sys.mem.io_regs.tima_written;
+    sys.mem.io_regs.tima_written = false;
 
-        if tima_ticked {
-            let tima = sys.mem.io_regs.get(IoReg::Tima);
-            let tima_ = u8::wrapping_add(tima, 1);
-            if tima_ == 0 {
-                // TIMA overflow
+    match sys.timer.tima_reload {
+        TimaReload::None => {}
+        TimaReload::Pending => {
+            if tima_written {
+                sys.timer.tima_reload = TimaReload::None;
+            } else {
                 let tma = sys.mem.io_regs.get(IoReg::Tma);
                 sys.mem.io_regs.set(IoReg::Tima, tma);
                 request_interrupt(sys, InterruptType::Timer);
+                sys.timer.tima_reload = TimaReload::Reloaded;
             }
-            sys.mem.io_regs.set(IoReg::Tima, tima_);
         }
+        TimaReload::Reloaded => {
+            // TIMA keeps following TMA for this M-cycle.
+            let tma = sys.mem.io_regs.get(IoReg::Tma);
+            sys.mem.io_regs.set(IoReg::Tima, tma);
+            sys.timer.tima_reload = TimaReload::None;
+        }
+    }
+}
+
+fn increment_tima(sys: &mut Sys) {
+    let tima = sys.mem.io_regs.get(IoReg::Tima);
+    let tima_ = u8::wrapping_add(tima, 1);
+    if tima_ == 0 {
+        // TIMA overflow
+        sys.timer.tima_reload = TimaReload::Pending;
+    }
+    sys.mem.io_regs.set(IoReg::Tima, tima_);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::program::idle_sys;
+
+    use super::*;
+
+    fn timer_sys(counter: u16, tac: u8) -> Sys {
+        let mut sys = idle_sys();
+        sys.timer.sys_counter = counter;
+        sys.mem.io_regs.set(IoReg::Tima, 0x00);
+        sys.mem.io_regs.set(IoReg::Tac, tac);
+        update_timer_regs(&mut sys);
+        sys
+    }
+
+    #[test]
+    fn test_div_is_upper_counter_byte() {
+        let mut sys = timer_sys(0x12FC, 0x00);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Div), 0x13);
+
+        for _ in 0..64 {
+            update_timer_regs(&mut sys);
+        }
+        assert_eq!(sys.mem.io_regs.get(IoReg::Div), 0x14);
+    }
+
+    #[test]
+    fn test_tima_counts_falling_edges() {
+        // 262144 Hz: bit 3 falls every 16 T-cycles (4 M-cycles).
+        let mut sys = timer_sys(0x0000, 0b101);
+        for _ in 0..40 {
+            update_timer_regs(&mut sys);
+        }
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 10);
+    }
+
+    #[test]
+    fn test_div_write_resets_counter_and_clocks_tima() {
+        // Bit 9 is set, so resetting the counter is a falling edge.
+        let mut sys = timer_sys(0x0200, 0b100);
+        sys.write(IoReg::Div.as_addr(), 0x55);
+        update_timer_regs(&mut sys);
+
+        assert_eq!(sys.timer.sys_counter, 4);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Div), 0x00);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 1);
+    }
+
+    #[test]
+    fn test_tac_write_glitch() {
+        // Disabling the timer while the selected bit is set clocks TIMA.
+        let mut sys = timer_sys(0x0200, 0b100);
+        sys.write(IoReg::Tac.as_addr(), 0b000);
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 1);
+
+        // So does switching to a clock whose bit is clear.
+        let mut sys = timer_sys(0x0200, 0b100);
+        sys.write(IoReg::Tac.as_addr(), 0b110);
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 1);
+    }
+
+    /// Returns a timer that overflows TIMA on the next update.
+    fn overflowing_sys() -> Sys {
+        // 262144 Hz: the counter is at 0x0C after the first update, so bit 3
+        // falls on the second one.
+        let mut sys = timer_sys(0x0008, 0b101);
+        sys.mem.io_regs.set(IoReg::Tima, 0xFF);
+        sys.mem.io_regs.set(IoReg::Tma, 0x42);
+        sys.mem.io_regs.set(IoReg::If, 0x00);
+        sys
+    }
+
+    fn timer_irq_requested(sys: &Sys) -> bool {
+        bit8(&sys.mem.io_regs.get(IoReg::If), 2) == 1
+    }
+
+    #[test]
+    fn test_tima_overflow_reload_is_delayed() {
+        let mut sys = overflowing_sys();
+
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 0x00);
+        assert!(!timer_irq_requested(&sys));
+
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 0x42);
+        assert!(timer_irq_requested(&sys));
+    }
+
+    #[test]
+    fn test_tima_write_cancels_pending_reload() {
+        let mut sys = overflowing_sys();
+        update_timer_regs(&mut sys);
+
+        sys.write(IoReg::Tima.as_addr(), 0x10);
+        update_timer_regs(&mut sys);
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 0x10);
+        assert!(!timer_irq_requested(&sys));
+    }
+
+    #[test]
+    fn test_writes_after_reload() {
+        // Writing TIMA right after the reload is ignored.
+        let mut sys = overflowing_sys();
+        update_timer_regs(&mut sys);
+        update_timer_regs(&mut sys);
+        sys.write(IoReg::Tima.as_addr(), 0x10);
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 0x42);
+
+        // Writing TMA right after the reload also loads TIMA.
+        let mut sys = overflowing_sys();
+        update_timer_regs(&mut sys);
+        update_timer_regs(&mut sys);
+        sys.write(IoReg::Tma.as_addr(), 0x99);
+        update_timer_regs(&mut sys);
+        assert_eq!(sys.mem.io_regs.get(IoReg::Tima), 0x99);
+    }
+
+    #[test]
+    fn test_frame_sequencer_follows_div_bit_4() {
+        let mut sys = timer_sys(0x1000, 0x00);
+        for _ in 0..(0x2000 / 4) {
+            update_timer_regs(&mut sys);
+        }
+        assert_eq!(sys.timer.frame_sequencer_step, 1);
     }
 }